@@ -1,13 +1,19 @@
 use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use async_scoped::TokioScope;
 use async_trait::async_trait;
+use chrono::Utc;
 use log::{debug, error, info, warn};
 use serde::Deserialize;
+use tokio::time::sleep;
 
+use crate::control::Mute;
+use crate::message::Message;
 use crate::message::MessageConfig;
-use crate::message::MessageParams;
+use crate::outbox::{Outbox, OutboxEntry};
 
 mod command;
 #[cfg(feature = "desktop")]
@@ -18,11 +24,18 @@ mod email;
 mod nostr;
 #[cfg(feature = "ntfy")]
 mod ntfy;
+mod rate_limit;
+mod retry;
+mod schedule;
 #[cfg(feature = "telegram")]
 mod telegram;
 mod terminal_print;
 
-#[derive(Deserialize, Debug)]
+use self::rate_limit::RateLimitConfig;
+use self::retry::{RetryConfig, RetryingAction};
+use self::schedule::{ScheduleConfig, ScheduledAction};
+
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 pub enum AnyActionConfig {
@@ -59,94 +72,412 @@ impl fmt::Display for AnyActionConfig {
     }
 }
 
+/// An action and the settings common to every action type
+#[derive(Deserialize, Debug, Clone)]
+pub struct ActionConfig {
+    id: Option<String>,
+    #[serde(flatten)]
+    kind: AnyActionConfig,
+    rate_limit: Option<RateLimitConfig>,
+    schedule: Option<ScheduleConfig>,
+    #[serde(default)]
+    retry: RetryConfig,
+    timeout_secs: Option<u64>,
+    /// Id older versions gave the action, whose outboxes are taken over
+    #[serde(skip)]
+    legacy_id: String,
+}
+
+impl ActionConfig {
+    /// Unique identifier of the action, used to keep track of its state across restarts
+    pub fn id(&self) -> &str {
+        self.id.as_deref().unwrap_or_default()
+    }
+
+    /// Opens the outbox the action keeps messages of the given purpose in
+    pub fn open_outbox(&self, purpose: &str) -> Outbox {
+        Outbox::open(
+            &format!("{}-{}", self.id(), purpose),
+            &format!("{}-{}", self.legacy_id, purpose),
+        )
+    }
+
+    /// Whether the action has the given id or type
+    pub fn matches(&self, id_or_type: &str) -> bool {
+        self.id() == id_or_type || self.kind.to_string() == id_or_type
+    }
+
+    pub fn kind(&self) -> &AnyActionConfig {
+        &self.kind
+    }
+
+    pub fn rate_limit(&self) -> Option<&RateLimitConfig> {
+        self.rate_limit.as_ref()
+    }
+
+    pub fn schedule(&self) -> Option<&ScheduleConfig> {
+        self.schedule.as_ref()
+    }
+
+    pub fn retry(&self) -> &RetryConfig {
+        &self.retry
+    }
+
+    /// Maximum time a single attempt to run the action can take
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs.unwrap_or(60))
+    }
+}
+
+impl fmt::Display for ActionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
 #[async_trait]
-pub trait Action<'a> {
+pub trait Action {
     fn name(&self) -> &'static str;
 
-    async fn run(&self, params: Option<&MessageParams<'_, '_>>) -> Result<()>;
+    async fn run(&self, message: &Message) -> Result<()>;
+
+    /// Called periodically to deliver messages that were held back
+    async fn process_pending(&self) {}
+
+    /// Messages held back to be delivered later
+    fn pending(&self) -> Vec<OutboxEntry> {
+        Vec::new()
+    }
+
+    /// Acknowledgement ids of escalated messages received through the action's channel since the
+    /// last call
+    async fn poll_acks(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Makes sure the action can reach its service (e.g. that the server is up and accepts the
+    /// credentials), without sending anything
+    async fn check(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
-pub async fn get_action<'a>(
-    message_config: &'a MessageConfig,
-    action_config: &'a AnyActionConfig,
-) -> Result<Box<dyn Action<'a> + 'a + Sync>> {
+async fn get_action_impl(
+    message_config: &Arc<MessageConfig>,
+    action_config: &AnyActionConfig,
+) -> Result<Box<dyn Action + Send + Sync>> {
     Ok(match action_config {
-        AnyActionConfig::TerminalPrint => Box::new(self::terminal_print::TerminalPrintAction::new(
-            message_config,
-        )),
-        AnyActionConfig::Command(config) => {
-            Box::new(self::command::CommandAction::new(message_config, config)?)
-        }
+        AnyActionConfig::TerminalPrint => Box::new(self::terminal_print::TerminalPrintAction),
+        AnyActionConfig::Command(config) => Box::new(self::command::CommandAction::new(
+            message_config.clone(),
+            config.clone(),
+        )?),
         #[cfg(feature = "desktop")]
-        AnyActionConfig::DesktopNotification => Box::new(
-            self::desktop_notification::DesktopNotificationAction::new(message_config),
-        ),
-        #[cfg(feature = "ntfy")]
-        AnyActionConfig::Ntfy(config) => {
-            Box::new(self::ntfy::NtfyAction::new(message_config, config)?)
+        AnyActionConfig::DesktopNotification => {
+            Box::new(self::desktop_notification::DesktopNotificationAction)
         }
+        #[cfg(feature = "ntfy")]
+        AnyActionConfig::Ntfy(config) => Box::new(self::ntfy::NtfyAction::new(config)?),
         #[cfg(feature = "email")]
-        AnyActionConfig::Email(config) => {
-            Box::new(self::email::EmailAction::new(message_config, config)?)
-        }
+        AnyActionConfig::Email(config) => Box::new(self::email::EmailAction::new(config)?),
         #[cfg(feature = "telegram")]
-        AnyActionConfig::Telegram(config) => {
-            Box::new(self::telegram::TelegramAction::new(message_config, config)?)
-        }
+        AnyActionConfig::Telegram(config) => Box::new(self::telegram::TelegramAction::new(config)?),
         #[cfg(feature = "nostr")]
-        AnyActionConfig::Nostr(config) => {
-            Box::new(self::nostr::NostrAction::new(message_config, config).await?)
-        }
+        AnyActionConfig::Nostr(config) => Box::new(self::nostr::NostrAction::new(config).await?),
     })
 }
 
-pub async fn get_actions<'a>(
-    message_config: &'a MessageConfig,
-    actions_config: &'a [AnyActionConfig],
-) -> Vec<Box<dyn Action<'a> + 'a + Sync>> {
-    let mut result: Vec<Box<dyn Action + Sync>> = Default::default();
-
-    // TODO: parallelize this. It's hard because the result vector needs to be shared.
-    for action_config in actions_config {
-        debug!("[{}] registering action", action_config);
-        match get_action(message_config, action_config).await {
-            Ok(action) => {
-                info!("[{}] registered action", action_config);
-                result.push(action);
+/// Actions without an explicit id are identified by their type, which doesn't change when other
+/// actions are added or moved around. Their messages are kept across restarts under that id, so
+/// several actions of the same type need explicit ids to be told apart.
+pub fn set_default_ids(actions: &mut [ActionConfig]) -> Result<()> {
+    let kinds = actions
+        .iter()
+        .filter(|a| a.id.is_none())
+        .map(|a| a.kind.to_string())
+        .collect::<Vec<_>>();
+    for (index, action) in actions.iter_mut().enumerate() {
+        let kind = action.kind.to_string();
+        action.legacy_id = match &action.id {
+            Some(id) => id.clone(),
+            None => format!("{}-{}", kind, index),
+        };
+        if action.id.is_none() {
+            if kinds.iter().filter(|k| **k == kind).count() > 1 {
+                bail!(
+                    "there are several '{}' actions, each of them needs an `id`",
+                    kind
+                );
             }
-            Err(e) => {
-                warn!("[{}] could not register action: {:#}", action_config, e);
+            action.id = Some(kind);
+        }
+    }
+    Ok(())
+}
+
+/// Sets up an action with its common settings. Test actions run right away and only once, so
+/// they skip retries and schedules.
+pub async fn get_action(
+    message_config: &Arc<MessageConfig>,
+    action_config: &ActionConfig,
+    test: bool,
+) -> Result<Box<dyn Action + Send + Sync>> {
+    let mut action = get_action_impl(message_config, action_config.kind()).await?;
+    if test {
+        return Ok(action);
+    }
+    action = Box::new(RetryingAction::new(
+        action,
+        action_config.id(),
+        action_config.retry().clone(),
+        action_config.timeout(),
+        action_config.rate_limit(),
+        action_config.open_outbox("retry"),
+    ));
+    if let Some(schedule) = action_config.schedule() {
+        action = Box::new(ScheduledAction::new(
+            action,
+            action_config.id(),
+            schedule,
+            message_config.timezone(),
+            action_config.open_outbox("deferred"),
+        )?);
+    }
+    Ok(action)
+}
+
+/// A configured action. Actions that could not be set up (e.g. because the network is not up yet)
+/// stay degraded, holding back their messages until they are registered in the background. The
+/// messages held back are persisted, so that they are still delivered after a restart.
+pub struct ConfiguredAction {
+    config: ActionConfig,
+    message_config: Arc<MessageConfig>,
+    test: bool,
+    action: OnceLock<Box<dyn Action + Send + Sync>>,
+    /// Test actions don't hold messages back, so they have no backlog
+    backlog: Mutex<Option<Outbox>>,
+    mute: Mute,
+}
+
+impl ConfiguredAction {
+    fn new(message_config: Arc<MessageConfig>, config: ActionConfig, test: bool) -> Self {
+        let backlog = (!test).then(|| config.open_outbox("backlog"));
+        Self {
+            config,
+            message_config,
+            test,
+            action: OnceLock::new(),
+            backlog: Mutex::new(backlog),
+            mute: Default::default(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        self.config.id()
+    }
+
+    pub fn kind(&self) -> String {
+        self.config.to_string()
+    }
+
+    /// Whether the action has the given id or type
+    pub fn matches(&self, id_or_type: &str) -> bool {
+        self.config.matches(id_or_type)
+    }
+
+    pub fn mute(&self) -> &Mute {
+        &self.mute
+    }
+
+    pub fn is_registered(&self) -> bool {
+        self.action.get().is_some()
+    }
+
+    async fn register(&self) {
+        debug!("[{}] registering action", self.id());
+        let timeout = self.config.timeout();
+        let action = match tokio::time::timeout(
+            timeout,
+            get_action(&self.message_config, &self.config, self.test),
+        )
+        .await
+        {
+            Ok(Ok(action)) => action,
+            Ok(Err(e)) => {
+                warn!("[{}] could not register action: {:#}", self.id(), e);
+                return;
+            }
+            Err(_) => {
+                warn!(
+                    "[{}] could not register action: timed out after {}s",
+                    self.id(),
+                    timeout.as_secs()
+                );
+                return;
+            }
+        };
+        info!("[{}] registered action", self.id());
+        let backlog = {
+            let backlog = self.backlog.lock().unwrap();
+            let _ = self.action.set(action);
+            backlog.as_ref().map(Outbox::entries).unwrap_or_default()
+        };
+        for entry in backlog {
+            let message = entry.message();
+            if let Err(e) = self.run(message).await {
+                error!(
+                    "[{}][{}][{}] could not run action: {:#}",
+                    message.source(),
+                    message.id(),
+                    self.id(),
+                    e
+                );
+            }
+            if let Some(backlog) = &*self.backlog.lock().unwrap() {
+                backlog.remove(entry.id());
+            }
+        }
+    }
+
+    /// Retries registering a degraded action with exponential backoff until it succeeds
+    pub async fn keep_registering(&self) {
+        let mut attempts = 0;
+        while !self.is_registered() {
+            attempts += 1;
+            let delay = self.config.retry().delay(attempts);
+            info!(
+                "[{}] action degraded, retrying registration in {}s",
+                self.id(),
+                delay.as_secs()
+            );
+            sleep(delay).await;
+            self.register().await;
+        }
+    }
+
+    pub async fn run(&self, message: &Message) -> Result<()> {
+        let action = {
+            let backlog = self.backlog.lock().unwrap();
+            match (self.action.get(), &*backlog) {
+                (Some(action), _) => action,
+                (None, None) => bail!("action is not registered"),
+                (None, Some(backlog)) => {
+                    info!(
+                        "[{}][{}][{}] action not registered yet, holding back message",
+                        message.source(),
+                        message.id(),
+                        self.id()
+                    );
+                    backlog.push(message.clone(), Utc::now(), 0);
+                    return Ok(());
+                }
             }
+        };
+        action.run(message).await
+    }
+
+    pub async fn process_pending(&self) {
+        if let Some(action) = self.action.get() {
+            action.process_pending().await;
+        }
+    }
+
+    /// Messages held back until the action is registered
+    pub fn backlog(&self) -> Vec<Message> {
+        match &*self.backlog.lock().unwrap() {
+            Some(backlog) => backlog
+                .entries()
+                .iter()
+                .map(|entry| entry.message().clone())
+                .collect(),
+            None => Vec::new(),
         }
     }
 
-    result
+    /// Messages the action holds back to deliver later (e.g. to retry them)
+    pub fn pending(&self) -> Vec<OutboxEntry> {
+        match self.action.get() {
+            Some(action) => action.pending(),
+            None => Vec::new(),
+        }
+    }
+
+    pub async fn poll_acks(&self) -> Vec<String> {
+        match self.action.get() {
+            Some(action) => action.poll_acks().await,
+            None => Vec::new(),
+        }
+    }
 }
 
-pub async fn run_actions(
-    actions: &[&(dyn Action<'_> + Sync)],
-    params: Option<MessageParams<'_, '_>>,
-) {
-    let params_ref = params.as_ref();
+/// Reuses the actions whose configuration didn't change, along with their state, and sets up the
+/// rest in parallel
+pub async fn update_actions<'a>(
+    mut old_actions: Vec<ConfiguredAction>,
+    message_config: &MessageConfig,
+    actions_config: impl IntoIterator<Item = &'a ActionConfig>,
+    test: bool,
+    unchanged: impl Fn(&str) -> bool,
+) -> Vec<ConfiguredAction> {
+    let message_config = Arc::new(message_config.clone());
+    let actions = actions_config
+        .into_iter()
+        .map(|config| {
+            match old_actions
+                .iter()
+                .position(|a| a.id() == config.id() && unchanged(config.id()))
+            {
+                Some(index) => old_actions.swap_remove(index),
+                None => ConfiguredAction::new(message_config.clone(), config.clone(), test),
+            }
+        })
+        .collect::<Vec<_>>();
+    for action in old_actions {
+        info!("[{}] removing action", action.id());
+    }
+    TokioScope::scope_and_block(|s| {
+        for action in actions.iter().filter(|a| !a.is_registered()) {
+            s.spawn(action.register());
+        }
+    });
+    actions
+}
+
+/// Sets up every configured action in parallel
+pub async fn get_actions<'a>(
+    message_config: &MessageConfig,
+    actions_config: impl IntoIterator<Item = &'a ActionConfig>,
+    test: bool,
+) -> Vec<ConfiguredAction> {
+    update_actions(Vec::new(), message_config, actions_config, test, |_| false).await
+}
+
+pub async fn run_actions(actions: &[&ConfiguredAction], message: Message) {
+    let message_ref = &message;
     TokioScope::scope_and_block(|s| {
         for &action in actions {
+            if action.mute().is_muted() {
+                info!(
+                    "[{}][{}][{}] action muted, skipping",
+                    message_ref.source(),
+                    message_ref.id(),
+                    action.id()
+                );
+                continue;
+            }
             s.spawn(async {
                 info!(
                     "[{}][{}][{}] running",
-                    params_ref.map(|p| p.wallet()).unwrap_or("wallet"),
-                    params_ref
-                        .map(|p| p.txid_short())
-                        .unwrap_or("txid".to_string()),
-                    action.name()
+                    message_ref.source(),
+                    message_ref.id(),
+                    action.id()
                 );
-                if let Err(e) = action.run(params_ref).await {
+                if let Err(e) = action.run(message_ref).await {
                     error!(
                         "[{}][{}][{}] could not run action: {}",
-                        params_ref.map(|p| p.wallet()).unwrap_or("wallet"),
-                        params_ref
-                            .map(|p| p.txid_short())
-                            .unwrap_or("txid".to_string()),
-                        action.name(),
+                        message_ref.source(),
+                        message_ref.id(),
+                        action.id(),
                         e
                     );
                 }
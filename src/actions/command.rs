@@ -1,14 +1,17 @@
 use std::collections::HashMap;
+use std::env;
+use std::path::Path;
 use std::process::Command;
+use std::sync::Arc;
 
 use super::Action;
+use crate::message::Message;
 use crate::message::MessageConfig;
-use crate::message::MessageParams;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use async_trait::async_trait;
 use serde::Deserialize;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct CommandConfig {
     cmd: String,
     #[serde(default)]
@@ -20,13 +23,20 @@ pub struct CommandConfig {
     working_dir: Option<String>,
 }
 
-pub struct CommandAction<'a> {
-    message_config: &'a MessageConfig,
-    cmd_config: &'a CommandConfig,
+impl CommandConfig {
+    /// Arguments, rendered as templates against each message
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+}
+
+pub struct CommandAction {
+    message_config: Arc<MessageConfig>,
+    cmd_config: CommandConfig,
 }
 
-impl<'a> CommandAction<'a> {
-    pub fn new(message_config: &'a MessageConfig, cmd_config: &'a CommandConfig) -> Result<Self> {
+impl CommandAction {
+    pub fn new(message_config: Arc<MessageConfig>, cmd_config: CommandConfig) -> Result<Self> {
         Ok(Self {
             message_config,
             cmd_config,
@@ -35,16 +45,16 @@ impl<'a> CommandAction<'a> {
 }
 
 #[async_trait]
-impl Action<'_> for CommandAction<'_> {
+impl Action for CommandAction {
     fn name(&self) -> &'static str {
         "command"
     }
 
-    async fn run(&self, params: Option<&MessageParams<'_, '_>>) -> Result<()> {
+    async fn run(&self, message: &Message) -> Result<()> {
         let mut cmd = Command::new(&self.cmd_config.cmd);
         for arg in self.cmd_config.args.iter() {
-            cmd.arg(if let Some(p) = params {
-                self.message_config.replace_template_params(arg, p)?
+            cmd.arg(if let Some(context) = message.context() {
+                self.message_config.render_lenient(arg, context)?
             } else {
                 arg.clone()
             });
@@ -62,4 +72,25 @@ impl Action<'_> for CommandAction<'_> {
         cmd.status()?;
         Ok(())
     }
+
+    /// Makes sure the command and its working directory exist
+    async fn check(&self) -> Result<()> {
+        let program = Path::new(&self.cmd_config.cmd);
+        let found = if program.components().count() > 1 {
+            program.is_file()
+        } else {
+            env::var_os("PATH").is_some_and(|paths| {
+                env::split_paths(&paths).any(|dir| dir.join(program).is_file())
+            })
+        };
+        if !found {
+            bail!("command '{}' not found", self.cmd_config.cmd);
+        }
+        if let Some(working_dir) = &self.cmd_config.working_dir {
+            if !Path::new(working_dir).is_dir() {
+                bail!("working directory '{}' does not exist", working_dir);
+            }
+        }
+        Ok(())
+    }
 }
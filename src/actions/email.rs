@@ -1,10 +1,13 @@
+use std::path::PathBuf;
+
 use super::Action;
-use crate::message::MessageConfig;
+use crate::message::Message;
 use crate::message::MessageFormat;
-use crate::message::MessageParams;
-use anyhow::{Context, Result};
+use crate::secret::get_secret;
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
 use lettre::message::header::ContentType;
+use lettre::message::Attachment;
 use lettre::message::MessageBuilder;
 use lettre::message::MultiPart;
 use lettre::message::SinglePart;
@@ -13,7 +16,6 @@ use lettre::transport::smtp::client::Tls;
 use lettre::transport::smtp::client::TlsParametersBuilder;
 use lettre::AsyncSmtpTransport;
 use lettre::AsyncTransport;
-use lettre::Message;
 use lettre::Tokio1Executor;
 use serde::Deserialize;
 
@@ -25,11 +27,32 @@ pub enum EmailConnectionType {
     Tls,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
+pub struct EmailCredentials {
+    authentication_identity: String,
+    secret: Option<String>,
+    secret_file: Option<PathBuf>,
+}
+
+impl EmailCredentials {
+    pub fn credentials(&self) -> Result<Credentials> {
+        let secret = get_secret(
+            "secret",
+            self.secret.as_deref(),
+            self.secret_file.as_deref(),
+        )?;
+        Ok(Credentials::new(
+            self.authentication_identity.clone(),
+            secret,
+        ))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct EmailConfig {
     server: String,
     port: Option<u16>,
-    credentials: Option<Credentials>,
+    credentials: Option<EmailCredentials>,
     connection: Option<EmailConnectionType>,
     self_signed_cert: Option<bool>,
     from: String,
@@ -62,13 +85,12 @@ impl EmailConfig {
     }
 }
 
-pub struct EmailAction<'a> {
-    message_config: &'a MessageConfig,
+pub struct EmailAction {
     mailer: AsyncSmtpTransport<Tokio1Executor>,
     message_builder: MessageBuilder,
 }
-impl<'a> EmailAction<'a> {
-    pub fn new(message_config: &'a MessageConfig, email_config: &'a EmailConfig) -> Result<Self> {
+impl EmailAction {
+    pub fn new(email_config: &EmailConfig) -> Result<Self> {
         let tls_builder = TlsParametersBuilder::new(email_config.server().into())
             .dangerous_accept_invalid_certs(email_config.self_signed_cert());
         let tls_parameters = tls_builder.build()?;
@@ -82,12 +104,11 @@ impl<'a> EmailAction<'a> {
                     EmailConnectionType::Plain => Tls::None,
                 });
         if let Some(cred) = &email_config.credentials {
-            smtp_builder = smtp_builder.credentials(cred.clone())
+            smtp_builder = smtp_builder.credentials(cred.credentials()?)
         }
         Ok(Self {
-            message_config,
             mailer: smtp_builder.build(),
-            message_builder: Message::builder()
+            message_builder: lettre::Message::builder()
                 .from(
                     email_config
                         .from
@@ -103,44 +124,83 @@ impl<'a> EmailAction<'a> {
 }
 
 #[async_trait]
-impl Action<'_> for EmailAction<'_> {
+impl Action for EmailAction {
     fn name(&self) -> &'static str {
         "email"
     }
 
-    async fn run(&self, params: Option<&MessageParams<'_, '_>>) -> Result<()> {
-        let body = self.message_config.body(params)?;
-        let html_body = match self.message_config.format() {
-            MessageFormat::Markdown => format!(
-                "<!DOCTYPE html><html><body>{}</body></html>",
-                markdown::to_html(&body)
-            ),
+    async fn run(&self, message: &Message) -> Result<()> {
+        let body = message.body().to_string();
+        let mut html_body = match message.format() {
+            MessageFormat::Markdown => markdown::to_html(&body),
             MessageFormat::Html => body.clone(),
             MessageFormat::Plain => Default::default(),
         };
-        let email_builder = self
-            .message_builder
-            .clone()
-            .subject(self.message_config.subject(params)?);
-        let email = match self.message_config.format() {
-            MessageFormat::Plain => email_builder
+        // Images are shown inline in HTML emails, referenced by their content ID
+        for (i, attachment) in message.attachments().iter().enumerate() {
+            if attachment.is_image() {
+                html_body.push_str(&format!("<p><img src=\"cid:attachment{}\"></p>", i));
+            }
+        }
+        let html_body = format!("<!DOCTYPE html><html><body>{}</body></html>", html_body);
+
+        let mut attachments = Vec::new();
+        for (i, attachment) in message.attachments().iter().enumerate() {
+            let content_type = ContentType::parse(attachment.content_type())?;
+            attachments.push(match message.format() {
+                MessageFormat::Markdown | MessageFormat::Html if attachment.is_image() => {
+                    Attachment::new_inline(format!("attachment{}", i))
+                        .body(attachment.data().to_vec(), content_type)
+                }
+                _ => Attachment::new(attachment.filename().to_string())
+                    .body(attachment.data().to_vec(), content_type),
+            });
+        }
+
+        let email_builder = self.message_builder.clone().subject(message.subject());
+        let email = match message.format() {
+            MessageFormat::Plain if attachments.is_empty() => email_builder
                 .header(ContentType::TEXT_PLAIN)
                 .body(body.clone())?,
-            MessageFormat::Markdown | MessageFormat::Html => email_builder.multipart(
-                MultiPart::alternative()
-                    .singlepart(
-                        SinglePart::builder()
-                            .header(ContentType::TEXT_PLAIN)
-                            .body(body.clone()),
-                    )
-                    .singlepart(
-                        SinglePart::builder()
-                            .header(ContentType::TEXT_HTML)
-                            .body(html_body.clone()),
-                    ),
-            )?,
+            MessageFormat::Plain => {
+                let mut multipart = MultiPart::mixed().singlepart(
+                    SinglePart::builder()
+                        .header(ContentType::TEXT_PLAIN)
+                        .body(body.clone()),
+                );
+                for attachment in attachments {
+                    multipart = multipart.singlepart(attachment);
+                }
+                email_builder.multipart(multipart)?
+            }
+            MessageFormat::Markdown | MessageFormat::Html => {
+                let mut related = MultiPart::related().singlepart(
+                    SinglePart::builder()
+                        .header(ContentType::TEXT_HTML)
+                        .body(html_body.clone()),
+                );
+                for attachment in attachments {
+                    related = related.singlepart(attachment);
+                }
+                email_builder.multipart(
+                    MultiPart::alternative()
+                        .singlepart(
+                            SinglePart::builder()
+                                .header(ContentType::TEXT_PLAIN)
+                                .body(body.clone()),
+                        )
+                        .multipart(related),
+                )?
+            }
         };
         self.mailer.send(email).await?;
         Ok(())
     }
+
+    async fn check(&self) -> Result<()> {
+        if !self.mailer.test_connection().await? {
+            bail!("the SMTP server did not respond");
+        }
+        Ok(())
+    }
 }
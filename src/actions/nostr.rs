@@ -21,8 +21,7 @@ use serde_json::from_reader;
 use serde_json::to_string;
 
 use super::Action;
-use crate::message::MessageConfig;
-use crate::message::MessageParams;
+use crate::message::Message;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct NostrData {
@@ -104,7 +103,7 @@ fn mark_bot_metadata_as_set(mut nostr_data: NostrData) -> Result<()> {
     Ok(())
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct NostrConfig {
     #[serde(default = "get_default_relays")]
     relays: Vec<String>,
@@ -120,18 +119,14 @@ pub struct NostrConfig {
 
 impl NostrConfig {}
 
-pub struct NostrAction<'a> {
-    message_config: &'a MessageConfig,
+pub struct NostrAction {
     client: Client,
     recipient: PublicKey,
     sealed_dm: bool,
 }
 
-impl<'a> NostrAction<'a> {
-    pub async fn new(
-        message_config: &'a MessageConfig,
-        nostr_config: &'a NostrConfig,
-    ) -> Result<Self> {
+impl NostrAction {
+    pub async fn new(nostr_config: &NostrConfig) -> Result<Self> {
         let nostr_data = get_nostr_data()?;
         let keys = Keys::parse(&nostr_data.key)
             .with_context(|| format!("could not parse nostr secret key '{}'", nostr_data.key))?;
@@ -165,7 +160,6 @@ impl<'a> NostrAction<'a> {
         };
 
         Ok(Self {
-            message_config,
             client,
             recipient,
             sealed_dm: nostr_config.sealed_dm,
@@ -174,15 +168,13 @@ impl<'a> NostrAction<'a> {
 }
 
 #[async_trait]
-impl Action<'_> for NostrAction<'_> {
+impl Action for NostrAction {
     fn name(&self) -> &'static str {
         "nostr"
     }
 
-    async fn run(&self, params: Option<&MessageParams<'_, '_>>) -> Result<()> {
-        let subject = self.message_config.subject(params)?;
-        let body = self.message_config.body(params)?;
-        let message = format!("{}\n{}", subject, body);
+    async fn run(&self, message: &Message) -> Result<()> {
+        let message = format!("{}\n{}", message.subject(), message.body());
 
         if self.sealed_dm {
             self.client
@@ -2,11 +2,16 @@ use std::fs::File;
 use std::io::BufReader;
 use std::io::Write;
 use std::path::PathBuf;
+use std::sync::Mutex;
 
 use anyhow::Context;
 use anyhow::Result;
 use async_trait::async_trait;
-use log::info;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use log::{info, warn};
+use ntfy::payload::{Action as NtfyButton, ActionType};
 use ntfy::Auth;
 use ntfy::Dispatcher;
 use ntfy::Payload;
@@ -21,9 +26,11 @@ use serde_json::from_reader;
 use serde_json::to_string;
 
 use super::Action;
-use crate::message::MessageConfig;
+use crate::message::find_ack_id;
+use crate::message::Attachment;
+use crate::message::Message;
 use crate::message::MessageFormat;
-use crate::message::MessageParams;
+use crate::secret::get_secret;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct NtfyData {
@@ -83,18 +90,32 @@ pub enum NtfyPriority {
     Min = 1,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct NtfyCredentials {
     username: String,
-    password: String,
+    password: Option<String>,
+    password_file: Option<PathBuf>,
 }
 
-#[derive(Deserialize, Debug)]
+impl NtfyCredentials {
+    pub fn auth(&self) -> Result<Auth> {
+        let password = get_secret(
+            "password",
+            self.password.as_deref(),
+            self.password_file.as_deref(),
+        )?;
+        Ok(Auth::new(&self.username, &password))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct NtfyConfig {
     url: Option<String>,
     proxy: Option<String>,
     topic: Option<String>,
     pub credentials: Option<NtfyCredentials>,
+    ack_token: Option<String>,
+    ack_token_file: Option<PathBuf>,
     #[serde(with = "NtfyPriority")]
     #[serde(default)]
     pub priority: Priority,
@@ -113,23 +134,57 @@ impl NtfyConfig {
     pub fn topic(&self) -> Option<&str> {
         self.topic.as_deref()
     }
+
+    /// Access token that can only publish to the ack topic, for the acknowledge button
+    fn ack_token(&self) -> Result<Option<String>> {
+        if self.ack_token.is_none() && self.ack_token_file.is_none() {
+            return Ok(None);
+        }
+        get_secret(
+            "ack_token",
+            self.ack_token.as_deref(),
+            self.ack_token_file.as_deref(),
+        )
+        .map(Some)
+    }
+}
+
+/// A message published to a topic, as returned when polling it
+#[derive(Deserialize, Debug)]
+struct NtfyMessage {
+    id: String,
+    event: String,
+    message: Option<String>,
 }
 
-pub struct NtfyAction<'a> {
-    message_config: &'a MessageConfig,
+pub struct NtfyAction {
     dispatcher: Dispatcher,
     payload_template: Payload,
+    url: String,
+    ack_topic: String,
+    ack_token: Option<String>,
+    credentials: Option<Auth>,
+    client: reqwest::Client,
+    /// Id of the last polled acknowledgement, or the startup time before there is any
+    ack_since: Mutex<String>,
 }
 
-impl<'a> NtfyAction<'a> {
-    pub fn new(message_config: &'a MessageConfig, ntfy_config: &'a NtfyConfig) -> Result<Self> {
+impl NtfyAction {
+    pub fn new(ntfy_config: &NtfyConfig) -> Result<Self> {
         let mut dispatcher_builder = Dispatcher::builder(ntfy_config.url());
-        if let Some(cred) = &ntfy_config.credentials {
-            dispatcher_builder =
-                dispatcher_builder.credentials(Auth::new(&cred.username, &cred.password));
+        // The dispatcher can't upload files, so attachments are sent with a separate client
+        let mut client_builder = reqwest::Client::builder();
+        let credentials = ntfy_config
+            .credentials
+            .as_ref()
+            .map(NtfyCredentials::auth)
+            .transpose()?;
+        if let Some(cred) = &credentials {
+            dispatcher_builder = dispatcher_builder.credentials(cred.clone());
         }
         if let Some(proxy) = &ntfy_config.proxy {
             dispatcher_builder = dispatcher_builder.proxy(proxy);
+            client_builder = client_builder.proxy(reqwest::Proxy::all(proxy)?);
         }
 
         let topic = ntfy_config
@@ -143,11 +198,6 @@ impl<'a> NtfyAction<'a> {
             topic
         );
         let mut payload = Payload::new(&topic)
-            .markdown(match message_config.format() {
-                MessageFormat::Plain => false,
-                MessageFormat::Markdown => true,
-                MessageFormat::Html => true,
-            })
             .priority(ntfy_config.priority.clone())
             .tags(
                 ntfy_config
@@ -168,27 +218,149 @@ impl<'a> NtfyAction<'a> {
             payload = payload.email(email.clone());
         }
         Ok(Self {
-            message_config,
             dispatcher: dispatcher_builder.build()?,
             payload_template: payload,
+            url: ntfy_config.url().trim_end_matches('/').to_string(),
+            ack_topic: format!("{}-ack", topic),
+            ack_token: ntfy_config.ack_token()?,
+            credentials,
+            client: client_builder.build()?,
+            ack_since: Mutex::new(Utc::now().timestamp().to_string()),
         })
     }
+
+    /// Button that acknowledges the message by publishing its ack id to the ack topic. It is sent
+    /// by the phone, which never gets the credentials: the ack topic has to be writable without
+    /// them, or with the ack token.
+    fn ack_button(&self, ack_id: &str) -> Result<NtfyButton> {
+        let mut url: Url = format!("{}/{}", self.url, self.ack_topic).parse()?;
+        if let Some(token) = &self.ack_token {
+            let header = format!("Bearer {}", token);
+            url.query_pairs_mut()
+                .append_pair("auth", &URL_SAFE_NO_PAD.encode(header));
+        }
+        Ok(NtfyButton::new(ActionType::Http, "Acknowledge", url)
+            .body(format!("ack {}", ack_id).into())
+            .clear(true))
+    }
+
+    /// Publishes the message with the attachment uploaded as the request body, which is how
+    /// ntfy receives files
+    async fn send_with_attachment(&self, payload: &Payload, attachment: &Attachment) -> Result<()> {
+        let priority = payload.priority.clone() as u8;
+        let mut query = vec![
+            ("message", payload.message.clone()),
+            ("filename", attachment.filename().to_string()),
+            ("priority", priority.to_string()),
+            ("markdown", payload.markdown.to_string()),
+        ];
+        let optional = [
+            ("title", payload.title.clone()),
+            ("tags", payload.tags.as_ref().map(|t| t.join(","))),
+            ("click", payload.click.as_ref().map(|c| c.to_string())),
+            ("delay", payload.delay.clone()),
+            ("email", payload.email.clone()),
+            (
+                "actions",
+                payload.actions.as_ref().map(to_string).transpose()?,
+            ),
+        ];
+        query.extend(
+            optional
+                .into_iter()
+                .filter_map(|(key, value)| value.map(|v| (key, v))),
+        );
+        let mut request = self
+            .client
+            .put(format!("{}/{}", self.url, payload.topic))
+            .query(&query)
+            .body(attachment.data().to_vec());
+        if let Some(cred) = &self.credentials {
+            request = request.header("Authorization", format!("Basic {}", cred.as_base64()));
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
 }
 
 #[async_trait]
-impl Action<'_> for NtfyAction<'_> {
+impl Action for NtfyAction {
     fn name(&self) -> &'static str {
         "ntfy"
     }
 
-    async fn run(&self, params: Option<&MessageParams<'_, '_>>) -> Result<()> {
-        let payload = self
+    async fn run(&self, message: &Message) -> Result<()> {
+        let mut payload = self
             .payload_template
             .clone()
-            .title(self.message_config.subject(params)?)
-            .message(self.message_config.body(params)?)
-            .click(self.message_config.get_tx_url(params)?.parse()?);
-        self.dispatcher.send(&payload).await?;
+            .title(message.subject())
+            .message(message.body())
+            .markdown(match message.format() {
+                MessageFormat::Plain => false,
+                MessageFormat::Markdown => true,
+                MessageFormat::Html => true,
+            });
+        if let Some(url) = message.url() {
+            payload = payload.click(url.parse()?);
+        }
+        if let Some(ack_id) = message.ack_id() {
+            payload = payload.actions([self.ack_button(ack_id)?]);
+        }
+        // ntfy only supports a single attachment per message
+        match message.attachments().first() {
+            Some(attachment) if payload.attach.is_none() => {
+                self.send_with_attachment(&payload, attachment).await?
+            }
+            _ => self.dispatcher.send(&payload).await?,
+        }
+        Ok(())
+    }
+
+    async fn poll_acks(&self) -> Vec<String> {
+        let since = self.ack_since.lock().unwrap().clone();
+        let mut request = self
+            .client
+            .get(format!("{}/{}/json", self.url, self.ack_topic))
+            .query(&[("poll", "1"), ("since", since.as_str())]);
+        if let Some(cred) = &self.credentials {
+            request = request.header("Authorization", format!("Basic {}", cred.as_base64()));
+        }
+        let response = match request.send().await.and_then(|r| r.error_for_status()) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("[ntfy] cannot poll acknowledgements: {:#}", e);
+                return Vec::new();
+            }
+        };
+        let body = response.text().await.unwrap_or_default();
+        let messages: Vec<NtfyMessage> = body
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        if let Some(last) = messages.last() {
+            *self.ack_since.lock().unwrap() = last.id.clone();
+        }
+        messages
+            .iter()
+            .filter(|m| m.event == "message")
+            .filter_map(|m| m.message.as_deref().and_then(find_ack_id))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Asks the server whether the topic can be accessed with the configured credentials
+    async fn check(&self) -> Result<()> {
+        let mut request = self
+            .client
+            .get(format!("{}/{}/auth", self.url, self.payload_template.topic));
+        if let Some(cred) = &self.credentials {
+            request = request.header("Authorization", format!("Basic {}", cred.as_base64()));
+        }
+        request
+            .send()
+            .await?
+            .error_for_status()
+            .context("the server denied access to the topic")?;
         Ok(())
     }
 }
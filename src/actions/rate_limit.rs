@@ -0,0 +1,66 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use serde::Deserialize;
+use tokio::time::sleep;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RateLimitConfig {
+    messages: usize,
+    period_secs: Option<u64>,
+}
+
+impl RateLimitConfig {
+    pub fn messages(&self) -> usize {
+        self.messages.max(1)
+    }
+
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period_secs.unwrap_or(60))
+    }
+}
+
+/// Delays messages so that no more than the configured amount are sent within any period
+pub struct RateLimiter {
+    messages: usize,
+    period: Duration,
+    sent: Mutex<VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            messages: config.messages(),
+            period: config.period(),
+            sent: Default::default(),
+        }
+    }
+
+    /// Time to wait before the next message can be sent, reserving a slot if there's no need
+    fn reserve(&self) -> Option<Duration> {
+        let mut sent = self.sent.lock().unwrap();
+        while sent.front().is_some_and(|t| t.elapsed() >= self.period) {
+            sent.pop_front();
+        }
+        if sent.len() < self.messages {
+            sent.push_back(Instant::now());
+            return None;
+        }
+        sent.front()
+            .map(|t| self.period.saturating_sub(t.elapsed()))
+    }
+
+    /// Waits until a message can be sent, and counts it as sent
+    pub async fn wait(&self, action_name: &str) {
+        while let Some(delay) = self.reserve() {
+            debug!(
+                "[{}] rate limited, waiting {}s",
+                action_name,
+                delay.as_secs()
+            );
+            sleep(delay).await;
+        }
+    }
+}
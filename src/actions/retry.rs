@@ -0,0 +1,254 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::Deserialize;
+use tokio::time::timeout;
+
+use super::rate_limit::{RateLimitConfig, RateLimiter};
+use super::Action;
+use crate::message::Message;
+use crate::outbox::{Outbox, OutboxEntry};
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct RetryConfig {
+    attempts: Option<u32>,
+    initial_delay_secs: Option<u64>,
+    max_delay_secs: Option<u64>,
+}
+
+impl RetryConfig {
+    /// How many times a failed message is retried
+    pub fn attempts(&self) -> u32 {
+        self.attempts.unwrap_or(5)
+    }
+
+    pub fn initial_delay(&self) -> Duration {
+        Duration::from_secs(self.initial_delay_secs.unwrap_or(30))
+    }
+
+    pub fn max_delay(&self) -> Duration {
+        Duration::from_secs(self.max_delay_secs.unwrap_or(3600))
+    }
+
+    /// Exponential backoff: the delay doubles after each failed attempt
+    pub fn delay(&self, attempts: u32) -> Duration {
+        self.initial_delay()
+            .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+            .min(self.max_delay())
+    }
+}
+
+pub struct RetryingAction {
+    action: Box<dyn Action + Send + Sync>,
+    id: String,
+    retry: RetryConfig,
+    timeout: Duration,
+    limiter: Option<RateLimiter>,
+    outbox: Outbox,
+}
+
+impl RetryingAction {
+    pub fn new(
+        action: Box<dyn Action + Send + Sync>,
+        id: &str,
+        retry: RetryConfig,
+        timeout: Duration,
+        rate_limit: Option<&RateLimitConfig>,
+        outbox: Outbox,
+    ) -> Self {
+        Self {
+            action,
+            id: id.to_string(),
+            retry,
+            timeout,
+            limiter: rate_limit.map(RateLimiter::new),
+            outbox,
+        }
+    }
+
+    /// Retries count against the rate limit like first attempts, but waiting for it doesn't count
+    /// against the timeout
+    async fn attempt(&self, message: &Message) -> Result<()> {
+        if let Some(limiter) = &self.limiter {
+            limiter.wait(self.name()).await;
+        }
+        let result = timeout(self.timeout, self.action.run(message))
+            .await
+            .map_err(|_| anyhow!("timed out after {}s", self.timeout.as_secs()))
+            .and_then(|result| result);
+        #[cfg(feature = "metrics")]
+        crate::metrics::count_delivery(&self.id, result.is_ok());
+        result
+    }
+
+    /// Reschedules the message for another attempt, unless it already failed too many times
+    fn retry_later(
+        &self,
+        id: u64,
+        message: &Message,
+        attempts: u32,
+        e: anyhow::Error,
+    ) -> Result<()> {
+        if attempts > self.retry.attempts() {
+            self.outbox.remove(id);
+            return Err(e.context(format!("giving up after {} attempts", attempts)));
+        }
+        let delay = self.retry.delay(attempts);
+        warn!(
+            "[{}][{}][{}] could not run action, retrying in {}s: {:#}",
+            message.source(),
+            message.id(),
+            self.id,
+            delay.as_secs(),
+            e
+        );
+        self.outbox.reschedule(id, Utc::now() + delay, attempts);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Action for RetryingAction {
+    fn name(&self) -> &'static str {
+        self.action.name()
+    }
+
+    async fn run(&self, message: &Message) -> Result<()> {
+        let id = self.outbox.push_in_flight(message.clone());
+        match self.attempt(message).await {
+            Ok(()) => {
+                self.outbox.remove(id);
+                Ok(())
+            }
+            Err(e) => self.retry_later(id, message, 1, e),
+        }
+    }
+
+    async fn process_pending(&self) {
+        self.action.process_pending().await;
+        for entry in self.outbox.take_due(Utc::now()) {
+            let message = entry.message();
+            let attempts = entry.attempts() + 1;
+            match self.attempt(message).await {
+                Ok(()) => {
+                    self.outbox.remove(entry.id());
+                    info!(
+                        "[{}][{}][{}] delivered after {} attempts",
+                        message.source(),
+                        message.id(),
+                        self.id,
+                        attempts
+                    )
+                }
+                Err(e) => {
+                    if let Err(e) = self.retry_later(entry.id(), message, attempts, e) {
+                        error!(
+                            "[{}][{}][{}] could not run action: {:#}",
+                            message.source(),
+                            message.id(),
+                            self.id,
+                            e
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn pending(&self) -> Vec<OutboxEntry> {
+        let mut pending = self.action.pending();
+        pending.extend(self.outbox.entries());
+        pending
+    }
+
+    async fn poll_acks(&self) -> Vec<String> {
+        self.action.poll_acks().await
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.action.check().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn delay_doubles_up_to_the_max() {
+        let retry: RetryConfig =
+            toml::from_str("initial_delay_secs = 10\nmax_delay_secs = 60").unwrap();
+        let delays: Vec<u64> = (1..=5).map(|a| retry.delay(a).as_secs()).collect();
+        assert_eq!(delays, [10, 20, 40, 60, 60]);
+    }
+
+    #[test]
+    fn delay_does_not_overflow() {
+        let retry = RetryConfig::default();
+        assert_eq!(retry.delay(0), retry.initial_delay());
+        assert_eq!(retry.delay(u32::MAX), retry.max_delay());
+    }
+
+    struct FailingAction(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl Action for FailingAction {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        async fn run(&self, _message: &Message) -> Result<()> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Err(anyhow!("unreachable"))
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_count_against_the_rate_limit() {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "{}-test-{}",
+                env!("CARGO_PKG_NAME"),
+                std::process::id()
+            ))
+            .join("rate-limited-retries.json");
+        let _ = std::fs::remove_file(&path);
+        let runs = Arc::new(AtomicUsize::new(0));
+        let retry: RetryConfig = toml::from_str("initial_delay_secs = 0").unwrap();
+        let rate_limit: RateLimitConfig = toml::from_str("messages = 1").unwrap();
+        let action = RetryingAction::new(
+            Box::new(FailingAction(runs.clone())),
+            "failing",
+            retry,
+            Duration::from_secs(1),
+            Some(&rate_limit),
+            Outbox::load(path),
+        );
+        let message = serde_json::from_value(serde_json::json!({
+            "source": "wallet",
+            "id": "a",
+            "subject": "subject",
+            "body": "body",
+            "format": "plain",
+            "url": null,
+            "context": null,
+            "attachments": [],
+            "critical": false,
+        }))
+        .unwrap();
+
+        assert!(action.run(&message).await.is_ok());
+        assert_eq!(action.pending().len(), 1);
+        // The only message allowed in the period was the first attempt
+        let retried = tokio::time::timeout(Duration::from_millis(200), action.process_pending());
+        assert!(retried.await.is_err());
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+    }
+}
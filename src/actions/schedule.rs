@@ -0,0 +1,268 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Days, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use log::{error, info};
+use serde::Deserialize;
+
+use super::Action;
+use crate::message::Message;
+use crate::outbox::{Outbox, OutboxEntry};
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QuietHoursPolicy {
+    #[default]
+    Defer,
+    Drop,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ScheduleConfig {
+    start: String,
+    end: String,
+    days: Option<Vec<String>>,
+    #[serde(default)]
+    quiet_hours: QuietHoursPolicy,
+    bypass_critical: Option<bool>,
+}
+
+impl ScheduleConfig {
+    pub fn quiet_hours(&self) -> QuietHoursPolicy {
+        self.quiet_hours
+    }
+
+    pub fn bypass_critical(&self) -> bool {
+        self.bypass_critical.unwrap_or(true)
+    }
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .with_context(|| format!("invalid schedule time '{}', expected HH:MM", time))
+}
+
+/// Daily time window in which an action may send messages. Windows with `start` after `end`
+/// span midnight and belong to the day they start on.
+struct Schedule {
+    start: NaiveTime,
+    end: NaiveTime,
+    days: Vec<Weekday>,
+    timezone: Tz,
+}
+
+impl Schedule {
+    fn new(config: &ScheduleConfig, timezone: Tz) -> Result<Self> {
+        let days = match &config.days {
+            Some(days) => days
+                .iter()
+                .map(|d| {
+                    d.parse::<Weekday>()
+                        .map_err(|_| anyhow!("invalid schedule day '{}'", d))
+                })
+                .collect::<Result<_>>()?,
+            None => vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+                Weekday::Sat,
+                Weekday::Sun,
+            ],
+        };
+        Ok(Self {
+            start: parse_time(&config.start)?,
+            end: parse_time(&config.end)?,
+            days,
+            timezone,
+        })
+    }
+
+    fn is_open(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.timezone);
+        let time = local.time();
+        let window_day = if self.start < self.end {
+            (time >= self.start && time < self.end).then(|| local.weekday())
+        } else if time >= self.start {
+            Some(local.weekday())
+        } else if time < self.end {
+            Some(local.weekday().pred())
+        } else {
+            None
+        };
+        window_day.is_some_and(|day| self.days.contains(&day))
+    }
+
+    /// When the window opens next, if it ever does
+    fn next_open(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let today = now.with_timezone(&self.timezone).date_naive();
+        (0..=7)
+            .filter_map(|d| today.checked_add_days(Days::new(d)))
+            .filter(|date| self.days.contains(&date.weekday()))
+            .filter_map(|date| {
+                self.timezone
+                    .from_local_datetime(&date.and_time(self.start))
+                    .earliest()
+            })
+            .map(|t| t.with_timezone(&Utc))
+            .find(|t| *t > now)
+    }
+}
+
+pub struct ScheduledAction {
+    action: Box<dyn Action + Send + Sync>,
+    id: String,
+    schedule: Schedule,
+    quiet_hours: QuietHoursPolicy,
+    bypass_critical: bool,
+    outbox: Outbox,
+}
+
+impl ScheduledAction {
+    pub fn new(
+        action: Box<dyn Action + Send + Sync>,
+        id: &str,
+        config: &ScheduleConfig,
+        timezone: Tz,
+        outbox: Outbox,
+    ) -> Result<Self> {
+        Ok(Self {
+            action,
+            id: id.to_string(),
+            schedule: Schedule::new(config, timezone)?,
+            quiet_hours: config.quiet_hours(),
+            bypass_critical: config.bypass_critical(),
+            outbox,
+        })
+    }
+}
+
+#[async_trait]
+impl Action for ScheduledAction {
+    fn name(&self) -> &'static str {
+        self.action.name()
+    }
+
+    async fn run(&self, message: &Message) -> Result<()> {
+        let now = Utc::now();
+        if self.schedule.is_open(now) || (self.bypass_critical && message.critical()) {
+            return self.action.run(message).await;
+        }
+        match (self.quiet_hours, self.schedule.next_open(now)) {
+            (QuietHoursPolicy::Defer, Some(next_open)) => {
+                info!(
+                    "[{}][{}][{}] quiet hours, deferring until {}",
+                    message.source(),
+                    message.id(),
+                    self.id,
+                    next_open
+                );
+                self.outbox.push(message.clone(), next_open, 0);
+            }
+            _ => info!(
+                "[{}][{}][{}] quiet hours, dropping message",
+                message.source(),
+                message.id(),
+                self.id
+            ),
+        }
+        Ok(())
+    }
+
+    async fn process_pending(&self) {
+        self.action.process_pending().await;
+        for entry in self.outbox.take_due(Utc::now()) {
+            let message = entry.message();
+            info!(
+                "[{}][{}][{}] sending deferred message",
+                message.source(),
+                message.id(),
+                self.id
+            );
+            if let Err(e) = self.action.run(message).await {
+                error!(
+                    "[{}][{}][{}] could not run action: {:#}",
+                    message.source(),
+                    message.id(),
+                    self.id,
+                    e
+                );
+            }
+            self.outbox.remove(entry.id());
+        }
+    }
+
+    fn pending(&self) -> Vec<OutboxEntry> {
+        let mut pending = self.action.pending();
+        pending.extend(self.outbox.entries());
+        pending
+    }
+
+    async fn poll_acks(&self) -> Vec<String> {
+        self.action.poll_acks().await
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.action.check().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(config: &str) -> Schedule {
+        Schedule::new(&toml::from_str(config).unwrap(), chrono_tz::UTC).unwrap()
+    }
+
+    fn at(time: &str) -> DateTime<Utc> {
+        // 2024-01-01 was a monday
+        DateTime::parse_from_rfc3339(&format!("2024-01-01T{}:00Z", time))
+            .unwrap()
+            .to_utc()
+    }
+
+    #[test]
+    fn daytime_window() {
+        let schedule = schedule("start = '08:00'\nend = '22:00'");
+        assert!(!schedule.is_open(at("07:59")));
+        assert!(schedule.is_open(at("08:00")));
+        assert!(schedule.is_open(at("21:59")));
+        assert!(!schedule.is_open(at("22:00")));
+    }
+
+    #[test]
+    fn window_spanning_midnight_belongs_to_its_start_day() {
+        let schedule = schedule("start = '22:00'\nend = '06:00'\ndays = ['sun']");
+        // Monday early morning is still sunday's window
+        assert!(schedule.is_open(at("05:59")));
+        assert!(!schedule.is_open(at("06:00")));
+        // Monday night's window is not scheduled
+        assert!(!schedule.is_open(at("23:00")));
+    }
+
+    #[test]
+    fn closed_on_other_days() {
+        let schedule = schedule("start = '08:00'\nend = '22:00'\ndays = ['sat', 'sun']");
+        assert!(!schedule.is_open(at("12:00")));
+    }
+
+    #[test]
+    fn windows_follow_the_timezone() {
+        let schedule = Schedule::new(
+            &toml::from_str("start = '08:00'\nend = '22:00'").unwrap(),
+            chrono_tz::Europe::Lisbon,
+        )
+        .unwrap();
+        // Lisbon is on UTC in winter
+        assert!(schedule.is_open(at("08:00")));
+        let schedule = Schedule::new(
+            &toml::from_str("start = '08:00'\nend = '22:00'").unwrap(),
+            chrono_tz::Europe::Berlin,
+        )
+        .unwrap();
+        assert!(schedule.is_open(at("07:00")));
+        assert!(!schedule.is_open(at("21:00")));
+    }
+}
@@ -1,22 +1,36 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+
 use super::Action;
-use crate::message::MessageConfig;
-use crate::message::MessageParams;
-use anyhow::Result;
+use crate::message::find_ack_id;
+use crate::message::Message;
+use crate::secret::get_secret;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
+use log::warn;
 use serde::Deserialize;
+use teloxide::payloads::GetUpdatesSetters;
 use teloxide::requests::Requester;
+use teloxide::types::ChatId;
+use teloxide::types::InputFile;
+use teloxide::types::UpdateKind;
 use teloxide::types::UserId;
 use teloxide::Bot;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct TelegramConfig {
-    bot_token: String,
+    bot_token: Option<String>,
+    bot_token_file: Option<PathBuf>,
     user_id: u64,
 }
 
 impl TelegramConfig {
-    pub fn bot_token(&self) -> &str {
-        &self.bot_token
+    pub fn bot_token(&self) -> Result<String> {
+        get_secret(
+            "bot_token",
+            self.bot_token.as_deref(),
+            self.bot_token_file.as_deref(),
+        )
     }
 
     pub fn user_id(&self) -> u64 {
@@ -24,37 +38,94 @@ impl TelegramConfig {
     }
 }
 
-pub struct TelegramAction<'a> {
-    message_config: &'a MessageConfig,
+pub struct TelegramAction {
     bot: Bot,
     user_id: UserId,
+    /// Id of the next update to fetch when polling for acknowledgements
+    update_offset: Mutex<Option<i32>>,
 }
 
-impl<'a> TelegramAction<'a> {
-    pub fn new(
-        message_config: &'a MessageConfig,
-        telegram_config: &'a TelegramConfig,
-    ) -> Result<Self> {
+impl TelegramAction {
+    pub fn new(telegram_config: &TelegramConfig) -> Result<Self> {
         Ok(Self {
-            message_config,
-            bot: Bot::new(telegram_config.bot_token()),
+            bot: Bot::new(telegram_config.bot_token()?),
             user_id: UserId(telegram_config.user_id()),
+            update_offset: Default::default(),
         })
     }
 }
 
 #[async_trait]
-impl Action<'_> for TelegramAction<'_> {
+impl Action for TelegramAction {
     fn name(&self) -> &'static str {
         "telegram"
     }
 
-    async fn run(&self, params: Option<&MessageParams<'_, '_>>) -> Result<()> {
-        let subject = self.message_config.subject(params)?;
-        let body = self.message_config.body(params)?;
+    async fn run(&self, message: &Message) -> Result<()> {
         self.bot
-            .send_message(self.user_id, format!("{}\n{}", subject, body))
+            .send_message(
+                self.user_id,
+                format!("{}\n{}", message.subject(), message.body()),
+            )
             .await?;
+        for attachment in message.attachments() {
+            let file = InputFile::memory(attachment.data().to_vec())
+                .file_name(attachment.filename().to_string());
+            if attachment.is_image() {
+                self.bot.send_photo(self.user_id, file).await?;
+            } else {
+                self.bot.send_document(self.user_id, file).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replies to an escalated message, or messages containing "ack <id>", acknowledge it
+    async fn poll_acks(&self) -> Vec<String> {
+        let offset = *self.update_offset.lock().unwrap();
+        let mut request = self.bot.get_updates();
+        if let Some(offset) = offset {
+            request = request.offset(offset);
+        }
+        let updates = match request.await {
+            Ok(updates) => updates,
+            Err(e) => {
+                warn!("[telegram] cannot poll acknowledgements: {:#}", e);
+                return Vec::new();
+            }
+        };
+        if let Some(last) = updates.last() {
+            *self.update_offset.lock().unwrap() = Some(last.id + 1);
+        }
+        updates
+            .iter()
+            .filter_map(|update| match &update.kind {
+                UpdateKind::Message(message) if message.chat.id == ChatId::from(self.user_id) => {
+                    message
+                        .text()
+                        .and_then(find_ack_id)
+                        .or_else(|| {
+                            message
+                                .reply_to_message()
+                                .and_then(|m| m.text())
+                                .and_then(find_ack_id)
+                        })
+                        .map(str::to_string)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.bot
+            .get_me()
+            .await
+            .context("could not log in as the bot")?;
+        self.bot
+            .get_chat(self.user_id)
+            .await
+            .context("the bot cannot message the user, make sure they started a chat with it")?;
         Ok(())
     }
 }
@@ -0,0 +1,336 @@
+use std::{collections::HashMap, fmt, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::blockchain::SafeBackendStatus;
+use crate::wallets::SafeWalletState;
+
+#[derive(Deserialize, Debug)]
+pub struct AlertsConfig {
+    backend_mins: Option<u64>,
+    sync_mins: Option<u64>,
+    critical: Option<bool>,
+}
+
+impl AlertsConfig {
+    /// How long the electrum server can be unreachable before alerting
+    pub fn backend_threshold(&self) -> Duration {
+        Duration::from_secs(self.backend_mins.unwrap_or(10) * 60)
+    }
+
+    /// How long a wallet can go without syncing before alerting
+    pub fn sync_threshold(&self) -> Duration {
+        Duration::from_secs(self.sync_mins.unwrap_or(30) * 60)
+    }
+
+    /// Whether alerts are critical messages, e.g. bypassing quiet hours. Recoveries never are.
+    pub fn critical(&self) -> bool {
+        self.critical.unwrap_or(true)
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Hash, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    BackendUnreachable,
+    BackendRecovered,
+    SyncStalled,
+    SyncRecovered,
+}
+
+impl fmt::Display for AlertKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AlertKind::BackendUnreachable => write!(f, "backend_unreachable"),
+            AlertKind::BackendRecovered => write!(f, "backend_recovered"),
+            AlertKind::SyncStalled => write!(f, "sync_stalled"),
+            AlertKind::SyncRecovered => write!(f, "sync_recovered"),
+        }
+    }
+}
+
+impl AlertKind {
+    pub fn is_recovery(&self) -> bool {
+        matches!(self, AlertKind::BackendRecovered | AlertKind::SyncRecovered)
+    }
+}
+
+/// Something sentrum noticed about itself, to be rendered into a message
+pub struct Alert {
+    kind: AlertKind,
+    wallet: Option<String>,
+    url: String,
+    since: DateTime<Utc>,
+    duration: chrono::Duration,
+    error: Option<String>,
+}
+
+impl Alert {
+    pub fn kind(&self) -> AlertKind {
+        self.kind
+    }
+
+    /// Wallet that is not syncing, for sync alerts
+    pub fn wallet(&self) -> Option<&str> {
+        self.wallet.as_deref()
+    }
+
+    /// Electrum server in use
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Last time things worked
+    pub fn since(&self) -> DateTime<Utc> {
+        self.since
+    }
+
+    /// How long things have not been working, or did not work for recoveries
+    pub fn duration(&self) -> chrono::Duration {
+        self.duration
+    }
+
+    /// Last error, if known
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+/// A made up alert of the given kind, to try out templates
+pub fn example_alert(kind: AlertKind, url: &str) -> Alert {
+    let duration = chrono::Duration::minutes(30);
+    let is_sync = matches!(kind, AlertKind::SyncStalled | AlertKind::SyncRecovered);
+    Alert {
+        kind,
+        wallet: is_sync.then(|| "example".to_string()),
+        url: url.to_string(),
+        since: Utc::now() - duration,
+        duration,
+        error: (!kind.is_recovery()).then(|| "connection refused".to_string()),
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum Watched {
+    Backend,
+    /// Wallets are told apart by their name, so that their outages carry over reloads
+    Wallet(String),
+}
+
+/// Start or end of an outage
+struct Transition {
+    recovered: bool,
+    since: DateTime<Utc>,
+    duration: chrono::Duration,
+}
+
+/// What is known about something being watched
+struct Watch {
+    /// Last time it was known to work, or when watching started if it never did
+    last_ok: DateTime<Utc>,
+    error: Option<String>,
+    muted: bool,
+    /// When the outage that was alerted started, until it recovers
+    alerted_since: Option<DateTime<Utc>>,
+}
+
+impl Watch {
+    fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            last_ok: now,
+            error: None,
+            muted: false,
+            alerted_since: None,
+        }
+    }
+
+    /// Starts an outage once it lasted longer than the threshold, and ends it when things work
+    /// again. Outages are not started while muted.
+    fn update(&mut self, threshold: Duration, now: DateTime<Utc>) -> Option<Transition> {
+        let down_for = now - self.last_ok;
+        match self.alerted_since {
+            None if !self.muted && down_for.to_std().is_ok_and(|d| d >= threshold) => {
+                self.alerted_since = Some(self.last_ok);
+                Some(Transition {
+                    recovered: false,
+                    since: self.last_ok,
+                    duration: down_for,
+                })
+            }
+            Some(since) if self.last_ok > since => {
+                self.alerted_since = None;
+                Some(Transition {
+                    recovered: true,
+                    since,
+                    duration: self.last_ok - since,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Watches the electrum server and the wallet syncs, raising an alert once per outage and another
+/// when it recovers. Kept across reloads, so that ongoing outages aren't alerted again.
+#[derive(Default)]
+pub struct Alerts {
+    watches: Mutex<HashMap<Watched, Watch>>,
+}
+
+impl Alerts {
+    /// Outages that started or ended since the last check
+    pub fn check(
+        &self,
+        config: &AlertsConfig,
+        wallets: &[SafeWalletState],
+        backend: &SafeBackendStatus,
+        now: DateTime<Utc>,
+    ) -> Vec<Alert> {
+        let mut watches = self.watches.lock().unwrap();
+        let backend = backend.lock().unwrap().clone();
+        let mut alerts = Vec::new();
+
+        let watch = watches
+            .entry(Watched::Backend)
+            .or_insert_with(|| Watch::new(now));
+        match &backend.error {
+            Some(e) => {
+                if let Some(last_seen) = backend.last_seen {
+                    watch.last_ok = watch.last_ok.max(last_seen);
+                }
+                watch.error = Some(e.clone());
+            }
+            // Also before the first poll, which is the one that finds out
+            None => {
+                watch.last_ok = now;
+                watch.error = None;
+            }
+        }
+        if let Some(transition) = watch.update(config.backend_threshold(), now) {
+            alerts.push(Alert {
+                kind: match transition.recovered {
+                    true => AlertKind::BackendRecovered,
+                    false => AlertKind::BackendUnreachable,
+                },
+                wallet: None,
+                url: backend.url.clone(),
+                since: transition.since,
+                duration: transition.duration,
+                error: watch.error.clone(),
+            });
+        }
+
+        let states = wallets
+            .iter()
+            .map(|state| state.lock().unwrap().clone())
+            .collect::<Vec<_>>();
+        watches.retain(|watched, _| match watched {
+            Watched::Backend => true,
+            Watched::Wallet(name) => states.iter().any(|state| state.name == *name),
+        });
+        for state in states {
+            let watch = watches
+                .entry(Watched::Wallet(state.name.clone()))
+                .or_insert_with(|| Watch::new(now));
+            if let Some(last_sync) = state.last_sync {
+                watch.last_ok = watch.last_ok.max(last_sync);
+            }
+            watch.error = state.sync_error;
+            watch.muted = state.mute.is_muted();
+            if let Some(transition) = watch.update(config.sync_threshold(), now) {
+                alerts.push(Alert {
+                    kind: match transition.recovered {
+                        true => AlertKind::SyncRecovered,
+                        false => AlertKind::SyncStalled,
+                    },
+                    wallet: Some(state.name),
+                    url: backend.url.clone(),
+                    since: transition.since,
+                    duration: transition.duration,
+                    error: watch.error.clone(),
+                });
+            }
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::blockchain::BackendStatus;
+    use crate::wallets::WalletState;
+
+    const THRESHOLD: Duration = Duration::from_secs(10 * 60);
+
+    fn minutes(m: i64) -> chrono::Duration {
+        chrono::Duration::minutes(m)
+    }
+
+    #[test]
+    fn alerts_once_and_recovers_once() {
+        let start = Utc::now();
+        let mut watch = Watch::new(start);
+        assert!(watch.update(THRESHOLD, start + minutes(9)).is_none());
+
+        let outage = watch.update(THRESHOLD, start + minutes(10)).unwrap();
+        assert!(!outage.recovered);
+        assert_eq!(outage.since, start);
+        assert_eq!(outage.duration, minutes(10));
+        assert!(watch.update(THRESHOLD, start + minutes(20)).is_none());
+
+        watch.last_ok = start + minutes(25);
+        let recovery = watch.update(THRESHOLD, start + minutes(25)).unwrap();
+        assert!(recovery.recovered);
+        assert_eq!(recovery.since, start);
+        assert_eq!(recovery.duration, minutes(25));
+        assert!(watch.update(THRESHOLD, start + minutes(26)).is_none());
+    }
+
+    #[test]
+    fn wallet_outages_carry_over_reopened_wallets() {
+        let start = Utc::now();
+        let config: AlertsConfig = toml::from_str("sync_mins = 10").unwrap();
+        let backend = Arc::new(Mutex::new(BackendStatus::default()));
+        let wallet = || {
+            Arc::new(Mutex::new(WalletState {
+                name: "a".to_string(),
+                last_sync: Some(start),
+                ..Default::default()
+            }))
+        };
+        let alerts = Alerts::default();
+        let kinds = |wallets: &[SafeWalletState], now| {
+            alerts
+                .check(&config, wallets, &backend, now)
+                .iter()
+                .map(Alert::kind)
+                .collect::<Vec<_>>()
+        };
+        assert!(kinds(&[wallet()], start).is_empty());
+        assert_eq!(
+            kinds(&[wallet()], start + minutes(10)),
+            [AlertKind::SyncStalled]
+        );
+        assert!(kinds(&[wallet()], start + minutes(20)).is_empty());
+        assert!(kinds(&[], start + minutes(21)).is_empty());
+        assert!(kinds(&[wallet()], start + minutes(21)).is_empty());
+        assert_eq!(
+            kinds(&[wallet()], start + minutes(31)),
+            [AlertKind::SyncStalled]
+        );
+    }
+
+    #[test]
+    fn muted_outages_are_not_alerted() {
+        let start = Utc::now();
+        let mut watch = Watch::new(start);
+        watch.muted = true;
+        assert!(watch.update(THRESHOLD, start + minutes(60)).is_none());
+        watch.muted = false;
+        assert!(watch.update(THRESHOLD, start + minutes(61)).is_some());
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        Path, Query, Request, State,
+    },
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use bdk::{bitcoin::Network, Balance};
+use chrono::{DateTime, Utc};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::TcpListener,
+    select,
+    sync::{broadcast, watch},
+};
+
+use crate::blockchain::{BackendStatus, SafeBackendStatus};
+use crate::message::Message;
+use crate::secret::get_secret;
+use crate::wallets::{SafeWalletState, WalletState};
+
+#[derive(Deserialize, Debug)]
+pub struct ApiConfig {
+    bind: Option<SocketAddr>,
+    token: Option<String>,
+    token_file: Option<PathBuf>,
+}
+
+impl ApiConfig {
+    pub fn bind(&self) -> SocketAddr {
+        self.bind
+            .unwrap_or(SocketAddr::from((Ipv4Addr::LOCALHOST, 8421)))
+    }
+
+    /// Token clients authenticate with
+    pub fn token(&self) -> Result<String> {
+        get_secret("token", self.token.as_deref(), self.token_file.as_deref())
+    }
+}
+
+#[derive(Clone)]
+struct ApiState {
+    token: String,
+    network: Network,
+    wallets: Vec<SafeWalletState>,
+    backend: SafeBackendStatus,
+    events: broadcast::Sender<Message>,
+    /// Closed when the server stops, ending the event streams
+    closing: watch::Receiver<()>,
+}
+
+#[derive(Serialize)]
+struct WalletSummary {
+    name: String,
+    network: String,
+    balance: Balance,
+    receive_address: Option<String>,
+    transactions: usize,
+    last_sync: Option<DateTime<Utc>>,
+    sync_error: Option<String>,
+    muted: bool,
+}
+
+impl WalletSummary {
+    fn new(state: &WalletState, network: Network) -> Self {
+        Self {
+            name: state.name.clone(),
+            network: network.to_string(),
+            balance: state.balance.clone(),
+            receive_address: state.receive_address.clone(),
+            transactions: state.transactions.len(),
+            last_sync: state.last_sync,
+            sync_error: state.sync_error.clone(),
+            muted: state.mute.is_muted(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Confirmation {
+    height: u32,
+    time: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct TransactionSummary {
+    txid: String,
+    /// Received minus sent, in sats
+    net: i64,
+    received: u64,
+    sent: u64,
+    fee: Option<u64>,
+    confirmation: Option<Confirmation>,
+}
+
+#[derive(Serialize)]
+struct Event<'m> {
+    wallet: &'m str,
+    id: &'m str,
+    subject: &'m str,
+    body: &'m str,
+    /// Everything known about the transaction, as available to the templates
+    data: Option<&'m minijinja::Value>,
+}
+
+#[derive(Deserialize)]
+struct TransactionsQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    access_token: Option<String>,
+}
+
+/// Wallets are read from the state published by their syncs, so requests never wait for a sync
+fn find_wallet(state: &ApiState, name: &str) -> Option<SafeWalletState> {
+    state
+        .wallets
+        .iter()
+        .find(|w| w.lock().unwrap().name == name)
+        .cloned()
+}
+
+async fn wallets_handler(State(state): State<ApiState>) -> Json<Vec<WalletSummary>> {
+    Json(
+        state
+            .wallets
+            .iter()
+            .map(|w| WalletSummary::new(&w.lock().unwrap(), state.network))
+            .collect(),
+    )
+}
+
+async fn wallet_handler(State(state): State<ApiState>, Path(name): Path<String>) -> Response {
+    match find_wallet(&state, &name) {
+        Some(wallet) => {
+            Json(WalletSummary::new(&wallet.lock().unwrap(), state.network)).into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Most recent transactions first, unconfirmed ones at the top
+async fn transactions_handler(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+    Query(query): Query<TransactionsQuery>,
+) -> Response {
+    let Some(wallet) = find_wallet(&state, &name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let mut txs = wallet.lock().unwrap().transactions.clone();
+    txs.sort_by_key(|tx| {
+        std::cmp::Reverse(tx.confirmation_time.as_ref().map_or(u32::MAX, |t| t.height))
+    });
+    let txs: Vec<TransactionSummary> = txs
+        .into_iter()
+        .take(query.limit.unwrap_or(50))
+        .map(|tx| TransactionSummary {
+            txid: tx.txid.to_string(),
+            net: tx.received as i64 - tx.sent as i64,
+            received: tx.received,
+            sent: tx.sent,
+            fee: tx.fee,
+            confirmation: tx.confirmation_time.map(|t| Confirmation {
+                height: t.height,
+                time: DateTime::from_timestamp(t.timestamp as i64, 0),
+            }),
+        })
+        .collect();
+    Json(txs).into_response()
+}
+
+async fn backend_handler(State(state): State<ApiState>) -> Json<BackendStatus> {
+    Json(state.backend.lock().unwrap().clone())
+}
+
+async fn events_handler(State(state): State<ApiState>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(|socket| stream_events(socket, state))
+}
+
+/// Sends every transaction message as a JSON text frame, until the client or the server goes away
+async fn stream_events(mut socket: WebSocket, mut state: ApiState) {
+    let mut events = state.events.subscribe();
+    loop {
+        let message = select! {
+            event = events.recv() => match event {
+                Ok(message) => message,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("event stream lagging, skipped {} events", skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            received = socket.recv() => match received {
+                Some(Ok(_)) => continue,
+                _ => break,
+            },
+            _ = state.closing.changed() => break,
+        };
+        let event = Event {
+            wallet: message.source(),
+            id: message.id(),
+            subject: message.subject(),
+            body: message.body(),
+            data: message.context(),
+        };
+        let Ok(json) = serde_json::to_string(&event) else {
+            continue;
+        };
+        if socket.send(WsMessage::Text(json)).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Compares in constant time, so the token can't be guessed from response times
+fn token_matches(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Requires `Authorization: Bearer <token>`, or an `access_token` query parameter since browsers
+/// can't set headers on WebSocket connections
+async fn check_token(
+    State(state): State<ApiState>,
+    Query(query): Query<TokenQuery>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let given = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(query.access_token.as_deref());
+    match given.is_some_and(|given| token_matches(given, &state.token)) {
+        true => next.run(request).await,
+        false => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Read-only HTTP API over the state of the daemon
+pub struct ApiServer {
+    listener: TcpListener,
+    token: String,
+}
+
+impl ApiServer {
+    pub async fn bind(config: &ApiConfig) -> Result<Self> {
+        let token = config.token().context("invalid api token")?;
+        let listener = TcpListener::bind(config.bind())
+            .await
+            .with_context(|| format!("could not listen on '{}'", config.bind()))?;
+        info!("serving the api on 'http://{}'", config.bind());
+        Ok(Self { listener, token })
+    }
+
+    /// Serves requests until dropped
+    pub async fn serve(
+        self,
+        network: Network,
+        wallets: Vec<SafeWalletState>,
+        backend: SafeBackendStatus,
+        events: broadcast::Sender<Message>,
+    ) {
+        let (_closing, closing_receiver) = watch::channel(());
+        let state = ApiState {
+            token: self.token,
+            network,
+            wallets,
+            backend,
+            events,
+            closing: closing_receiver,
+        };
+        let app = Router::new()
+            .route("/api/wallets", get(wallets_handler))
+            .route("/api/wallets/:name", get(wallet_handler))
+            .route("/api/wallets/:name/transactions", get(transactions_handler))
+            .route("/api/backend", get(backend_handler))
+            .route("/api/events", get(events_handler))
+            .layer(middleware::from_fn_with_state(state.clone(), check_token))
+            .with_state(state);
+        if let Err(e) = axum::serve(self.listener, app).await {
+            log::error!("api server failed: {:#}", e);
+        }
+    }
+}
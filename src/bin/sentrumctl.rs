@@ -0,0 +1,241 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use anyhow::{anyhow, bail, Result};
+use bdk::bitcoin::Txid;
+use chrono::{DateTime, Duration, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+
+#[path = "../control/protocol.rs"]
+mod protocol;
+
+use protocol::{MuteStatus, PendingMessage, Request, Response, Status, Target};
+
+/// Controls a running sentrum daemon
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Args {
+    /// Unix socket the daemon listens on, if not the default one
+    #[arg(short, long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum TargetKind {
+    Wallet,
+    Action,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the wallets, with their balance and sync status, and the actions
+    Status,
+    /// Stop notifying about a wallet, or running an action
+    #[command(alias = "pause")]
+    Mute {
+        kind: TargetKind,
+        /// Wallet name or action id
+        name: String,
+        /// Unmute automatically after this long (e.g. 30m, 2h or 1d)
+        #[arg(short, long = "for", value_name = "DURATION", value_parser = parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// Undo a mute
+    #[command(alias = "resume")]
+    Unmute {
+        kind: TargetKind,
+        /// Wallet name or action id
+        name: String,
+    },
+    /// Sync a wallet, or every wallet, right away
+    Resync {
+        /// Wallet name
+        wallet: Option<String>,
+    },
+    /// Send a test notification through every action, or only the selected one
+    Test {
+        /// Action id or type (e.g. "email")
+        #[arg(short, long, value_name = "ID|TYPE")]
+        action: Option<String>,
+        /// Render it from this transaction of a wallet instead of a made up one
+        #[arg(long)]
+        txid: Option<Txid>,
+    },
+    /// List the messages waiting to be delivered (e.g. retries or deferred by quiet hours)
+    Outbox,
+    /// Acknowledge an escalated notification, stopping its escalation
+    Ack {
+        /// Ack id included in the notification
+        id: String,
+    },
+}
+
+/// Durations are a number followed by s, m, h or d, or a number of minutes
+fn parse_duration(s: &str) -> Result<Duration> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => s.split_at(index),
+        None => (s, "m"),
+    };
+    let number: i64 = number
+        .parse()
+        .map_err(|_| anyhow!("invalid duration '{}'", s))?;
+    Ok(match unit {
+        "s" => Duration::seconds(number),
+        "m" => Duration::minutes(number),
+        "h" => Duration::hours(number),
+        "d" => Duration::days(number),
+        _ => bail!("invalid duration unit '{}', expected s, m, h or d", unit),
+    })
+}
+
+fn get_target(kind: TargetKind, name: &str) -> Target {
+    match kind {
+        TargetKind::Wallet => Target::Wallet(name.to_string()),
+        TargetKind::Action => Target::Action(name.to_string()),
+    }
+}
+
+fn get_request(command: &Command) -> Request {
+    match command {
+        Command::Status => Request::Status,
+        Command::Mute {
+            kind,
+            name,
+            duration,
+        } => Request::Mute {
+            target: get_target(*kind, name),
+            until: duration.map(|d| Utc::now() + d),
+        },
+        Command::Unmute { kind, name } => Request::Unmute {
+            target: get_target(*kind, name),
+        },
+        Command::Resync { wallet } => Request::Resync {
+            wallet: wallet.clone(),
+        },
+        Command::Test { action, txid } => Request::Test {
+            action: action.clone(),
+            txid: *txid,
+        },
+        Command::Outbox => Request::Outbox,
+        Command::Ack { id } => Request::Ack { id: id.clone() },
+    }
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+fn format_mute(mute: &MuteStatus) -> String {
+    match mute {
+        MuteStatus::Unmuted => String::new(),
+        MuteStatus::Until(until) => format!(", muted until {}", format_time(until)),
+        MuteStatus::Indefinitely => ", muted".to_string(),
+    }
+}
+
+fn print_status(status: &Status) {
+    println!("wallets:");
+    for wallet in &status.wallets {
+        let sync = match (&wallet.sync_error, &wallet.last_sync) {
+            (Some(e), _) => format!("sync failing: {}", e),
+            (None, Some(last_sync)) => format!("synced at {}", format_time(last_sync)),
+            (None, None) => "not synced yet".to_string(),
+        };
+        println!(
+            "  {}: {} sats, {}{}",
+            wallet.name,
+            wallet.balance,
+            sync,
+            format_mute(&wallet.mute)
+        );
+    }
+    println!("actions:");
+    for action in &status.actions {
+        println!(
+            "  {} ({}): {}{}",
+            action.id,
+            action.kind,
+            if action.registered {
+                "registered"
+            } else {
+                "degraded, retrying registration"
+            },
+            format_mute(&action.mute)
+        );
+    }
+}
+
+fn print_outbox(pending: &[PendingMessage]) {
+    if pending.is_empty() {
+        println!("no pending messages");
+    }
+    for message in pending {
+        let due = match &message.due {
+            Some(due) => format!("due at {}", format_time(due)),
+            None => "waiting for the action to be registered".to_string(),
+        };
+        println!(
+            "[{}][{}][{}] {}, {} failed attempts: {}",
+            message.source, message.id, message.action, due, message.attempts, message.subject
+        );
+    }
+}
+
+#[cfg(unix)]
+async fn request(args: &Args) -> Result<Response> {
+    use anyhow::Context;
+    use tokio::{io::BufReader, net::UnixStream};
+
+    let path = match &args.socket {
+        Some(path) => path.clone(),
+        None => {
+            let paths = protocol::get_socket_paths();
+            match paths.iter().find(|p| p.exists()) {
+                Some(path) => path.clone(),
+                None => bail!(
+                    "sentrum does not seem to be running, no socket found in: {}",
+                    paths
+                        .iter()
+                        .map(|p| format!("'{}'", p.display()))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            }
+        }
+    };
+    let stream = UnixStream::connect(&path)
+        .await
+        .with_context(|| format!("could not connect to '{}'", path.display()))?;
+    let (reader, mut writer) = stream.into_split();
+    protocol::send(&mut writer, &get_request(&args.command)).await?;
+    protocol::receive(&mut BufReader::new(reader)).await
+}
+
+#[cfg(not(unix))]
+async fn request(args: &Args) -> Result<Response> {
+    let _ = get_request(&args.command);
+    bail!("controlling sentrum is only supported on unix")
+}
+
+async fn run(args: &Args) -> Result<()> {
+    match request(args).await? {
+        Response::Status(status) => print_status(&status),
+        Response::Outbox(pending) => print_outbox(&pending),
+        Response::Done(done) => println!("{}", done),
+        Response::Error(e) => bail!(e),
+    }
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(&args).await {
+        eprintln!("error: {:#}", e);
+        exit(1);
+    }
+}
@@ -1,11 +1,14 @@
+use std::sync::{Arc, Mutex};
+
 use anyhow::{Context, Result};
 use bdk::{
     bitcoin::Network,
     blockchain::{ElectrumBlockchain, GetHeight},
     electrum_client::{Client, ConfigBuilder, Socks5Config},
 };
+use chrono::{DateTime, Utc};
 use log::{debug, info, warn};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 fn get_default_electrum_server(network: Network) -> &'static str {
     match network {
@@ -46,6 +49,10 @@ impl ElectrumConfig {
     pub fn socks5(&self) -> Option<Socks5Config> {
         self.socks5.as_ref().map(Socks5Config::new)
     }
+
+    pub fn socks5_url(&self) -> Option<&str> {
+        self.socks5.as_deref()
+    }
 }
 
 pub fn get_blockchain(electrum_cfg: &ElectrumConfig) -> Result<ElectrumBlockchain> {
@@ -59,39 +66,64 @@ pub fn get_blockchain(electrum_cfg: &ElectrumConfig) -> Result<ElectrumBlockchai
     Ok(ElectrumBlockchain::from(client))
 }
 
+/// What is known about the electrum server from polling its height
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct BackendStatus {
+    pub url: String,
+    pub height: Option<u32>,
+    /// Last time the server answered
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Why the server could not be reached the last time it was polled
+    pub error: Option<String>,
+}
+
+pub type SafeBackendStatus = Arc<Mutex<BackendStatus>>;
+
 pub struct BlockchainState {
-    height: Option<u32>,
-    url: String,
+    status: SafeBackendStatus,
     blockchain: ElectrumBlockchain,
 }
 
 impl BlockchainState {
     pub fn new(electrum_cfg: &ElectrumConfig) -> Result<Self> {
         Ok(Self {
-            height: Default::default(),
-            url: String::from(electrum_cfg.url()),
+            status: Arc::new(Mutex::new(BackendStatus {
+                url: String::from(electrum_cfg.url()),
+                ..Default::default()
+            })),
             blockchain: get_blockchain(electrum_cfg)?,
         })
     }
 
+    /// Status kept up to date by `update_height`, shareable with other threads
+    pub fn status(&self) -> SafeBackendStatus {
+        self.status.clone()
+    }
+
     pub fn update_height(&mut self) {
-        match self.blockchain.get_height() {
+        let polled = self.blockchain.get_height();
+        let mut status = self.status.lock().unwrap();
+        match polled {
             Ok(polled_height) => {
-                match self.height {
+                match status.height {
                     Some(h) => {
                         if polled_height != h {
-                            self.height = Some(polled_height);
                             debug!("current block height: {}", polled_height);
                         }
                     }
                     None => {
-                        self.height = Some(polled_height);
-                        info!("connected to '{}'", self.url);
+                        info!("connected to '{}'", status.url);
                         info!("current block height: {}", polled_height);
                     }
                 };
+                status.height = Some(polled_height);
+                status.last_seen = Some(Utc::now());
+                status.error = None;
+            }
+            Err(e) => {
+                warn!("could not reach '{}': {:#}", status.url, e);
+                status.error = Some(format!("{:#}", e));
             }
-            Err(e) => warn!("could not reach '{}': {:#}", self.url, e),
         };
     }
 }
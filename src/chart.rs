@@ -0,0 +1,60 @@
+use anyhow::Result;
+use plotters::prelude::*;
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 300;
+/// Number of horizontal grid lines drawn above the x axis
+const GRID_LINES: u64 = 4;
+
+/// Renders balances over time (unix timestamp, sats) into a PNG step chart. No fonts are bundled,
+/// so the chart has no labels and is meant to be shown next to the digest text.
+pub fn balance_chart_png(points: &[(i64, u64)]) -> Result<Vec<u8>> {
+    let x_min = points.iter().map(|(t, _)| *t).min().unwrap_or_default();
+    let x_max = points
+        .iter()
+        .map(|(t, _)| *t)
+        .max()
+        .unwrap_or_default()
+        .max(x_min + 1);
+    let y_max = points.iter().map(|(_, b)| *b).max().unwrap_or_default();
+    let y_max = (y_max + y_max / 10).max(1);
+
+    // Balances only change at snapshots, so hold each value until the next one
+    let mut steps = Vec::with_capacity(points.len() * 2);
+    for (i, (t, balance)) in points.iter().enumerate() {
+        if i > 0 {
+            steps.push((*t, points[i - 1].1));
+        }
+        steps.push((*t, *balance));
+    }
+
+    let mut pixels = vec![0u8; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut pixels, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(&root)
+            .margin(10)
+            .build_cartesian_2d(x_min..x_max, 0..y_max)?;
+        for i in 0..=GRID_LINES {
+            let y = y_max * i / GRID_LINES;
+            chart.draw_series(LineSeries::new(
+                [(x_min, y), (x_max, y)],
+                RGBColor(220, 220, 220),
+            ))?;
+        }
+        chart.draw_series(
+            AreaSeries::new(steps, 0, RGBColor(247, 147, 26).mix(0.2))
+                .border_style(RGBColor(247, 147, 26).stroke_width(2)),
+        )?;
+        root.present()?;
+    }
+
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, WIDTH, HEIGHT);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+    Ok(png_data)
+}
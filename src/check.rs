@@ -0,0 +1,220 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use bdk::blockchain::GetHeight;
+use chrono::Utc;
+use tokio::time::timeout;
+
+use crate::actions::{get_action, AnyActionConfig};
+use crate::alerts::{example_alert, AlertKind};
+use crate::blockchain::get_blockchain;
+use crate::config::{get_config, Args, Config};
+use crate::digest::example_digest;
+use crate::example::ExampleWallet;
+use crate::message::{Message, MessageParams, TxKind};
+use crate::price::{FiatPrices, PriceFeed};
+use crate::wallets::check_wallet;
+
+/// Results of the checks, printed as they complete
+#[derive(Default)]
+struct Report {
+    passed: usize,
+    failed: usize,
+}
+
+impl Report {
+    /// Records the result of a check, along with what it found out if it passed
+    fn record<T>(&mut self, what: impl Display, result: Result<(T, String)>) -> Option<T> {
+        match result {
+            Ok((value, details)) => {
+                self.passed += 1;
+                if details.is_empty() {
+                    println!("[ok] {}", what);
+                } else {
+                    println!("[ok] {}: {}", what, details);
+                }
+                Some(value)
+            }
+            Err(e) => {
+                self.failed += 1;
+                println!("[error] {}: {:#}", what, e);
+                None
+            }
+        }
+    }
+}
+
+fn check_wallets(config: &Config, report: &mut Report) {
+    let network = config.electrum().network();
+    for wallet_config in config.wallets() {
+        report.record(
+            format_args!("wallet '{}'", wallet_config.name()),
+            check_wallet(wallet_config, network)
+                .map(|address| ((), format!("first address {}", address))),
+        );
+    }
+}
+
+/// Renders every template and command argument, and evaluates every rule, against made up
+/// transactions, digests and alerts
+fn check_templates(config: &Config, report: &mut Report) {
+    let message_config = config.message();
+    let network = config.electrum().network();
+    let currency = config
+        .price()
+        .map(|p| p.currency())
+        .unwrap_or("USD".to_string());
+
+    let mut messages: Vec<Message> = Vec::new();
+    for kind in [
+        TxKind::Incoming,
+        TxKind::Outgoing,
+        TxKind::SelfTransfer,
+        TxKind::Consolidation,
+        TxKind::Coinjoin,
+        TxKind::Coinbase,
+    ] {
+        let wallet = ExampleWallet::new("example", network, kind, true);
+        let params = MessageParams::new(wallet.tx(), &wallet, FiatPrices::example(&currency));
+        let message = message_config.message(&params).and_then(|message| {
+            let context = message.context().cloned().unwrap_or_default();
+            if let Some(critical) = message_config.critical_rule() {
+                message_config
+                    .evaluate(critical, &context)
+                    .map_err(|e| anyhow!("critical rule: {:#}", e))?;
+            }
+            for escalation in config.escalations() {
+                if let Some(when) = escalation.when() {
+                    message_config
+                        .evaluate(when, &context)
+                        .map_err(|e| anyhow!("escalation '{}': {:#}", escalation.name(), e))?;
+                }
+            }
+            // Arguments are rendered leniently since other messages lack transaction parameters,
+            // so unknown parameters are caught here
+            for action_config in config.actions() {
+                if let AnyActionConfig::Command(command_config) = action_config.kind() {
+                    for arg in command_config.args() {
+                        message_config.render(arg, &context).map_err(|e| {
+                            anyhow!("action '{}' argument: {:#}", action_config.id(), e)
+                        })?;
+                    }
+                }
+            }
+            Ok(message)
+        });
+        if let Some(message) = report.record(
+            format_args!("{} transaction message", kind),
+            message.map(|m| (m, String::new())),
+        ) {
+            messages.push(message);
+        }
+    }
+    if !messages.is_empty() {
+        report.record(
+            "summary message",
+            message_config
+                .summary_message(&messages)
+                .map(|_| ((), String::new())),
+        );
+    }
+
+    let now = Utc::now();
+    for digest_config in config.digests() {
+        let (start, end) = digest_config.last_period(now, &message_config.timezone());
+        let digest = example_digest(digest_config, network, start, end);
+        report.record(
+            format_args!("digest '{}' message", digest_config.name()),
+            message_config
+                .digest_message(&digest)
+                .map(|_| ((), String::new())),
+        );
+    }
+
+    if let Some(alerts_config) = config.alerts() {
+        for kind in [
+            AlertKind::BackendUnreachable,
+            AlertKind::BackendRecovered,
+            AlertKind::SyncStalled,
+            AlertKind::SyncRecovered,
+        ] {
+            let alert = example_alert(kind, config.electrum().url());
+            report.record(
+                format_args!("{} alert message", kind),
+                message_config
+                    .alert_message(&alert, alerts_config.critical())
+                    .map(|_| ((), String::new())),
+            );
+        }
+    }
+}
+
+async fn check_backends(config: &Config, report: &mut Report) {
+    let electrum_config = config.electrum();
+    report.record(
+        format_args!("electrum server '{}'", electrum_config.url()),
+        get_blockchain(electrum_config)
+            .and_then(|blockchain| Ok(blockchain.get_height()?))
+            .map(|height| ((), format!("block height {}", height))),
+    );
+
+    if let Some(price_config) = config.price() {
+        let price = match PriceFeed::new(price_config, electrum_config) {
+            Ok(feed) => feed.fetch_price().await,
+            Err(e) => Err(e),
+        };
+        report.record(
+            "price feed",
+            price.map(|price| ((), format!("{:.2} {}", price, price_config.currency()))),
+        );
+    }
+}
+
+async fn check_actions(config: &Config, report: &mut Report) {
+    let message_config = Arc::new(config.message().clone());
+    for action_config in config.actions() {
+        let result = async {
+            let action = get_action(&message_config, action_config, true).await?;
+            timeout(action_config.timeout(), action.check())
+                .await
+                .map_err(|_| anyhow!("timed out"))?
+        };
+        report.record(
+            format_args!("action '{}'", action_config.id()),
+            result.await.map(|_| ((), String::new())),
+        );
+    }
+}
+
+/// Checks the configuration and everything it connects to, without sending any notification
+pub async fn check(args: &Args) -> Result<()> {
+    let mut report = Report::default();
+    let Some(config) = report.record(
+        "configuration",
+        get_config(&args.config()).map(|config| {
+            let path = config.path().display().to_string();
+            (config, path)
+        }),
+    ) else {
+        bail!("the configuration is invalid");
+    };
+    if config.actions().is_empty() {
+        report.record::<()>("actions", Err(anyhow!("no actions configured")));
+    }
+
+    check_wallets(&config, &mut report);
+    check_templates(&config, &mut report);
+    check_backends(&config, &mut report).await;
+    check_actions(&config, &mut report).await;
+
+    if report.failed > 0 {
+        bail!(
+            "{} of {} checks failed",
+            report.failed,
+            report.passed + report.failed
+        );
+    }
+    println!("all {} checks passed", report.passed);
+    Ok(())
+}
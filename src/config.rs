@@ -1,16 +1,33 @@
 use std::{
     env, fs,
+    io::Write,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
-use anyhow::{bail, Context, Result};
-use clap::Parser;
+use anyhow::{anyhow, bail, Context, Result};
+use bdk::{bitcoin::Txid, TransactionDetails};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, Subcommand};
 use const_format::{formatcp, map_ascii_case, Case};
-use log::info;
+use log::{info, warn};
 use serde::Deserialize;
 
+#[cfg(feature = "api")]
+use crate::api::ApiConfig;
+#[cfg(feature = "metrics")]
+use crate::metrics::MetricsConfig;
 use crate::{
-    actions::AnyActionConfig, blockchain::ElectrumConfig, message::MessageConfig,
+    actions::{set_default_ids, ActionConfig},
+    alerts::AlertsConfig,
+    blockchain::ElectrumConfig,
+    digest::DigestConfig,
+    escalation::EscalationConfig,
+    heartbeat::HeartbeatConfig,
+    message::MessageConfig,
+    notifier::BatchingConfig,
+    price::PriceConfig,
+    secret::{has_inline_secrets, is_secret_key},
     wallets::WalletConfig,
 };
 
@@ -19,13 +36,53 @@ use crate::{
 pub struct Args {
     /// Path to toml configuration file
     config: Option<String>,
-    /// Perform configured actions on a test notification
+    /// Perform configured actions on a test notification, rendered from a made up transaction
     #[arg(short, long)]
     test: bool,
+    /// Render the test notification from this transaction of a configured wallet instead
+    #[arg(long, requires = "test")]
+    txid: Option<Txid>,
+    /// Only send the test notification to the action with this id or type (e.g. "email")
+    #[arg(long, value_name = "ID|TYPE", requires = "test")]
+    action: Option<String>,
     /// Notify for every past transaction (careful: if you have a long transaction history, this
     /// can SPAM your configured actions
     #[arg(short, long)]
     notify_past_txs: bool,
+    /// Reload the configuration when the config file changes, besides on SIGHUP
+    #[arg(short, long)]
+    watch_config: bool,
+    /// Notify for past transactions confirmed at or after a block height (e.g. 840000) or a date
+    /// (e.g. 2024-04-20 or 2024-04-20T12:00:00Z)
+    #[arg(long, value_name = "HEIGHT|DATE", conflicts_with = "notify_past_txs")]
+    notify_since: Option<NotifySince>,
+    /// Listen for control requests (e.g. from sentrumctl) on this unix socket instead of the
+    /// default one
+    #[arg(long, value_name = "PATH")]
+    control_socket: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Acknowledge an escalated notification, stopping its escalation
+    Ack {
+        /// Ack id included in the notification
+        id: String,
+    },
+    /// Interactively create a configuration file
+    Init,
+    /// Validate the configuration, render every template and test every connection, without
+    /// sending any notification
+    Check,
+    /// Edit the configuration in a web page served on localhost
+    #[cfg(feature = "ui")]
+    Ui {
+        /// Port to listen on
+        #[arg(short, long, default_value_t = 8420)]
+        port: u16,
+    },
 }
 
 impl Args {
@@ -37,9 +94,69 @@ impl Args {
         self.test
     }
 
+    pub fn txid(&self) -> Option<&Txid> {
+        self.txid.as_ref()
+    }
+
+    pub fn action(&self) -> Option<&str> {
+        self.action.as_deref()
+    }
+
     pub fn notify_past_txs(&self) -> bool {
         self.notify_past_txs
     }
+
+    pub fn watch_config(&self) -> bool {
+        self.watch_config
+    }
+
+    pub fn notify_since(&self) -> Option<&NotifySince> {
+        self.notify_since.as_ref()
+    }
+
+    pub fn control_socket(&self) -> Option<&Path> {
+        self.control_socket.as_deref()
+    }
+
+    pub fn command(&self) -> Option<&Command> {
+        self.command.as_ref()
+    }
+}
+
+/// Point from which past transactions are notified
+#[derive(Debug, Clone)]
+pub enum NotifySince {
+    Height(u32),
+    Date(DateTime<Utc>),
+}
+
+impl NotifySince {
+    /// Whether the transaction was confirmed after this point. Unconfirmed transactions always are.
+    pub fn includes(&self, tx: &TransactionDetails) -> bool {
+        let Some(time) = &tx.confirmation_time else {
+            return true;
+        };
+        match self {
+            NotifySince::Height(height) => time.height >= *height,
+            NotifySince::Date(date) => time.timestamp >= date.timestamp().max(0) as u64,
+        }
+    }
+}
+
+impl FromStr for NotifySince {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(height) = s.parse() {
+            return Ok(NotifySince::Height(height));
+        }
+        if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+            return Ok(NotifySince::Date(date.to_utc()));
+        }
+        NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .map(|date| NotifySince::Date(date.and_time(Default::default()).and_utc()))
+            .map_err(|_| anyhow!("expected a block height or a date, got '{}'", s))
+    }
 }
 
 fn get_config_filename() -> &'static str {
@@ -71,7 +188,15 @@ fn get_system_config_path() -> PathBuf {
     get_config_path_impl(&systemd_directories::config_dir().unwrap_or(PathBuf::from("/etc")))
 }
 
-fn get_config_path(maybe_arg_config: &Option<&str>) -> Result<PathBuf> {
+/// Where the configuration is looked for, in order of priority
+pub fn get_config_search_paths() -> Vec<PathBuf> {
+    let mut paths = vec![get_cwd_config_path()];
+    paths.extend(get_user_config_path());
+    paths.push(get_system_config_path());
+    paths
+}
+
+pub fn get_config_path(maybe_arg_config: &Option<&str>) -> Result<PathBuf> {
     if let Some(arg_path) = maybe_arg_config {
         return Ok(PathBuf::from(arg_path));
     }
@@ -91,8 +216,9 @@ fn get_config_path(maybe_arg_config: &Option<&str>) -> Result<PathBuf> {
         }
     }
 
+    // Also used when configuring through environment variables only, for its drop-in directory
     let system_config_path = get_system_config_path();
-    if system_config_path.try_exists().is_ok_and(|x| x) {
+    if system_config_path.try_exists().is_ok_and(|x| x) || has_env_overrides() {
         return Ok(system_config_path);
     }
 
@@ -113,7 +239,24 @@ pub struct Config {
     #[serde(default)]
     message: MessageConfig,
     #[serde(default)]
-    actions: Vec<AnyActionConfig>,
+    actions: Vec<ActionConfig>,
+    price: Option<PriceConfig>,
+    #[serde(default)]
+    digests: Vec<DigestConfig>,
+    batching: Option<BatchingConfig>,
+    #[serde(default)]
+    escalations: Vec<EscalationConfig>,
+    heartbeat: Option<HeartbeatConfig>,
+    alerts: Option<AlertsConfig>,
+    #[cfg(feature = "api")]
+    api: Option<ApiConfig>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<MetricsConfig>,
+    #[serde(skip)]
+    path: PathBuf,
+    /// Parsed but not deserialized configuration, to find out what changed on reloads
+    #[serde(skip)]
+    source: toml::Table,
 }
 
 impl Config {
@@ -129,16 +272,441 @@ impl Config {
         &self.message
     }
 
-    pub fn actions(&self) -> &[AnyActionConfig] {
+    pub fn actions(&self) -> &[ActionConfig] {
         &self.actions
     }
+
+    pub fn price(&self) -> Option<&PriceConfig> {
+        self.price.as_ref()
+    }
+
+    pub fn digests(&self) -> &[DigestConfig] {
+        &self.digests
+    }
+
+    pub fn batching(&self) -> Option<&BatchingConfig> {
+        self.batching.as_ref()
+    }
+
+    pub fn escalations(&self) -> &[EscalationConfig] {
+        &self.escalations
+    }
+
+    pub fn heartbeat(&self) -> Option<&HeartbeatConfig> {
+        self.heartbeat.as_ref()
+    }
+
+    pub fn alerts(&self) -> Option<&AlertsConfig> {
+        self.alerts.as_ref()
+    }
+
+    #[cfg(feature = "api")]
+    pub fn api(&self) -> Option<&ApiConfig> {
+        self.api.as_ref()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Option<&MetricsConfig> {
+        self.metrics.as_ref()
+    }
+
+    /// Path the configuration was read from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether a top level section (e.g. "message") differs from the one in another configuration
+    pub fn section_changed(&self, other: &Config, section: &str) -> bool {
+        self.source.get(section) != other.source.get(section)
+    }
+
+    fn array_entry(&self, section: &str, index: Option<usize>) -> Option<&toml::Value> {
+        self.source.get(section)?.as_array()?.get(index?)
+    }
+
+    fn action_source(&self, id: &str) -> Option<&toml::Value> {
+        let index = self.actions.iter().position(|a| a.id() == id);
+        self.array_entry("actions", index)
+    }
+
+    fn wallet_source(&self, name: &str) -> Option<&toml::Value> {
+        let index = self.wallets.iter().position(|w| w.name() == name);
+        self.array_entry("wallets", index)
+    }
+
+    /// Whether the action needs to be set up again to apply this configuration
+    pub fn action_changed(&self, other: &Config, id: &str) -> bool {
+        self.section_changed(other, "message") || self.action_source(id) != other.action_source(id)
+    }
+
+    /// Whether the wallet needs to be set up again to apply this configuration
+    pub fn wallet_changed(&self, other: &Config, name: &str) -> bool {
+        self.section_changed(other, "electrum")
+            || self.wallet_source(name) != other.wallet_source(name)
+    }
 }
 
+/// Warns when secrets in the config file can be read by other users
+fn warn_if_readable(path: &Path) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o044 != 0) {
+            warn!(
+                "'{}' contains secrets but is readable by other users, restrict its permissions (e.g. chmod 600) or move the secrets to files or environment variables",
+                path.display()
+            );
+        }
+    }
+}
+
+/// Directory next to the config file whose `*.toml` files are merged into it, in name order
+pub fn get_drop_in_dir(config_path: &Path) -> PathBuf {
+    config_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(formatcp!("{}.d", env!("CARGO_PKG_NAME")))
+}
+
+/// Files merged into the config file, in the order they are merged
+fn get_drop_in_files(config_path: &Path) -> Result<Vec<PathBuf>> {
+    let dir = get_drop_in_dir(config_path);
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Ok(Vec::new());
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("could not read directory '{}'", dir.display()))?
+            .path();
+        if path.extension().is_some_and(|e| e == "toml") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub fn read_config_file(path: &Path) -> Result<toml::Table> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("could not read config file '{}'", path.display()))?;
+    let table: toml::Table = toml::from_str(&content)
+        .with_context(|| format!("could not parse config file '{}'", path.display()))?;
+    if has_inline_secrets(&toml::Value::Table(table.clone())) {
+        warn_if_readable(path);
+    }
+    Ok(table)
+}
+
+/// Merges tables key by key and appends arrays (e.g. wallets or actions), other values are
+/// replaced
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge(base, overlay),
+            (Some(toml::Value::Array(base)), toml::Value::Array(overlay)) => base.extend(overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn get_env_override_prefix() -> &'static str {
+    formatcp!("{}__", map_ascii_case!(Case::Upper, env!("CARGO_PKG_NAME")))
+}
+
+/// Values are parsed as TOML (e.g. `true`, `42` or `["a", "b"]`), falling back to a string.
+/// Settings expected to be strings only take a string, so that e.g. a numeric password stays
+/// as written.
+fn parse_env_value(value: &str, string: bool) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut t| t.remove("value"))
+        .filter(|v| !string || v.is_str())
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+/// Value at a path of keys, where numbers index into arrays
+fn get_path<'a>(current: &'a toml::Value, keys: &[String]) -> Option<&'a toml::Value> {
+    keys.iter().try_fold(current, |current, key| match current {
+        toml::Value::Array(array) => array.get(key.parse::<usize>().ok()?),
+        _ => current.get(key.as_str()),
+    })
+}
+
+/// Sets the value at a path of keys, where numbers index into arrays (e.g. `actions.0.topic`).
+/// Missing tables are created, as well as array entries right after the last one.
+pub fn set_path(current: &mut toml::Value, keys: &[String], value: toml::Value) -> Result<()> {
+    let Some((key, rest)) = keys.split_first() else {
+        *current = value;
+        return Ok(());
+    };
+    let empty = || match rest.first().map(|k| k.parse::<usize>()) {
+        Some(Ok(_)) => toml::Value::Array(Vec::new()),
+        _ => toml::Value::Table(Default::default()),
+    };
+    let next = match current {
+        toml::Value::Table(table) => table.entry(key.clone()).or_insert_with(empty),
+        toml::Value::Array(array) => {
+            let index: usize = key
+                .parse()
+                .with_context(|| format!("'{}' is not an array index", key))?;
+            if index == array.len() {
+                array.push(empty());
+            }
+            array
+                .get_mut(index)
+                .with_context(|| format!("array index {} is out of bounds", index))?
+        }
+        other => bail!("cannot set '{}' in a {}", key, other.type_str()),
+    };
+    set_path(next, rest, value)
+}
+
+/// Overrides any setting with `SENTRUM__<SECTION>__<KEY>` environment variables, e.g.
+/// `SENTRUM__ELECTRUM__URL` or `SENTRUM__ACTIONS__0__BOT_TOKEN`. Secrets and settings the file
+/// already sets to a string are taken as strings.
+fn apply_env_overrides(
+    source: toml::Table,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<toml::Table> {
+    let mut source = toml::Value::Table(source);
+    let mut overrides: Vec<(Vec<String>, String, String)> = vars
+        .filter(|(name, _)| name.starts_with(get_env_override_prefix()))
+        .map(|(name, value)| {
+            let keys = name[get_env_override_prefix().len()..]
+                .split("__")
+                .map(str::to_lowercase)
+                .collect();
+            (keys, name, value)
+        })
+        .collect();
+    // Array entries can only be appended, so they are set in the order of their index
+    overrides.sort_by_cached_key(|(keys, _, _)| {
+        keys.iter()
+            .map(|key| (key.parse::<usize>().ok(), key.clone()))
+            .collect::<Vec<_>>()
+    });
+    for (keys, name, value) in overrides {
+        let string = keys.last().is_some_and(|key| is_secret_key(key))
+            || get_path(&source, &keys).is_some_and(toml::Value::is_str);
+        set_path(&mut source, &keys, parse_env_value(&value, string))
+            .with_context(|| format!("invalid environment variable '{}'", name))?;
+    }
+    Ok(source.try_into()?)
+}
+
+fn has_env_overrides() -> bool {
+    env::vars().any(|(name, _)| name.starts_with(get_env_override_prefix()))
+}
+
+/// Reads the config file, merges the drop-in files into it and applies the environment overrides
 pub fn get_config(maybe_arg_config: &Option<&str>) -> Result<Config> {
     let config_path = get_config_path(maybe_arg_config)?;
-    info!("reading configuration from '{}'", config_path.display());
-    let config_content = fs::read_to_string(&config_path)
-        .with_context(|| format!("could not read config file '{}'", config_path.display()))?;
-    toml::from_str(&config_content)
-        .with_context(|| format!("could not parse config file '{}'", config_path.display(),))
+    let source = if config_path.exists() || !has_env_overrides() {
+        info!("reading configuration from '{}'", config_path.display());
+        read_config_file(&config_path)?
+    } else {
+        info!(
+            "no config file at '{}', configuring through environment variables",
+            config_path.display()
+        );
+        toml::Table::new()
+    };
+    let source = apply_layers(&config_path, source)?;
+    parse_config(source, config_path)
+}
+
+/// Merges the drop-in files next to the config file into its contents and applies the
+/// environment overrides
+pub fn apply_layers(config_path: &Path, mut source: toml::Table) -> Result<toml::Table> {
+    for path in get_drop_in_files(config_path)? {
+        info!("merging configuration from '{}'", path.display());
+        merge(&mut source, read_config_file(&path)?);
+    }
+    apply_env_overrides(source, env::vars())
+}
+
+/// Deserializes and validates a configuration read from `path`
+pub fn parse_config(source: toml::Table, path: PathBuf) -> Result<Config> {
+    let mut config =
+        Config::deserialize(toml::Value::Table(source.clone())).context("invalid configuration")?;
+    config.source = source;
+    config
+        .message
+        .load()
+        .context("could not load message templates")?;
+    set_default_ids(&mut config.actions)?;
+    for (index, action) in config.actions.iter().enumerate() {
+        if config.actions[..index]
+            .iter()
+            .any(|a| a.id() == action.id())
+        {
+            bail!("duplicate action id '{}'", action.id());
+        }
+    }
+    let action_ids = config.actions.iter().map(|a| a.id()).collect::<Vec<_>>();
+    for escalation in &config.escalations {
+        escalation
+            .check(&config.message, &action_ids)
+            .with_context(|| format!("invalid escalation '{}'", escalation.name()))?;
+    }
+    config.path = path;
+    Ok(config)
+}
+
+/// Writes a config file, only readable by its owner since it might contain secrets
+pub fn write_config_file(path: &Path, content: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("could not create directory '{}'", dir.display()))?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .with_context(|| format!("could not write config file '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(content: &str) -> toml::Table {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn notify_since_parses_heights_and_dates() {
+        assert!(matches!(
+            "840000".parse::<NotifySince>().unwrap(),
+            NotifySince::Height(840000)
+        ));
+        let NotifySince::Date(date) = "2024-04-20".parse().unwrap() else {
+            panic!("expected a date");
+        };
+        assert_eq!(date.to_rfc3339(), "2024-04-20T00:00:00+00:00");
+        let NotifySince::Date(date) = "2024-04-20T12:30:00+02:00".parse().unwrap() else {
+            panic!("expected a date");
+        };
+        assert_eq!(date.to_rfc3339(), "2024-04-20T10:30:00+00:00");
+        assert!("last tuesday".parse::<NotifySince>().is_err());
+        assert!("-1".parse::<NotifySince>().is_err());
+    }
+
+    #[test]
+    fn merge_combines_tables_and_appends_arrays() {
+        let mut base = table(
+            "[electrum]\nurl = 'a'\nnetwork = 'bitcoin'\n[[actions]]\ntype = 'terminal_print'",
+        );
+        merge(
+            &mut base,
+            table("[electrum]\nurl = 'b'\n[[actions]]\ntype = 'desktop_notification'"),
+        );
+        assert_eq!(
+            base,
+            table(
+                "[electrum]\nurl = 'b'\nnetwork = 'bitcoin'\n\
+                 [[actions]]\ntype = 'terminal_print'\n\
+                 [[actions]]\ntype = 'desktop_notification'"
+            )
+        );
+    }
+
+    #[test]
+    fn set_path_creates_tables_and_array_entries() {
+        let mut value = toml::Value::Table(table("[[actions]]\ntype = 'ntfy'"));
+        let keys = |path: &str| path.split('.').map(str::to_string).collect::<Vec<_>>();
+        set_path(&mut value, &keys("actions.0.topic"), "a".into()).unwrap();
+        set_path(&mut value, &keys("actions.1.type"), "command".into()).unwrap();
+        set_path(&mut value, &keys("electrum.url"), "b".into()).unwrap();
+        assert_eq!(
+            value,
+            toml::Value::Table(table(
+                "[electrum]\nurl = 'b'\n\
+                 [[actions]]\ntype = 'ntfy'\ntopic = 'a'\n\
+                 [[actions]]\ntype = 'command'"
+            ))
+        );
+        assert!(set_path(&mut value, &keys("actions.3.type"), "a".into()).is_err());
+        assert!(set_path(&mut value, &keys("actions.x"), "a".into()).is_err());
+        assert!(set_path(&mut value, &keys("electrum.url.x"), "a".into()).is_err());
+    }
+
+    #[test]
+    fn env_overrides_keep_strings_as_strings() {
+        let source = table("[electrum]\nurl = 'a'\n[[actions]]\ntype = 'ntfy'\ntopic = 'alerts'");
+        let vars = [
+            ("SENTRUM__ELECTRUM__URL", "\"ssl://b:50002\""),
+            ("SENTRUM__ACTIONS__0__TOPIC", "12345"),
+            ("SENTRUM__ACTIONS__0__PASSWORD", "0042"),
+            ("SENTRUM__ACTIONS__0__PRIORITY", "4"),
+            ("SENTRUM__ACTIONS__1__TYPE", "command"),
+            ("SENTRUM__ACTIONS__1__CMD", "\"123\""),
+            ("SENTRUM__ACTIONS__1__ARGS", "[\"a\", 1]"),
+            ("OTHER__ELECTRUM__URL", "c"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        assert_eq!(
+            apply_env_overrides(source, vars.into_iter()).unwrap(),
+            table(
+                "[electrum]\nurl = 'ssl://b:50002'\n\
+                 [[actions]]\ntype = 'ntfy'\ntopic = '12345'\npassword = '0042'\npriority = 4\n\
+                 [[actions]]\ntype = 'command'\ncmd = '123'\nargs = ['a', 1]"
+            )
+        );
+    }
+
+    #[test]
+    fn env_overrides_fill_arrays_in_index_order() {
+        let source = table("[[actions]]\ntype = 'ntfy'");
+        let vars = (1..=10)
+            .rev()
+            .flat_map(|i| {
+                [
+                    (
+                        format!("SENTRUM__ACTIONS__{}__TYPE", i),
+                        "command".to_string(),
+                    ),
+                    (format!("SENTRUM__ACTIONS__{}__CMD", i), format!("cmd{}", i)),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let config = apply_env_overrides(source, vars.into_iter()).unwrap();
+        let actions = config["actions"].as_array().unwrap();
+        assert_eq!(actions.len(), 11);
+        assert_eq!(actions[2]["cmd"].as_str(), Some("cmd2"));
+        assert_eq!(actions[10]["cmd"].as_str(), Some("cmd10"));
+    }
+
+    #[test]
+    fn actions_are_identified_by_their_type_unless_ambiguous() {
+        let parse = |actions: &str| {
+            let source = table(&format!("wallets = []\n{}", actions));
+            parse_config(source, PathBuf::new())
+        };
+        let config = parse(
+            "[[actions]]\ntype = 'terminal_print'\n\
+             [[actions]]\ntype = 'command'\ncmd = 'a'\n\
+             [[actions]]\ntype = 'command'\ncmd = 'b'\nid = 'b'",
+        )
+        .unwrap();
+        let ids = config.actions().iter().map(|a| a.id()).collect::<Vec<_>>();
+        assert_eq!(ids, ["terminal_print", "command", "b"]);
+        assert!(parse(
+            "[[actions]]\ntype = 'command'\ncmd = 'a'\n\
+             [[actions]]\ntype = 'command'\ncmd = 'b'"
+        )
+        .is_err());
+    }
 }
@@ -0,0 +1,425 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use bdk::bitcoin::Txid;
+use chrono::Utc;
+use log::{info, warn};
+
+use crate::actions::{run_actions, ConfiguredAction};
+use crate::config::Config;
+use crate::digest::BalanceHistory;
+use crate::notifier::Notifier;
+use crate::price::PriceFeed;
+use crate::wallets::{SafeWalletInfo, SafeWalletState};
+
+pub mod protocol;
+
+use self::protocol::{ActionStatus, MuteStatus, PendingMessage, Status, Target, WalletStatus};
+pub use self::protocol::{Request, Response};
+
+/// Whether a wallet or action is muted, changed through the control socket
+#[derive(Default, Debug)]
+pub struct Mute(Mutex<MuteStatus>);
+
+impl Mute {
+    pub fn set(&self, status: MuteStatus) {
+        *self.0.lock().unwrap() = status;
+    }
+
+    /// Expired mutes are lifted
+    pub fn status(&self) -> MuteStatus {
+        let mut status = self.0.lock().unwrap();
+        if matches!(*status, MuteStatus::Until(until) if until <= Utc::now()) {
+            *status = MuteStatus::Unmuted;
+        }
+        *status
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.status() != MuteStatus::Unmuted
+    }
+}
+
+/// What the running daemon exposes to control requests
+pub struct Daemon<'d, 'a> {
+    pub config: &'d Config,
+    pub wallets: &'d [SafeWalletInfo],
+    /// What the syncs last published for each wallet, in the same order, so that requests
+    /// don't wait for a sync in progress
+    pub states: &'d [SafeWalletState],
+    pub actions: &'d [ConfiguredAction],
+    pub notifier: &'d Notifier<'d, 'a>,
+    pub price_feed: Option<&'d PriceFeed>,
+    pub balance_history: &'d BalanceHistory,
+}
+
+impl Daemon<'_, '_> {
+    fn status(&self) -> Status {
+        let wallets = self
+            .states
+            .iter()
+            .map(|state| {
+                let state = state.lock().unwrap();
+                WalletStatus {
+                    name: state.name.clone(),
+                    balance: state.balance.get_total(),
+                    last_sync: state.last_sync,
+                    sync_error: state.sync_error.clone(),
+                    mute: state.mute.status(),
+                }
+            })
+            .collect();
+        let actions = self
+            .actions
+            .iter()
+            .map(|action| ActionStatus {
+                id: action.id().to_string(),
+                kind: action.kind(),
+                registered: action.is_registered(),
+                mute: action.mute().status(),
+            })
+            .collect();
+        Status { wallets, actions }
+    }
+
+    fn find_mute(&self, target: &Target, f: impl FnOnce(&Mute)) -> Result<(), String> {
+        match target {
+            Target::Wallet(name) => {
+                let mute = self
+                    .states
+                    .iter()
+                    .map(|state| state.lock().unwrap())
+                    .find(|state| state.name == *name)
+                    .map(|state| state.mute.clone())
+                    .ok_or(format!("no wallet named '{}'", name))?;
+                f(&mute);
+            }
+            Target::Action(id) => {
+                let action = self
+                    .actions
+                    .iter()
+                    .find(|a| a.id() == id)
+                    .ok_or(format!("no action with id '{}'", id))?;
+                f(action.mute());
+            }
+        }
+        Ok(())
+    }
+
+    fn set_mute(&self, target: &Target, status: MuteStatus) -> Response {
+        match self.find_mute(target, |mute| mute.set(status)) {
+            Ok(()) => {
+                let done = match status {
+                    MuteStatus::Unmuted => format!("unmuted {}", target),
+                    MuteStatus::Until(until) => format!(
+                        "muted {} until {}",
+                        target,
+                        until.format("%Y-%m-%d %H:%M:%S UTC")
+                    ),
+                    MuteStatus::Indefinitely => format!("muted {}", target),
+                };
+                info!("{}", done);
+                Response::Done(done)
+            }
+            Err(e) => Response::Error(e),
+        }
+    }
+
+    /// Syncs the wallets, notifying their new transactions as usual
+    async fn resync(&self, name: Option<&str>) -> Response {
+        let wallets = self
+            .wallets
+            .iter()
+            .zip(self.states)
+            .filter(|(_, state)| name.is_none_or(|name| state.lock().unwrap().name == name))
+            .collect::<Vec<_>>();
+        if let (Some(name), true) = (name, wallets.is_empty()) {
+            return Response::Error(format!("no wallet named '{}'", name));
+        }
+        let mut failed = Vec::new();
+        for (wallet_info, state) in wallets {
+            info!("[{}] resync requested", state.lock().unwrap().name);
+            crate::get_and_handle_new_txs(
+                wallet_info,
+                Some(self.notifier),
+                None,
+                self.price_feed,
+                self.balance_history,
+            )
+            .await;
+            let state = state.lock().unwrap();
+            if let Some(e) = &state.sync_error {
+                failed.push(format!("[{}] {}", state.name, e));
+            }
+        }
+        match failed.is_empty() {
+            true => Response::Done("synced".to_string()),
+            false => Response::Error(format!("could not sync: {}", failed.join(", "))),
+        }
+    }
+
+    async fn test(&self, id_or_type: Option<&str>, txid: Option<&Txid>) -> Response {
+        let actions = self
+            .actions
+            .iter()
+            .filter(|a| id_or_type.is_none_or(|id_or_type| a.matches(id_or_type)))
+            .collect::<Vec<_>>();
+        if let (Some(id_or_type), true) = (id_or_type, actions.is_empty()) {
+            return Response::Error(format!("no action with id or type '{}'", id_or_type));
+        }
+        let message = match txid {
+            Some(txid) => {
+                crate::get_tx_message(self.config, self.wallets, txid, self.price_feed).await
+            }
+            None => crate::get_example_message(self.config, self.price_feed).await,
+        };
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => return Response::Error(format!("{:#}", e)),
+        };
+        let muted = actions.iter().filter(|a| a.mute().is_muted()).count();
+        info!("running test actions");
+        run_actions(&actions, message).await;
+        let mut done = format!(
+            "sent a test notification to {} actions",
+            actions.len() - muted
+        );
+        if muted > 0 {
+            done.push_str(&format!(", skipped {} muted ones", muted));
+        }
+        Response::Done(done)
+    }
+
+    fn outbox(&self) -> Vec<PendingMessage> {
+        let mut pending = Vec::new();
+        for action in self.actions {
+            for message in action.backlog() {
+                pending.push(PendingMessage {
+                    action: action.id().to_string(),
+                    source: message.source().to_string(),
+                    id: message.id().to_string(),
+                    subject: message.subject().to_string(),
+                    due: None,
+                    attempts: 0,
+                });
+            }
+            for entry in action.pending() {
+                pending.push(PendingMessage {
+                    action: action.id().to_string(),
+                    source: entry.message().source().to_string(),
+                    id: entry.message().id().to_string(),
+                    subject: entry.message().subject().to_string(),
+                    due: Some(entry.due()),
+                    attempts: entry.attempts(),
+                });
+            }
+        }
+        pending
+    }
+
+    async fn handle(&self, request: Request) -> Response {
+        match request {
+            Request::Status => Response::Status(self.status()),
+            Request::Mute { target, until } => self.set_mute(
+                &target,
+                until.map_or(MuteStatus::Indefinitely, MuteStatus::Until),
+            ),
+            Request::Unmute { target } => self.set_mute(&target, MuteStatus::Unmuted),
+            Request::Resync { wallet } => self.resync(wallet.as_deref()).await,
+            Request::Test { action, txid } => self.test(action.as_deref(), txid.as_ref()).await,
+            Request::Outbox => Response::Outbox(self.outbox()),
+            Request::Ack { id } => match self.notifier.acknowledge(&id, "control socket") {
+                true => Response::Done(format!("acknowledged '{}'", id)),
+                false => Response::Error(format!("no escalation with ack id '{}'", id)),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Target::Wallet(name) => write!(f, "wallet '{}'", name),
+            Target::Action(id) => write!(f, "action '{}'", id),
+        }
+    }
+}
+
+/// Unix socket the daemon answers control requests on, e.g. from `sentrumctl`
+pub struct ControlSocket {
+    path: PathBuf,
+    #[cfg(unix)]
+    listener: tokio::net::UnixListener,
+}
+
+impl ControlSocket {
+    /// Listens on the given path or on the first default one that works, in the order `sentrumctl`
+    /// looks for them. A leftover socket from a previous run is replaced, unless another daemon is
+    /// still listening on it.
+    #[cfg(unix)]
+    pub async fn bind(path: Option<PathBuf>) -> Result<Self> {
+        use anyhow::bail;
+        use tokio::net::UnixStream;
+
+        let paths = path.map_or_else(protocol::get_socket_paths, |path| vec![path]);
+        let mut errors = Vec::new();
+        for path in paths {
+            // Clients use the first socket they find, so another daemon must not be shadowed
+            if path.exists() && UnixStream::connect(&path).await.is_ok() {
+                bail!("another daemon is listening on '{}'", path.display());
+            }
+            match Self::bind_path(&path) {
+                Ok(listener) => {
+                    info!("listening for control requests on '{}'", path.display());
+                    return Ok(Self { path, listener });
+                }
+                Err(e) => errors.push(format!("{:#}", e)),
+            }
+        }
+        bail!(
+            "could not listen for control requests: {}",
+            errors.join(", ")
+        )
+    }
+
+    #[cfg(unix)]
+    fn bind_path(path: &Path) -> Result<tokio::net::UnixListener> {
+        use anyhow::Context;
+        use std::{
+            fs,
+            os::unix::fs::{DirBuilderExt, PermissionsExt},
+            process,
+        };
+        use tokio::net::UnixListener;
+
+        if path.exists() {
+            fs::remove_file(path)
+                .with_context(|| format!("could not remove stale socket '{}'", path.display()))?;
+        }
+        let dir = path.parent().unwrap_or(Path::new("."));
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .with_context(|| format!("could not create directory '{}'", dir.display()))?;
+        // Only the user running the daemon can control it. The socket is bound in a private
+        // directory and moved into place once restricted, so that nobody else can connect before.
+        let private_dir = dir.join(format!(".{}-{}", env!("CARGO_PKG_NAME"), process::id()));
+        fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&private_dir)
+            .with_context(|| format!("could not create directory '{}'", private_dir.display()))?;
+        let private_path = private_dir.join(path.file_name().context("invalid socket path")?);
+        let bound = UnixListener::bind(&private_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|listener| {
+                fs::set_permissions(&private_path, fs::Permissions::from_mode(0o600))?;
+                fs::rename(&private_path, path)?;
+                Ok(listener)
+            });
+        let _ = fs::remove_file(&private_path);
+        let _ = fs::remove_dir(&private_dir);
+        bound.with_context(|| format!("could not listen on '{}'", path.display()))
+    }
+
+    #[cfg(not(unix))]
+    pub async fn bind(_path: Option<PathBuf>) -> Result<Self> {
+        anyhow::bail!("the control socket is only supported on unix")
+    }
+
+    #[cfg(unix)]
+    async fn serve_connection(
+        &self,
+        stream: tokio::net::UnixStream,
+        daemon: &Daemon<'_, '_>,
+    ) -> Result<()> {
+        use std::time::Duration;
+        use tokio::time::timeout;
+
+        /// How long a client has to send its request once connected
+        const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+        let (reader, mut writer) = stream.into_split();
+        let request = timeout(
+            REQUEST_TIMEOUT,
+            protocol::receive(&mut tokio::io::BufReader::new(reader)),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out waiting for the request"))??;
+        let response = daemon.handle(request).await;
+        protocol::send(&mut writer, &response).await
+    }
+
+    /// Answers control requests as they come, so that a slow client doesn't hold up the others
+    pub async fn serve(&self, daemon: Daemon<'_, '_>) {
+        #[cfg(unix)]
+        {
+            use futures::stream::{FuturesUnordered, StreamExt};
+
+            let mut connections = FuturesUnordered::new();
+            loop {
+                tokio::select! {
+                    accepted = self.listener.accept() => match accepted {
+                        Ok((stream, _)) => connections.push(self.serve_connection(stream, &daemon)),
+                        Err(e) => warn!(
+                            "cannot accept control connection on '{}': {:#}",
+                            self.path.display(),
+                            e
+                        ),
+                    },
+                    Some(result) = connections.next() => {
+                        if let Err(e) = result {
+                            warn!("control request failed: {:#}", e);
+                        }
+                    }
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = (&self.path, daemon);
+    }
+}
+
+/// Sends a request to the daemon listening on the given path or on a default one, if any
+#[cfg(unix)]
+pub async fn request(path: Option<&Path>, request: &Request) -> Result<Option<Response>> {
+    use tokio::{io::BufReader, net::UnixStream};
+
+    let paths = path.map_or_else(protocol::get_socket_paths, |path| vec![path.to_path_buf()]);
+    for path in paths.iter().filter(|p| p.exists()) {
+        let Ok(stream) = UnixStream::connect(path).await else {
+            continue;
+        };
+        let (reader, mut writer) = stream.into_split();
+        protocol::send(&mut writer, request).await?;
+        return protocol::receive(&mut BufReader::new(reader))
+            .await
+            .map(Some);
+    }
+    Ok(None)
+}
+
+#[cfg(not(unix))]
+pub async fn request(_path: Option<&Path>, _request: &Request) -> Result<Option<Response>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expired_mutes_are_lifted() {
+        let mute = Mute::default();
+        assert!(!mute.is_muted());
+        mute.set(MuteStatus::Until(Utc::now() + chrono::Duration::minutes(5)));
+        assert!(mute.is_muted());
+        mute.set(MuteStatus::Until(Utc::now() - chrono::Duration::seconds(1)));
+        assert_eq!(mute.status(), MuteStatus::Unmuted);
+        mute.set(MuteStatus::Indefinitely);
+        assert_eq!(mute.status(), MuteStatus::Indefinitely);
+    }
+}
@@ -0,0 +1,143 @@
+use std::{env, path::PathBuf};
+
+use anyhow::{Context, Result};
+use bdk::bitcoin::Txid;
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+const SOCKET_FILENAME: &str = "control.sock";
+
+/// Where the daemon listens for control requests, in order of preference: the runtime directory
+/// systemd created for the service (see `RuntimeDirectory=`), the user's runtime directory and the
+/// cache directory
+pub fn get_socket_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(dir) = env::var_os("RUNTIME_DIRECTORY") {
+        paths.push(PathBuf::from(dir).join(SOCKET_FILENAME));
+    }
+    if let Some(dir) = dirs::runtime_dir() {
+        paths.push(dir.join(env!("CARGO_PKG_NAME")).join(SOCKET_FILENAME));
+    }
+    paths.push(
+        PathBuf::from("/run")
+            .join(env!("CARGO_PKG_NAME"))
+            .join(SOCKET_FILENAME),
+    );
+    if let Some(dir) = dirs::cache_dir() {
+        paths.push(dir.join(env!("CARGO_PKG_NAME")).join(SOCKET_FILENAME));
+    }
+    paths
+}
+
+/// Wallet or action a request applies to
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    Wallet(String),
+    Action(String),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "command")]
+pub enum Request {
+    Status,
+    /// Stops notifying about a wallet, or running an action, until the given time or until
+    /// unmuted
+    Mute {
+        target: Target,
+        until: Option<DateTime<Utc>>,
+    },
+    Unmute {
+        target: Target,
+    },
+    /// Syncs a wallet, or every wallet, right away
+    Resync {
+        wallet: Option<String>,
+    },
+    /// Sends a test notification to an action with this id or type, or to every action,
+    /// rendered from a transaction of a wallet or from a made up one
+    Test {
+        action: Option<String>,
+        #[serde(default)]
+        txid: Option<Txid>,
+    },
+    Outbox,
+    /// Acknowledges an escalated message, stopping its escalation
+    Ack {
+        id: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MuteStatus {
+    #[default]
+    Unmuted,
+    Until(DateTime<Utc>),
+    Indefinitely,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WalletStatus {
+    pub name: String,
+    /// Total balance in sats
+    pub balance: u64,
+    pub last_sync: Option<DateTime<Utc>>,
+    /// Error of the last sync, if it failed
+    pub sync_error: Option<String>,
+    pub mute: MuteStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ActionStatus {
+    pub id: String,
+    pub kind: String,
+    pub registered: bool,
+    pub mute: MuteStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Status {
+    pub wallets: Vec<WalletStatus>,
+    pub actions: Vec<ActionStatus>,
+}
+
+/// Message waiting to be delivered by an action
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PendingMessage {
+    pub action: String,
+    pub source: String,
+    pub id: String,
+    pub subject: String,
+    /// When it will be delivered, or None if it waits for the action to be registered
+    pub due: Option<DateTime<Utc>>,
+    /// Failed delivery attempts so far
+    pub attempts: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    Status(Status),
+    Outbox(Vec<PendingMessage>),
+    Done(String),
+    Error(String),
+}
+
+/// Writes a request or response as a line of JSON
+pub async fn send<T: Serialize>(stream: &mut (impl AsyncWrite + Unpin), value: &T) -> Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Reads a request or response sent with `send`
+pub async fn receive<T: DeserializeOwned>(stream: &mut (impl AsyncBufRead + Unpin)) -> Result<T> {
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    serde_json::from_str(&line).context("invalid control message")
+}
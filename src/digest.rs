@@ -0,0 +1,536 @@
+use std::{collections::HashMap, fmt, fs::File, io::BufReader, path::PathBuf, sync::Mutex};
+
+use anyhow::{Context, Result};
+use bdk::bitcoin::{hashes::Hash, Network, Txid};
+use chrono::{DateTime, Datelike, Days, Duration, Months, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_reader, to_string};
+
+use crate::persist::{get_state_dir, migrate, set_aside, write_atomically};
+use crate::wallets::SafeWalletInfo;
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestPeriod {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl fmt::Display for DigestPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DigestPeriod::Daily => write!(f, "daily"),
+            DigestPeriod::Weekly => write!(f, "weekly"),
+            DigestPeriod::Monthly => write!(f, "monthly"),
+        }
+    }
+}
+
+impl DigestPeriod {
+    /// First day of the period containing `date` (weeks start on monday)
+    fn start(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            DigestPeriod::Daily => date,
+            DigestPeriod::Weekly => date - Days::new(date.weekday().num_days_from_monday().into()),
+            DigestPeriod::Monthly => date.with_day(1).unwrap_or(date),
+        }
+    }
+
+    fn next(&self, start: NaiveDate) -> NaiveDate {
+        match self {
+            DigestPeriod::Daily => start + Days::new(1),
+            DigestPeriod::Weekly => start + Days::new(7),
+            DigestPeriod::Monthly => start + Months::new(1),
+        }
+    }
+
+    fn previous(&self, start: NaiveDate) -> NaiveDate {
+        match self {
+            DigestPeriod::Daily => start - Days::new(1),
+            DigestPeriod::Weekly => start - Days::new(7),
+            DigestPeriod::Monthly => start - Months::new(1),
+        }
+    }
+
+    /// How far back a digest can look: the last full period starts at most two periods ago,
+    /// with a day to spare for DST changes
+    fn lookback(&self) -> Duration {
+        match self {
+            DigestPeriod::Daily => Duration::days(3),
+            DigestPeriod::Weekly => Duration::days(15),
+            DigestPeriod::Monthly => Duration::days(63),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DigestConfig {
+    name: Option<String>,
+    period: DigestPeriod,
+    wallets: Option<Vec<String>>,
+    hour: Option<u32>,
+    chart: Option<bool>,
+}
+
+impl DigestConfig {
+    pub fn name(&self) -> String {
+        match (&self.name, &self.wallets) {
+            (Some(name), _) => name.clone(),
+            (None, Some(wallets)) => wallets.join(", "),
+            (None, None) => "all wallets".to_string(),
+        }
+    }
+
+    pub fn period(&self) -> DigestPeriod {
+        self.period
+    }
+
+    /// Hour of the day (in the message timezone) at which the digest is sent
+    pub fn hour(&self) -> u32 {
+        self.hour.unwrap_or(8).min(23)
+    }
+
+    pub fn chart(&self) -> bool {
+        self.chart.unwrap_or(true)
+    }
+
+    /// Whether the wallet is covered by this digest. All wallets are, if none are configured.
+    pub fn includes(&self, wallet: &str) -> bool {
+        self.wallets
+            .as_ref()
+            .is_none_or(|w| w.iter().any(|w| w == wallet))
+    }
+
+    fn at(&self, date: NaiveDate, hour: u32, tz: &Tz) -> DateTime<Utc> {
+        let naive = date.and_hms_opt(hour, 0, 0).unwrap_or_default();
+        // Around DST changes the local time might not exist, in which case UTC is close enough
+        tz.from_local_datetime(&naive)
+            .earliest()
+            .map(|t| t.with_timezone(&Utc))
+            .unwrap_or_else(|| naive.and_utc())
+    }
+
+    /// Next time the digest is due after `now`
+    pub fn next_run(&self, now: DateTime<Utc>, tz: &Tz) -> DateTime<Utc> {
+        let start = self.period.start(now.with_timezone(tz).date_naive());
+        let run = self.at(start, self.hour(), tz);
+        if run > now {
+            run
+        } else {
+            self.at(self.period.next(start), self.hour(), tz)
+        }
+    }
+
+    /// The last full period before `now`, as a (start, end) pair
+    pub fn last_period(&self, now: DateTime<Utc>, tz: &Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+        let start = self.period.start(now.with_timezone(tz).date_naive());
+        (
+            self.at(self.period.previous(start), 0, tz),
+            self.at(start, 0, tz),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+struct BalanceSnapshot {
+    timestamp: i64,
+    balance: u64,
+}
+
+fn get_balance_history_filepath() -> PathBuf {
+    get_state_dir().join("balance_history.json")
+}
+
+/// Where older versions kept the balance history
+fn get_legacy_balance_history_filepath() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or(PathBuf::from("cache"))
+        .join(env!("CARGO_PKG_NAME"))
+        .join("balance_history.json")
+}
+
+/// Drops the snapshots older than `cutoff`, except the last one which still tells the balance
+/// at the cutoff
+fn prune(snapshots: &mut Vec<BalanceSnapshot>, cutoff: i64) {
+    let old = snapshots.partition_point(|s| s.timestamp <= cutoff);
+    snapshots.drain(..old.saturating_sub(1));
+}
+
+/// Balance of each wallet over time, persisted to disk. A snapshot is only stored when the
+/// balance changes, and only for as long as the configured digests can look back.
+pub struct BalanceHistory {
+    path: PathBuf,
+    lookback: Mutex<Duration>,
+    wallets: Mutex<HashMap<String, Vec<BalanceSnapshot>>>,
+}
+
+impl BalanceHistory {
+    pub fn load(digests: &[DigestConfig]) -> Self {
+        let path = get_balance_history_filepath();
+        migrate(&get_legacy_balance_history_filepath(), &path);
+        let wallets = match File::open(&path) {
+            Ok(file) => from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+                warn!(
+                    "cannot read balance history from '{}': {:#}",
+                    path.display(),
+                    e
+                );
+                set_aside(&path);
+                Default::default()
+            }),
+            Err(_) => Default::default(),
+        };
+        let history = Self {
+            path,
+            lookback: Mutex::default(),
+            wallets: Mutex::new(wallets),
+        };
+        history.set_digests(digests);
+        history
+    }
+
+    /// Keeps as much history as the longest of the digests needs
+    pub fn set_digests(&self, digests: &[DigestConfig]) {
+        *self.lookback.lock().unwrap() = digests
+            .iter()
+            .map(|d| d.period().lookback())
+            .max()
+            .unwrap_or_default();
+    }
+
+    fn save(&self, wallets: &mut HashMap<String, Vec<BalanceSnapshot>>) -> Result<()> {
+        let cutoff = (Utc::now() - *self.lookback.lock().unwrap()).timestamp();
+        for snapshots in wallets.values_mut() {
+            prune(snapshots, cutoff);
+        }
+        write_atomically(&self.path, &to_string(wallets)?)
+            .context("could not save the balance history")
+    }
+
+    pub fn record(&self, wallet: &str, balance: u64) {
+        let mut wallets = self.wallets.lock().unwrap();
+        let snapshots = wallets.entry(wallet.to_string()).or_default();
+        if snapshots.last().is_some_and(|s| s.balance == balance) {
+            return;
+        }
+        snapshots.push(BalanceSnapshot {
+            timestamp: Utc::now().timestamp(),
+            balance,
+        });
+        if let Err(e) = self.save(&mut wallets) {
+            warn!("[{}] {:#}", wallet, e);
+        }
+    }
+
+    /// Balance of the wallet at a given time, if there is a snapshot before it
+    fn balance_at(&self, wallet: &str, timestamp: i64) -> Option<u64> {
+        self.wallets
+            .lock()
+            .unwrap()
+            .get(wallet)?
+            .iter()
+            .rev()
+            .find(|s| s.timestamp <= timestamp)
+            .map(|s| s.balance)
+    }
+
+    fn timestamps(&self, wallet: &str, start: i64, end: i64) -> Vec<i64> {
+        self.wallets
+            .lock()
+            .unwrap()
+            .get(wallet)
+            .map(|snapshots| {
+                snapshots
+                    .iter()
+                    .map(|s| s.timestamp)
+                    .filter(|t| *t > start && *t < end)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DigestTx {
+    wallet: String,
+    txid: String,
+    net: i64,
+    fee: u64,
+    timestamp: i64,
+    network: Network,
+}
+
+impl DigestTx {
+    pub fn wallet(&self) -> &str {
+        &self.wallet
+    }
+
+    pub fn txid(&self) -> &str {
+        &self.txid
+    }
+
+    pub fn net(&self) -> i64 {
+        self.net
+    }
+
+    pub fn fee(&self) -> u64 {
+        self.fee
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp(self.timestamp, 0).unwrap_or_default()
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+}
+
+/// Summary of the activity of one or more wallets over a period
+pub struct Digest {
+    name: String,
+    period: DigestPeriod,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    opening_balance: u64,
+    closing_balance: u64,
+    transactions: Vec<DigestTx>,
+    balances: Vec<(i64, u64)>,
+    chart: bool,
+}
+
+impl Digest {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn period(&self) -> DigestPeriod {
+        self.period
+    }
+
+    pub fn start(&self) -> DateTime<Utc> {
+        self.start
+    }
+
+    pub fn end(&self) -> DateTime<Utc> {
+        self.end
+    }
+
+    pub fn opening_balance(&self) -> u64 {
+        self.opening_balance
+    }
+
+    pub fn closing_balance(&self) -> u64 {
+        self.closing_balance
+    }
+
+    pub fn change(&self) -> i64 {
+        self.closing_balance as i64 - self.opening_balance as i64
+    }
+
+    /// Transactions confirmed during the period, oldest first
+    pub fn transactions(&self) -> &[DigestTx] {
+        &self.transactions
+    }
+
+    /// Fees of the transactions funded by the wallets
+    pub fn fees(&self) -> u64 {
+        self.transactions
+            .iter()
+            .filter(|tx| tx.net < 0)
+            .map(|tx| tx.fee)
+            .sum()
+    }
+
+    pub fn largest(&self, count: usize) -> Vec<&DigestTx> {
+        let mut txs: Vec<&DigestTx> = self.transactions.iter().collect();
+        txs.sort_by_key(|tx| std::cmp::Reverse(tx.net.unsigned_abs()));
+        txs.truncate(count);
+        txs
+    }
+
+    /// Total balance at the start of the period, at every snapshot within it and at its end
+    pub fn balances(&self) -> &[(i64, u64)] {
+        &self.balances
+    }
+
+    pub fn chart(&self) -> bool {
+        self.chart
+    }
+}
+
+struct WalletDigest {
+    name: String,
+    transactions: Vec<DigestTx>,
+    balance_before_start: u64,
+    current_balance: u64,
+}
+
+fn get_wallet_digest(wallet_info: &SafeWalletInfo, start: i64, end: i64) -> WalletDigest {
+    let wallet_info = wallet_info.lock().unwrap();
+    let network = wallet_info.get_network();
+    let mut transactions = Vec::new();
+    let mut balance_before_start = 0i64;
+    for tx in wallet_info.transactions() {
+        let Some(timestamp) = tx.confirmation_time.as_ref().map(|t| t.timestamp as i64) else {
+            continue;
+        };
+        let net = tx.received as i64 - tx.sent as i64;
+        if timestamp < start {
+            balance_before_start += net;
+        } else if timestamp < end {
+            transactions.push(DigestTx {
+                wallet: wallet_info.name().to_string(),
+                txid: tx.txid.to_string(),
+                net,
+                fee: tx.fee.unwrap_or_default(),
+                timestamp,
+                network,
+            });
+        }
+    }
+    WalletDigest {
+        name: wallet_info.name().to_string(),
+        transactions,
+        balance_before_start: balance_before_start.max(0) as u64,
+        current_balance: wallet_info
+            .balance()
+            .map(|b| b.get_total())
+            .unwrap_or_default(),
+    }
+}
+
+pub fn get_digest(
+    digest_config: &DigestConfig,
+    wallets: &[SafeWalletInfo],
+    balance_history: &BalanceHistory,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Digest {
+    let (start_ts, end_ts) = (start.timestamp(), end.timestamp());
+    let wallet_digests: Vec<WalletDigest> = wallets
+        .iter()
+        .filter(|w| digest_config.includes(w.lock().unwrap().name()))
+        .map(|w| get_wallet_digest(w, start_ts, end_ts))
+        .collect();
+
+    // Snapshots are preferred, but they only exist since the wallet started being monitored, so
+    // fall back to the transaction history
+    let opening = |w: &WalletDigest| {
+        balance_history
+            .balance_at(&w.name, start_ts)
+            .unwrap_or(w.balance_before_start)
+    };
+    let balance_at = |timestamp: i64| -> u64 {
+        wallet_digests
+            .iter()
+            .map(|w| {
+                balance_history
+                    .balance_at(&w.name, timestamp)
+                    .unwrap_or_else(|| opening(w))
+            })
+            .sum()
+    };
+
+    let mut timestamps: Vec<i64> = wallet_digests
+        .iter()
+        .flat_map(|w| balance_history.timestamps(&w.name, start_ts, end_ts))
+        .collect();
+    timestamps.sort_unstable();
+    timestamps.dedup();
+
+    let opening_balance = wallet_digests.iter().map(opening).sum();
+    let closing_balance = wallet_digests
+        .iter()
+        .map(|w| {
+            balance_history
+                .balance_at(&w.name, end_ts)
+                .unwrap_or(w.current_balance)
+        })
+        .sum();
+    let mut balances = vec![(start_ts, opening_balance)];
+    balances.extend(timestamps.into_iter().map(|t| (t, balance_at(t))));
+    balances.push((end_ts, closing_balance));
+
+    let mut transactions: Vec<DigestTx> = wallet_digests
+        .into_iter()
+        .flat_map(|w| w.transactions)
+        .collect();
+    transactions.sort_by_key(|tx| tx.timestamp);
+
+    Digest {
+        name: digest_config.name(),
+        period: digest_config.period(),
+        start,
+        end,
+        opening_balance,
+        closing_balance,
+        transactions,
+        balances,
+        chart: digest_config.chart(),
+    }
+}
+
+/// A made up digest of a couple of transactions, to try out templates
+pub fn example_digest(
+    digest_config: &DigestConfig,
+    network: Network,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Digest {
+    let (start_ts, end_ts) = (start.timestamp(), end.timestamp());
+    let middle_ts = start_ts + (end_ts - start_ts) / 2;
+    let example_tx = |index: u8, net: i64, timestamp: i64| DigestTx {
+        wallet: "example".to_string(),
+        txid: Txid::hash(&[index]).to_string(),
+        net,
+        fee: 1_410,
+        timestamp,
+        network,
+    };
+    let opening_balance = 10_000_000;
+    Digest {
+        name: digest_config.name(),
+        period: digest_config.period(),
+        start,
+        end,
+        opening_balance,
+        closing_balance: opening_balance + 1_500_000 - 601_410,
+        transactions: vec![
+            example_tx(0, 1_500_000, start_ts + 1),
+            example_tx(1, -601_410, middle_ts),
+        ],
+        balances: vec![
+            (start_ts, opening_balance),
+            (start_ts + 1, opening_balance + 1_500_000),
+            (middle_ts, opening_balance + 1_500_000 - 601_410),
+            (end_ts, opening_balance + 1_500_000 - 601_410),
+        ],
+        chart: digest_config.chart(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pruning_keeps_the_balance_at_the_cutoff() {
+        let mut snapshots: Vec<BalanceSnapshot> = [(10, 1), (20, 2), (30, 3), (40, 4)]
+            .into_iter()
+            .map(|(timestamp, balance)| BalanceSnapshot { timestamp, balance })
+            .collect();
+        prune(&mut snapshots, 35);
+        let kept: Vec<i64> = snapshots.iter().map(|s| s.timestamp).collect();
+        assert_eq!(kept, [30, 40]);
+
+        prune(&mut snapshots, 5);
+        assert_eq!(snapshots.len(), 2);
+        prune(&mut snapshots, 50);
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].balance, 4);
+    }
+}
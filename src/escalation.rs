@@ -0,0 +1,304 @@
+use std::{fs::File, io::BufReader, path::PathBuf, sync::Mutex, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_reader, to_string};
+
+use crate::message::{Message, MessageConfig};
+use crate::persist::{get_state_dir, migrate, set_aside, write_atomically};
+
+#[derive(Deserialize, Debug)]
+pub struct EscalationStepConfig {
+    action: String,
+    wait_mins: Option<u64>,
+}
+
+impl EscalationStepConfig {
+    /// Id of the action notified in this step
+    pub fn action(&self) -> &str {
+        &self.action
+    }
+
+    /// How long to wait for an acknowledgement before escalating to the next step
+    pub fn wait(&self) -> Duration {
+        Duration::from_secs(self.wait_mins.unwrap_or(10) * 60)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EscalationConfig {
+    name: String,
+    when: Option<String>,
+    steps: Vec<EscalationStepConfig>,
+}
+
+impl EscalationConfig {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Rule selecting the escalated messages. Defaults to the critical ones.
+    pub fn when(&self) -> Option<&str> {
+        self.when.as_deref()
+    }
+
+    pub fn steps(&self) -> &[EscalationStepConfig] {
+        &self.steps
+    }
+
+    /// Whether the action is notified by one of the steps of the chain
+    pub fn involves(&self, action_id: &str) -> bool {
+        self.steps.iter().any(|s| s.action() == action_id)
+    }
+
+    pub fn check(&self, message_config: &MessageConfig, action_ids: &[&str]) -> Result<()> {
+        if self.steps.is_empty() {
+            bail!("no steps configured");
+        }
+        for step in &self.steps {
+            if !action_ids.contains(&step.action()) {
+                bail!("unknown action id '{}'", step.action());
+            }
+        }
+        if let Some(when) = self.when() {
+            message_config.check_expression(when)?;
+        }
+        Ok(())
+    }
+
+    fn applies(&self, message: &Message, message_config: &MessageConfig) -> bool {
+        let Some(when) = self.when() else {
+            return message.critical();
+        };
+        let Some(context) = message.context() else {
+            return false;
+        };
+        message_config.evaluate(when, context).unwrap_or_else(|e| {
+            warn!(
+                "[{}][{}][{}] {:#}",
+                message.source(),
+                message.id(),
+                self.name,
+                e
+            );
+            false
+        })
+    }
+}
+
+/// An escalated message waiting for an acknowledgement
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Escalation {
+    ack_id: String,
+    policy: String,
+    message: Message,
+    step: usize,
+    next_step: DateTime<Utc>,
+}
+
+fn get_escalations_filepath() -> PathBuf {
+    get_state_dir().join("escalations.json")
+}
+
+/// Where older versions kept the escalations
+fn get_legacy_escalations_filepath() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or(PathBuf::from("cache"))
+        .join(env!("CARGO_PKG_NAME"))
+        .join("escalations.json")
+}
+
+fn get_ack_id() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(char::from)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Escalation chains in progress, persisted to disk so they survive restarts
+pub struct Escalations<'a> {
+    configs: &'a [EscalationConfig],
+    path: PathBuf,
+    active: Mutex<Vec<Escalation>>,
+}
+
+impl<'a> Escalations<'a> {
+    pub fn load(configs: &'a [EscalationConfig]) -> Self {
+        let path = get_escalations_filepath();
+        migrate(&get_legacy_escalations_filepath(), &path);
+        let active: Vec<Escalation> = match File::open(&path) {
+            Ok(file) => from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+                warn!("cannot read escalations from '{}': {:#}", path.display(), e);
+                set_aside(&path);
+                Default::default()
+            }),
+            Err(_) => Default::default(),
+        };
+        for escalation in &active {
+            info!(
+                "[{}][{}][{}] resuming escalation at step {}, ack id '{}'",
+                escalation.message.source(),
+                escalation.message.id(),
+                escalation.policy,
+                escalation.step + 1,
+                escalation.ack_id
+            );
+        }
+        Self {
+            configs,
+            path,
+            active: Mutex::new(active),
+        }
+    }
+
+    fn save(&self, active: &[Escalation]) -> Result<()> {
+        write_atomically(&self.path, &to_string(active)?).context("could not save escalations")
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut Vec<Escalation>) -> T) -> T {
+        let mut active = self.active.lock().unwrap();
+        let result = f(&mut active);
+        if let Err(e) = self.save(&active) {
+            warn!("{:#}", e);
+        }
+        result
+    }
+
+    fn config(&self, name: &str) -> Option<&'a EscalationConfig> {
+        self.configs.iter().find(|c| c.name() == name)
+    }
+
+    /// The first escalation policy that applies to the message, if any
+    pub fn policy_for(
+        &self,
+        message: &Message,
+        message_config: &MessageConfig,
+    ) -> Option<&'a EscalationConfig> {
+        self.configs
+            .iter()
+            .find(|c| c.applies(message, message_config))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.active.lock().unwrap().is_empty()
+    }
+
+    /// Starts escalating the message, returning the action of the first step and the message it
+    /// should be sent
+    pub fn start(&self, policy: &'a EscalationConfig, message: &Message) -> (&'a str, Message) {
+        let ack_id = get_ack_id();
+        let message = message.with_ack_id(&ack_id);
+        let step = &policy.steps()[0];
+        info!(
+            "[{}][{}][{}] escalating, step 1/{} via '{}', ack id '{}'",
+            message.source(),
+            message.id(),
+            policy.name(),
+            policy.steps().len(),
+            step.action(),
+            ack_id
+        );
+        let escalation = Escalation {
+            ack_id,
+            policy: policy.name().to_string(),
+            message: message.clone(),
+            step: 0,
+            next_step: Utc::now() + step.wait(),
+        };
+        self.update(|active| active.push(escalation));
+        (step.action(), message)
+    }
+
+    /// Stops escalating the message with the given ack id, returning whether there was one
+    pub fn acknowledge(&self, ack_id: &str, via: &str) -> bool {
+        let ack_id = ack_id.to_lowercase();
+        if !self
+            .active
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|e| e.ack_id == ack_id)
+        {
+            debug!("ignoring acknowledgement of unknown ack id '{}'", ack_id);
+            return false;
+        }
+        self.update(|active| {
+            active.retain(|e| {
+                if e.ack_id != ack_id {
+                    return true;
+                }
+                info!(
+                    "[{}][{}][{}] acknowledged via {} at step {}, stopping escalation",
+                    e.message.source(),
+                    e.message.id(),
+                    e.policy,
+                    via,
+                    e.step + 1
+                );
+                false
+            })
+        });
+        true
+    }
+
+    /// Advances the unacknowledged escalations whose wait expired, returning the actions of the
+    /// next steps and the messages they should be sent
+    pub fn take_due(&self, now: DateTime<Utc>) -> Vec<(&'a str, Message)> {
+        if !self
+            .active
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|e| e.next_step <= now)
+        {
+            return Vec::new();
+        }
+        self.update(|active| {
+            let mut due = Vec::new();
+            active.retain_mut(|e| {
+                if e.next_step > now {
+                    return true;
+                }
+                let Some(policy) = self.config(&e.policy) else {
+                    warn!(
+                        "[{}][{}][{}] escalation policy no longer exists, stopping escalation",
+                        e.message.source(),
+                        e.message.id(),
+                        e.policy
+                    );
+                    return false;
+                };
+                e.step += 1;
+                let Some(step) = policy.steps().get(e.step) else {
+                    warn!(
+                        "[{}][{}][{}] no acknowledgement after {} steps, giving up",
+                        e.message.source(),
+                        e.message.id(),
+                        e.policy,
+                        policy.steps().len()
+                    );
+                    return false;
+                };
+                info!(
+                    "[{}][{}][{}] not acknowledged, escalating to step {}/{} via '{}'",
+                    e.message.source(),
+                    e.message.id(),
+                    e.policy,
+                    e.step + 1,
+                    policy.steps().len(),
+                    step.action()
+                );
+                e.next_step = now + step.wait();
+                due.push((step.action(), e.message.clone()));
+                true
+            });
+            due
+        })
+    }
+}
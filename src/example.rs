@@ -0,0 +1,183 @@
+use bdk::{
+    bitcoin::{
+        absolute::LockTime, hashes::Hash, Address, Network, OutPoint, Script, ScriptBuf, Sequence,
+        Transaction, TxIn, TxOut, Txid, WPubkeyHash, Witness,
+    },
+    Balance, BlockTime, TransactionDetails,
+};
+
+use crate::message::{TxKind, WalletView};
+
+const FEE: u64 = 1_410;
+const BLOCK_HEIGHT: u32 = 840_000;
+const BLOCK_TIMESTAMP: u64 = 1_713_571_767;
+
+/// Made up script, own ones use even seeds and foreign ones odd seeds
+fn script(seed: u8) -> ScriptBuf {
+    ScriptBuf::new_v0_p2wpkh(&WPubkeyHash::hash(&[seed]))
+}
+
+fn own_script(index: u8) -> ScriptBuf {
+    script(index * 2)
+}
+
+fn foreign_script(index: u8) -> ScriptBuf {
+    script(index * 2 + 1)
+}
+
+/// A made up wallet holding a single made up transaction, to try out templates and actions
+pub struct ExampleWallet {
+    name: String,
+    network: Network,
+    previous_outputs: Vec<(OutPoint, TxOut)>,
+    tx: TransactionDetails,
+}
+
+impl ExampleWallet {
+    /// Sets up a transaction of the given kind, confirmed unless `confirmed` is false
+    pub fn new(name: &str, network: Network, kind: TxKind, confirmed: bool) -> Self {
+        let (inputs, outputs) = match kind {
+            TxKind::Incoming => (
+                vec![(foreign_script(0), 2_101_410)],
+                vec![(own_script(0), 1_500_000), (foreign_script(1), 600_000)],
+            ),
+            TxKind::Outgoing => (
+                vec![(own_script(0), 2_101_410)],
+                vec![(foreign_script(0), 1_500_000), (own_script(1), 600_000)],
+            ),
+            TxKind::SelfTransfer => (
+                vec![(own_script(0), 2_101_410)],
+                vec![(own_script(1), 2_100_000)],
+            ),
+            TxKind::Consolidation => (
+                vec![(own_script(0), 1_500_000), (own_script(1), 601_410)],
+                vec![(own_script(2), 2_100_000)],
+            ),
+            TxKind::Coinjoin => (
+                vec![(own_script(0), 1_100_705), (foreign_script(0), 1_100_705)],
+                vec![
+                    (own_script(1), 1_000_000),
+                    (foreign_script(1), 1_000_000),
+                    (own_script(2), 100_000),
+                    (foreign_script(2), 100_000),
+                ],
+            ),
+            TxKind::Coinbase => (vec![], vec![(own_script(0), 312_500_000)]),
+        };
+
+        let previous_outputs: Vec<(OutPoint, TxOut)> = inputs
+            .into_iter()
+            .enumerate()
+            .map(|(index, (script_pubkey, value))| {
+                (
+                    OutPoint::new(Txid::hash(&[index as u8]), 0),
+                    TxOut {
+                        value,
+                        script_pubkey,
+                    },
+                )
+            })
+            .collect();
+        let input = if kind == TxKind::Coinbase {
+            vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::from_bytes(BLOCK_HEIGHT.to_le_bytes().to_vec()),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }]
+        } else {
+            previous_outputs
+                .iter()
+                .map(|(outpoint, _)| TxIn {
+                    previous_output: *outpoint,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::from_slice(&[vec![0u8; 72], vec![2u8; 33]]),
+                })
+                .collect()
+        };
+        let transaction = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input,
+            output: outputs
+                .into_iter()
+                .map(|(script_pubkey, value)| TxOut {
+                    value,
+                    script_pubkey,
+                })
+                .collect(),
+        };
+
+        let tx = TransactionDetails {
+            txid: transaction.txid(),
+            received: own_value(transaction.output.iter()),
+            sent: own_value(previous_outputs.iter().map(|(_, o)| o)),
+            fee: (kind != TxKind::Coinbase).then_some(FEE),
+            confirmation_time: confirmed.then_some(BlockTime {
+                height: BLOCK_HEIGHT,
+                timestamp: BLOCK_TIMESTAMP,
+            }),
+            transaction: Some(transaction),
+        };
+        Self {
+            name: name.to_string(),
+            network,
+            previous_outputs,
+            tx,
+        }
+    }
+
+    pub fn tx(&self) -> &TransactionDetails {
+        &self.tx
+    }
+}
+
+fn is_own_script(script: &Script) -> bool {
+    (0..8).any(|index| own_script(index).as_script() == script)
+}
+
+fn own_value<'a>(outputs: impl Iterator<Item = &'a TxOut>) -> u64 {
+    outputs
+        .filter(|o| is_own_script(&o.script_pubkey))
+        .map(|o| o.value)
+        .sum()
+}
+
+impl WalletView for ExampleWallet {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn network(&self) -> Network {
+        self.network
+    }
+
+    fn current_balance(&self) -> Balance {
+        Balance {
+            confirmed: 12_345_678,
+            ..Default::default()
+        }
+    }
+
+    fn current_height(&self) -> u32 {
+        BLOCK_HEIGHT + 2
+    }
+
+    fn receive_address(&self) -> Option<String> {
+        Address::from_script(&own_script(7), self.network)
+            .ok()
+            .map(|a| a.to_string())
+    }
+
+    fn is_mine(&self, script: &Script) -> bool {
+        is_own_script(script)
+    }
+
+    fn get_output(&self, outpoint: &OutPoint) -> Option<TxOut> {
+        self.previous_outputs
+            .iter()
+            .find(|(o, _)| o == outpoint)
+            .map(|(_, txout)| txout.clone())
+    }
+}
@@ -0,0 +1,199 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use serde::Deserialize;
+
+use crate::blockchain::{ElectrumConfig, SafeBackendStatus};
+use crate::wallets::SafeWalletState;
+
+/// How long a sync can run before the wallet is considered stuck
+const STALLED_SYNC_SECS: i64 = 600;
+
+#[derive(Deserialize, Debug)]
+pub struct HeartbeatConfig {
+    url: String,
+    start_url: Option<String>,
+    fail_url: Option<String>,
+    interval_secs: Option<u64>,
+    proxy: Option<String>,
+}
+
+impl HeartbeatConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.unwrap_or(60))
+    }
+}
+
+/// Pings an external monitor (e.g. healthchecks.io or an Uptime Kuma push monitor) while sentrum
+/// is healthy, so that it raises the alarm when the pings stop
+pub struct Heartbeat {
+    url: String,
+    start_url: Option<String>,
+    fail_url: Option<String>,
+    interval: Duration,
+    client: reqwest::Client,
+}
+
+impl Heartbeat {
+    pub fn new(
+        heartbeat_config: &HeartbeatConfig,
+        electrum_config: &ElectrumConfig,
+    ) -> Result<Self> {
+        let mut client_builder = reqwest::Client::builder().timeout(Duration::from_secs(10));
+        // Default to the same proxy used to reach the electrum server, so tor users stay on tor
+        let proxy = heartbeat_config.proxy.clone().or(electrum_config
+            .socks5_url()
+            .map(|p| format!("socks5h://{}", p)));
+        if let Some(proxy) = proxy {
+            client_builder = client_builder.proxy(
+                reqwest::Proxy::all(&proxy)
+                    .with_context(|| format!("invalid heartbeat proxy '{}'", proxy))?,
+            );
+        }
+        Ok(Self {
+            url: heartbeat_config.url.clone(),
+            start_url: heartbeat_config.start_url.clone(),
+            fail_url: heartbeat_config.fail_url.clone(),
+            interval: heartbeat_config.interval(),
+            client: client_builder.build()?,
+        })
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    async fn ping(&self, url: &str) {
+        debug!("pinging heartbeat '{}'", redact(url));
+        let result = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            warn!(
+                "could not ping heartbeat '{}': {:#}",
+                redact(url),
+                e.without_url()
+            );
+        }
+    }
+
+    /// Tells the monitor sentrum (re)started, if it has an endpoint for that
+    pub async fn start(&self) {
+        if let Some(start_url) = &self.start_url {
+            self.ping(start_url).await;
+        }
+    }
+
+    /// Pings the monitor if the electrum server and every wallet are healthy, or tells it they
+    /// aren't if it has an endpoint for that
+    pub async fn beat(&self, wallets: &[SafeWalletState], backend: &SafeBackendStatus) {
+        let reasons = unhealthy_reasons(wallets, backend, Utc::now());
+        if reasons.is_empty() {
+            return self.ping(&self.url).await;
+        }
+        warn!(
+            "unhealthy, holding back the heartbeat: {}",
+            reasons.join(", ")
+        );
+        if let Some(fail_url) = &self.fail_url {
+            self.ping(fail_url).await;
+        }
+    }
+}
+
+/// Heartbeat URLs are secrets, since anyone knowing them can ping the monitor, so only their host
+/// is logged
+fn redact(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(url) => format!(
+            "{}://{}/...",
+            url.scheme(),
+            url.host_str().unwrap_or_default()
+        ),
+        Err(_) => "...".to_string(),
+    }
+}
+
+/// Why sentrum is not healthy: the electrum server can't be reached, or a wallet doesn't sync or
+/// its sync has been running for too long
+fn unhealthy_reasons(
+    wallets: &[SafeWalletState],
+    backend: &SafeBackendStatus,
+    now: DateTime<Utc>,
+) -> Vec<String> {
+    let mut reasons = Vec::new();
+    {
+        let backend = backend.lock().unwrap();
+        match (backend.last_seen, &backend.error) {
+            (_, Some(e)) => reasons.push(format!("cannot reach '{}': {}", backend.url, e)),
+            (None, None) => reasons.push(format!("not connected to '{}' yet", backend.url)),
+            (Some(_), None) => {}
+        }
+    }
+    for state in wallets {
+        let state = state.lock().unwrap();
+        if let Some(since) = state.syncing_since {
+            if (now - since).num_seconds() > STALLED_SYNC_SECS {
+                reasons.push(format!(
+                    "[{}] sync running for {}s",
+                    state.name,
+                    (now - since).num_seconds()
+                ));
+                continue;
+            }
+        }
+        match (state.last_sync, &state.sync_error) {
+            (_, Some(e)) => reasons.push(format!("[{}] cannot sync: {}", state.name, e)),
+            (None, None) => reasons.push(format!("[{}] not synced yet", state.name)),
+            (Some(_), None) => {}
+        }
+    }
+    reasons
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::blockchain::BackendStatus;
+    use crate::wallets::WalletState;
+
+    #[test]
+    fn stalled_syncs_are_unhealthy() {
+        let now = Utc::now();
+        let backend = Arc::new(Mutex::new(BackendStatus {
+            url: "tcp://localhost:50001".to_string(),
+            last_seen: Some(now),
+            ..Default::default()
+        }));
+        let state = |syncing_for: Option<i64>| {
+            Arc::new(Mutex::new(WalletState {
+                name: "a".to_string(),
+                last_sync: Some(now - chrono::Duration::minutes(30)),
+                syncing_since: syncing_for.map(|secs| now - chrono::Duration::seconds(secs)),
+                ..Default::default()
+            }))
+        };
+        assert!(unhealthy_reasons(&[state(None)], &backend, now).is_empty());
+        assert!(unhealthy_reasons(&[state(Some(30))], &backend, now).is_empty());
+        assert_eq!(
+            unhealthy_reasons(&[state(Some(900))], &backend, now),
+            ["[a] sync running for 900s"]
+        );
+    }
+
+    #[test]
+    fn urls_are_redacted() {
+        assert_eq!(
+            redact("https://hc-ping.com/0c2ab4ec-ffff-4f5c-9a3c-6f1b2e7d9a10/fail"),
+            "https://hc-ping.com/..."
+        );
+        assert_eq!(redact("not a url"), "...");
+    }
+}
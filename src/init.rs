@@ -0,0 +1,469 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use bdk::{bitcoin::Network, blockchain::GetHeight};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, MultiSelect, Password, Select};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::de::DeserializeOwned;
+
+use crate::actions::{get_action, ActionConfig};
+use crate::blockchain::{get_blockchain, ElectrumConfig};
+use crate::config::{get_config_search_paths, set_path, write_config_file};
+use crate::example::ExampleWallet;
+use crate::message::{Message, MessageConfig, MessageParams, TxKind};
+use crate::price::FiatPrices;
+use crate::wallets::{check_wallet, WalletConfig};
+
+const HEADER: &str = "\
+# sentrum configuration, written by `sentrum init`
+# See sentrum.sample.toml or the README for every available setting
+
+";
+
+const FOOTER: &str = "\
+# Customize the notifications (see the README for all the template parameters)
+#[message]
+#subject = \"[{wallet}] new transaction\"
+#body = \"net: {tx_net_fmt}, balance: {total_balance_fmt}, txid: {txid_short}\"
+";
+
+/// A section of the generated config file, with a comment for each setting
+struct Section {
+    header: &'static str,
+    settings: Vec<(String, toml::Value, &'static str)>,
+}
+
+impl Section {
+    fn new(header: &'static str) -> Self {
+        Self {
+            header,
+            settings: Vec::new(),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: impl Into<toml::Value>, comment: &'static str) {
+        self.settings.push((key.to_string(), value.into(), comment));
+    }
+
+    fn table(&self) -> Result<toml::Value> {
+        let mut table = toml::Value::Table(Default::default());
+        for (key, value, _) in &self.settings {
+            let keys: Vec<String> = key.split('.').map(str::to_string).collect();
+            set_path(&mut table, &keys, value.clone())?;
+        }
+        Ok(table)
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(T::deserialize(self.table()?)?)
+    }
+
+    fn write(&self, content: &mut String) {
+        content.push_str(self.header);
+        content.push('\n');
+        for (key, value, comment) in &self.settings {
+            content.push_str(&format!("# {}\n{} = {}\n", comment, key, value));
+        }
+        content.push('\n');
+    }
+}
+
+fn theme() -> ColorfulTheme {
+    ColorfulTheme::default()
+}
+
+fn ask_optional(prompt: &str) -> Result<Option<String>> {
+    let answer: String = Input::with_theme(&theme())
+        .with_prompt(format!("{} (optional)", prompt))
+        .allow_empty(true)
+        .interact_text()?;
+    Ok(Some(answer).filter(|a| !a.is_empty()))
+}
+
+fn ask_config_path() -> Result<PathBuf> {
+    let paths = get_config_search_paths();
+    let items: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+    let index = Select::with_theme(&theme())
+        .with_prompt("Where should the configuration be written?")
+        .items(&items)
+        .default(0)
+        .interact()?;
+    let path = paths[index].clone();
+    if path.exists()
+        && !Confirm::with_theme(&theme())
+            .with_prompt(format!(
+                "'{}' already exists, overwrite it?",
+                path.display()
+            ))
+            .default(false)
+            .interact()?
+    {
+        bail!("not overwriting '{}'", path.display());
+    }
+    Ok(path)
+}
+
+/// Asks for the network and the electrum server, testing the connection
+fn ask_electrum() -> Result<(Network, Section)> {
+    let networks = [Network::Bitcoin, Network::Testnet, Network::Signet];
+    let index = Select::with_theme(&theme())
+        .with_prompt("Which network are your wallets on?")
+        .items(&["bitcoin (mainnet)", "testnet", "signet"])
+        .default(0)
+        .interact()?;
+    let network = networks[index];
+    loop {
+        let mut section = Section::new("[electrum]");
+        if network != Network::Bitcoin {
+            section.set("network", network.to_string(), "Network of the wallets");
+        }
+        let default_url = section.deserialize::<ElectrumConfig>()?.url().to_string();
+        let url: String = Input::with_theme(&theme())
+            .with_prompt("Electrum server (preferably your own)")
+            .default(default_url)
+            .interact_text()?;
+        section.set("url", url, "Electrum server (\"tcp://\" or \"ssl://\")");
+
+        let electrum_config: ElectrumConfig = section.deserialize()?;
+        println!("Connecting to '{}'...", electrum_config.url());
+        match get_blockchain(&electrum_config).and_then(|b| Ok(b.get_height()?)) {
+            Ok(height) => {
+                println!("Connected, current block height is {}", height);
+                return Ok((network, section));
+            }
+            Err(e) => println!("Could not connect: {:#}", e),
+        }
+        if Confirm::with_theme(&theme())
+            .with_prompt("Use this server anyway?")
+            .default(false)
+            .interact()?
+        {
+            return Ok((network, section));
+        }
+    }
+}
+
+/// Asks for a wallet until it's valid and its first address is the expected one
+fn ask_wallet(network: Network) -> Result<Section> {
+    loop {
+        let mut section = Section::new("[[wallets]]");
+        let name: String = Input::with_theme(&theme())
+            .with_prompt("Wallet name")
+            .interact_text()?;
+        section.set("name", name, "Identifier for naming purposes");
+
+        let by_descriptor = Select::with_theme(&theme())
+            .with_prompt("How do you want to add it?")
+            .items(&[
+                "extended public key (single signature)",
+                "output descriptor (multisig and others)",
+            ])
+            .default(0)
+            .interact()?
+            == 1;
+        if by_descriptor {
+            let descriptor: String = Input::with_theme(&theme())
+                .with_prompt("Output descriptor")
+                .interact_text()?;
+            section.set("descriptor", descriptor, "Wallet descriptor");
+            if let Some(change) = ask_optional("Change descriptor")? {
+                section.set("change_descriptor", change, "Wallet change descriptor");
+            }
+        } else {
+            let xpub: String = Input::with_theme(&theme())
+                .with_prompt("Extended public key (xpub)")
+                .interact_text()?;
+            section.set("xpub", xpub, "Wallet xpub");
+            let kinds = ["segwit", "taproot", "nested_segwit", "legacy"];
+            let kind = Select::with_theme(&theme())
+                .with_prompt("Address type")
+                .items(&[
+                    "native segwit (bc1q...)",
+                    "taproot (bc1p...)",
+                    "nested segwit (3...)",
+                    "legacy (1...)",
+                ])
+                .default(0)
+                .interact()?;
+            section.set(
+                "kind",
+                kinds[kind],
+                "Script kind (\"legacy\", \"nested_segwit\", \"segwit\", \"taproot\")",
+            );
+        }
+
+        let birthday: String = Input::with_theme(&theme())
+            .with_prompt(
+                "Block height before which transactions are never notified, the whole history is \
+                 still synced (optional)",
+            )
+            .allow_empty(true)
+            .validate_with(|input: &String| -> Result<(), &str> {
+                match input.is_empty() || input.parse::<u32>().is_ok() {
+                    true => Ok(()),
+                    false => Err("not a block height"),
+                }
+            })
+            .interact_text()?;
+        if let Ok(birthday) = birthday.parse::<u32>() {
+            section.set(
+                "birthday",
+                birthday,
+                "Block height before which transactions are never notified. Only filters \
+                 notifications, the whole history is still synced",
+            );
+        }
+
+        match section
+            .deserialize::<WalletConfig>()
+            .and_then(|wallet| check_wallet(&wallet, network))
+        {
+            Ok(address) => {
+                println!("Its first receive address is {}", address);
+                if Confirm::with_theme(&theme())
+                    .with_prompt("Does it match the first address shown by your wallet software?")
+                    .default(true)
+                    .interact()?
+                {
+                    return Ok(section);
+                }
+            }
+            Err(e) => println!("Invalid wallet: {:#}", e),
+        }
+        println!("Let's try again");
+    }
+}
+
+fn action_types() -> Vec<(&'static str, &'static str)> {
+    let mut types = vec![("terminal_print", "print to the terminal")];
+    #[cfg(feature = "ntfy")]
+    types.push(("ntfy", "ntfy push notifications"));
+    #[cfg(feature = "email")]
+    types.push(("email", "email"));
+    #[cfg(feature = "telegram")]
+    types.push(("telegram", "telegram bot message"));
+    #[cfg(feature = "nostr")]
+    types.push(("nostr", "nostr direct message"));
+    #[cfg(feature = "desktop")]
+    types.push(("desktop_notification", "desktop notification"));
+    types.push(("command", "run a command"));
+    types
+}
+
+fn ask_action_settings(section: &mut Section, action_type: &str) -> Result<()> {
+    match action_type {
+        "ntfy" => {
+            let url: String = Input::with_theme(&theme())
+                .with_prompt("ntfy server")
+                .default("https://ntfy.sh".to_string())
+                .interact_text()?;
+            section.set("url", url, "ntfy server");
+            let random_topic: String = thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(16)
+                .map(char::from)
+                .collect();
+            let topic: String = Input::with_theme(&theme())
+                .with_prompt("Topic (subscribe to it in the ntfy app)")
+                .default(random_topic)
+                .interact_text()?;
+            section.set("topic", topic, "Notification channel name");
+            if Confirm::with_theme(&theme())
+                .with_prompt("Does the server require credentials?")
+                .default(false)
+                .interact()?
+            {
+                let username: String = Input::with_theme(&theme())
+                    .with_prompt("Username (empty for an access token)")
+                    .allow_empty(true)
+                    .interact_text()?;
+                section.set("credentials.username", username, "Username");
+                let password = Password::with_theme(&theme())
+                    .with_prompt("Password or access token")
+                    .interact()?;
+                section.set("credentials.password", password, "Password or access token");
+            }
+        }
+        "email" => {
+            let server: String = Input::with_theme(&theme())
+                .with_prompt("SMTP server (e.g. smtp.gmail.com)")
+                .interact_text()?;
+            section.set("server", server, "SMTP server");
+            let connections = ["tls", "starttls", "plain"];
+            let connection = Select::with_theme(&theme())
+                .with_prompt("SMTP connection type")
+                .items(&connections)
+                .default(0)
+                .interact()?;
+            section.set(
+                "connection",
+                connections[connection],
+                "SMTP connection type (\"tls\", \"starttls\" or \"plain\")",
+            );
+            if let Some(port) = ask_optional("SMTP port")? {
+                let port: u16 = port.parse().context("invalid port")?;
+                section.set("port", port as i64, "SMTP port");
+            }
+            let login: String = Input::with_theme(&theme())
+                .with_prompt("SMTP login")
+                .interact_text()?;
+            section.set("credentials.authentication_identity", login, "SMTP login");
+            let password = Password::with_theme(&theme())
+                .with_prompt("SMTP password")
+                .interact()?;
+            section.set("credentials.secret", password, "SMTP password");
+            let from: String = Input::with_theme(&theme())
+                .with_prompt("Sender (e.g. sentrum <you@example.com>)")
+                .interact_text()?;
+            section.set("from", from, "Sender");
+            if let Some(to) = ask_optional("Recipient, if not the sender")? {
+                section.set("to", to, "Recipient");
+            }
+        }
+        "telegram" => {
+            let bot_token = Password::with_theme(&theme())
+                .with_prompt("Bot token (from @BotFather)")
+                .interact()?;
+            section.set("bot_token", bot_token, "Auth token of the bot");
+            let user_id: u64 = Input::with_theme(&theme())
+                .with_prompt("Your user id (start a chat with the bot first)")
+                .interact_text()?;
+            section.set(
+                "user_id",
+                user_id as i64,
+                "User id of the recipient of the messages",
+            );
+        }
+        "nostr" => {
+            let recipient: String = Input::with_theme(&theme())
+                .with_prompt("Recipient (npub, hex pubkey, nprofile or nip05)")
+                .interact_text()?;
+            section.set("recipient", recipient, "Recipient of the direct messages");
+        }
+        "command" => {
+            let cmd: String = Input::with_theme(&theme())
+                .with_prompt("Command to run")
+                .interact_text()?;
+            section.set("cmd", cmd, "Command to run");
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn send_test(
+    section: &Section,
+    message_config: &MessageConfig,
+    message: &Message,
+) -> Result<()> {
+    let action_config: ActionConfig = section.deserialize()?;
+    let action = get_action(&Arc::new(message_config.clone()), &action_config, true).await?;
+    action.run(message).await
+}
+
+/// Asks for the settings of the action, optionally sending a test notification through it until
+/// it works
+async fn ask_action(
+    action_type: &str,
+    message_config: &MessageConfig,
+    message: &Message,
+) -> Result<Section> {
+    loop {
+        println!("Setting up {}", action_type);
+        let mut section = Section::new("[[actions]]");
+        section.set("type", action_type, "Action type");
+        ask_action_settings(&mut section, action_type)?;
+        if !Confirm::with_theme(&theme())
+            .with_prompt("Send a test notification?")
+            .default(true)
+            .interact()?
+        {
+            return Ok(section);
+        }
+        match send_test(&section, message_config, message).await {
+            Ok(()) => {
+                if Confirm::with_theme(&theme())
+                    .with_prompt("Test notification sent, did you get it?")
+                    .default(true)
+                    .interact()?
+                {
+                    return Ok(section);
+                }
+            }
+            Err(e) => println!("Could not send the test notification: {:#}", e),
+        }
+        if !Confirm::with_theme(&theme())
+            .with_prompt("Set it up again?")
+            .default(true)
+            .interact()?
+        {
+            return Ok(section);
+        }
+    }
+}
+
+fn write_config(path: &Path, sections: &[Section]) -> Result<()> {
+    let mut content = HEADER.to_string();
+    for section in sections {
+        section.write(&mut content);
+    }
+    content.push_str(FOOTER);
+    write_config_file(path, &content)
+}
+
+/// Walks through setting up wallets, the electrum server and actions, and writes the result
+pub async fn init() -> Result<()> {
+    println!("Welcome! Let's set up sentrum.\n");
+    let path = ask_config_path()?;
+    let (network, electrum) = ask_electrum()?;
+
+    let mut sections = Vec::new();
+    loop {
+        sections.push(ask_wallet(network)?);
+        if !Confirm::with_theme(&theme())
+            .with_prompt("Add another wallet?")
+            .default(false)
+            .interact()?
+        {
+            break;
+        }
+    }
+    sections.push(electrum);
+
+    let action_types = action_types();
+    let selected = loop {
+        let selected = MultiSelect::with_theme(&theme())
+            .with_prompt("How do you want to be notified? (space to select, enter to confirm)")
+            .items(
+                &action_types
+                    .iter()
+                    .map(|(_, description)| description)
+                    .collect::<Vec<_>>(),
+            )
+            .interact()?;
+        if !selected.is_empty() {
+            break selected;
+        }
+        println!("Select at least one");
+    };
+
+    let mut message_config = MessageConfig::default();
+    message_config.load()?;
+    let wallet = ExampleWallet::new("example", network, TxKind::Incoming, true);
+    let message = message_config.message(&MessageParams::new(
+        wallet.tx(),
+        &wallet,
+        FiatPrices::default(),
+    ))?;
+    for index in selected {
+        sections.push(ask_action(action_types[index].0, &message_config, &message).await?);
+    }
+
+    write_config(&path, &sections)?;
+    println!(
+        "\nConfiguration written to '{}'. Run `sentrum check` to verify it, and `sentrum` to start monitoring your wallets.",
+        path.display()
+    );
+    Ok(())
+}
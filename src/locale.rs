@@ -0,0 +1,403 @@
+use serde::Deserialize;
+
+use crate::alerts::AlertKind;
+use crate::digest::DigestPeriod;
+use crate::message::TxKind;
+
+#[derive(Deserialize, Debug, PartialEq, Copy, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+    Es,
+    Fr,
+    It,
+    Pt,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Copy, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Denomination {
+    #[default]
+    Sats,
+    Btc,
+    Mbtc,
+    Bits,
+    Symbol,
+}
+
+/// Words used to build the default message templates of each locale
+struct Translations {
+    new_transaction: &'static str,
+    new_transactions: &'static str,
+    incoming: &'static str,
+    outgoing: &'static str,
+    self_transfer: &'static str,
+    consolidation: &'static str,
+    coinjoin: &'static str,
+    coinbase: &'static str,
+    net: &'static str,
+    balance: &'static str,
+    fee: &'static str,
+    daily_digest: &'static str,
+    weekly_digest: &'static str,
+    monthly_digest: &'static str,
+    opening_balance: &'static str,
+    closing_balance: &'static str,
+    transactions: &'static str,
+    fees_paid: &'static str,
+    largest_movements: &'static str,
+    backend_unreachable: &'static str,
+    backend_recovered: &'static str,
+    sync_stalled: &'static str,
+    sync_recovered: &'static str,
+    unreachable_since: &'static str,
+    last_sync: &'static str,
+    error: &'static str,
+    downtime: &'static str,
+}
+
+impl Locale {
+    fn thousands_separator(&self) -> &'static str {
+        match self {
+            Locale::En => ",",
+            Locale::Fr => "\u{202f}",
+            Locale::De | Locale::Es | Locale::It | Locale::Pt => ".",
+        }
+    }
+
+    fn decimal_separator(&self) -> &'static str {
+        match self {
+            Locale::En => ".",
+            Locale::De | Locale::Es | Locale::Fr | Locale::It | Locale::Pt => ",",
+        }
+    }
+
+    fn translations(&self) -> Translations {
+        match self {
+            Locale::En => Translations {
+                new_transaction: "new transaction",
+                new_transactions: "new transactions",
+                incoming: "incoming transaction",
+                outgoing: "outgoing transaction",
+                self_transfer: "self-transfer",
+                consolidation: "consolidation",
+                coinjoin: "coinjoin transaction",
+                coinbase: "coinbase transaction",
+                net: "net",
+                balance: "balance",
+                fee: "fee",
+                daily_digest: "daily digest",
+                weekly_digest: "weekly digest",
+                monthly_digest: "monthly digest",
+                opening_balance: "opening balance",
+                closing_balance: "closing balance",
+                transactions: "transactions",
+                fees_paid: "fees paid",
+                largest_movements: "largest movements",
+                backend_unreachable: "electrum server unreachable",
+                backend_recovered: "electrum server reachable again",
+                sync_stalled: "wallet not syncing",
+                sync_recovered: "wallet syncing again",
+                unreachable_since: "unreachable since",
+                last_sync: "last sync",
+                error: "error",
+                downtime: "downtime",
+            },
+            Locale::De => Translations {
+                new_transaction: "neue Transaktion",
+                new_transactions: "neue Transaktionen",
+                incoming: "eingehende Transaktion",
+                outgoing: "ausgehende Transaktion",
+                self_transfer: "Eigenüberweisung",
+                consolidation: "Konsolidierung",
+                coinjoin: "Coinjoin-Transaktion",
+                coinbase: "Coinbase-Transaktion",
+                net: "netto",
+                balance: "Guthaben",
+                fee: "Gebühr",
+                daily_digest: "Tagesbericht",
+                weekly_digest: "Wochenbericht",
+                monthly_digest: "Monatsbericht",
+                opening_balance: "Anfangssaldo",
+                closing_balance: "Endsaldo",
+                transactions: "Transaktionen",
+                fees_paid: "gezahlte Gebühren",
+                largest_movements: "größte Bewegungen",
+                backend_unreachable: "Electrum-Server nicht erreichbar",
+                backend_recovered: "Electrum-Server wieder erreichbar",
+                sync_stalled: "Wallet synchronisiert nicht",
+                sync_recovered: "Wallet synchronisiert wieder",
+                unreachable_since: "nicht erreichbar seit",
+                last_sync: "letzte Synchronisierung",
+                error: "Fehler",
+                downtime: "Ausfallzeit",
+            },
+            Locale::Es => Translations {
+                new_transaction: "nueva transacción",
+                new_transactions: "nuevas transacciones",
+                incoming: "transacción entrante",
+                outgoing: "transacción saliente",
+                self_transfer: "transferencia propia",
+                consolidation: "consolidación",
+                coinjoin: "transacción coinjoin",
+                coinbase: "transacción coinbase",
+                net: "neto",
+                balance: "saldo",
+                fee: "comisión",
+                daily_digest: "resumen diario",
+                weekly_digest: "resumen semanal",
+                monthly_digest: "resumen mensual",
+                opening_balance: "saldo inicial",
+                closing_balance: "saldo final",
+                transactions: "transacciones",
+                fees_paid: "comisiones pagadas",
+                largest_movements: "mayores movimientos",
+                backend_unreachable: "servidor electrum inaccesible",
+                backend_recovered: "servidor electrum accesible de nuevo",
+                sync_stalled: "la cartera no se sincroniza",
+                sync_recovered: "la cartera se sincroniza de nuevo",
+                unreachable_since: "inaccesible desde",
+                last_sync: "última sincronización",
+                error: "error",
+                downtime: "tiempo de inactividad",
+            },
+            Locale::Fr => Translations {
+                new_transaction: "nouvelle transaction",
+                new_transactions: "nouvelles transactions",
+                incoming: "transaction entrante",
+                outgoing: "transaction sortante",
+                self_transfer: "transfert interne",
+                consolidation: "consolidation",
+                coinjoin: "transaction coinjoin",
+                coinbase: "transaction coinbase",
+                net: "net",
+                balance: "solde",
+                fee: "frais",
+                daily_digest: "résumé quotidien",
+                weekly_digest: "résumé hebdomadaire",
+                monthly_digest: "résumé mensuel",
+                opening_balance: "solde d'ouverture",
+                closing_balance: "solde de clôture",
+                transactions: "transactions",
+                fees_paid: "frais payés",
+                largest_movements: "plus grands mouvements",
+                backend_unreachable: "serveur electrum injoignable",
+                backend_recovered: "serveur electrum de nouveau joignable",
+                sync_stalled: "le portefeuille ne se synchronise plus",
+                sync_recovered: "le portefeuille se synchronise de nouveau",
+                unreachable_since: "injoignable depuis",
+                last_sync: "dernière synchronisation",
+                error: "erreur",
+                downtime: "durée d'indisponibilité",
+            },
+            Locale::It => Translations {
+                new_transaction: "nuova transazione",
+                new_transactions: "nuove transazioni",
+                incoming: "transazione in entrata",
+                outgoing: "transazione in uscita",
+                self_transfer: "trasferimento interno",
+                consolidation: "consolidamento",
+                coinjoin: "transazione coinjoin",
+                coinbase: "transazione coinbase",
+                net: "netto",
+                balance: "saldo",
+                fee: "commissione",
+                daily_digest: "riepilogo giornaliero",
+                weekly_digest: "riepilogo settimanale",
+                monthly_digest: "riepilogo mensile",
+                opening_balance: "saldo iniziale",
+                closing_balance: "saldo finale",
+                transactions: "transazioni",
+                fees_paid: "commissioni pagate",
+                largest_movements: "movimenti maggiori",
+                backend_unreachable: "server electrum irraggiungibile",
+                backend_recovered: "server electrum di nuovo raggiungibile",
+                sync_stalled: "il portafoglio non si sincronizza",
+                sync_recovered: "il portafoglio si sincronizza di nuovo",
+                unreachable_since: "irraggiungibile da",
+                last_sync: "ultima sincronizzazione",
+                error: "errore",
+                downtime: "durata dell'interruzione",
+            },
+            Locale::Pt => Translations {
+                new_transaction: "nova transação",
+                new_transactions: "novas transações",
+                incoming: "transação recebida",
+                outgoing: "transação enviada",
+                self_transfer: "transferência própria",
+                consolidation: "consolidação",
+                coinjoin: "transação coinjoin",
+                coinbase: "transação coinbase",
+                net: "líquido",
+                balance: "saldo",
+                fee: "taxa",
+                daily_digest: "resumo diário",
+                weekly_digest: "resumo semanal",
+                monthly_digest: "resumo mensal",
+                opening_balance: "saldo inicial",
+                closing_balance: "saldo final",
+                transactions: "transações",
+                fees_paid: "taxas pagas",
+                largest_movements: "maiores movimentos",
+                backend_unreachable: "servidor electrum inacessível",
+                backend_recovered: "servidor electrum acessível novamente",
+                sync_stalled: "a carteira não sincroniza",
+                sync_recovered: "a carteira sincroniza novamente",
+                unreachable_since: "inacessível desde",
+                last_sync: "última sincronização",
+                error: "erro",
+                downtime: "tempo de indisponibilidade",
+            },
+        }
+    }
+
+    pub fn default_subject_template(&self, kind: Option<TxKind>) -> String {
+        let t = self.translations();
+        let title = match kind {
+            None => t.new_transaction,
+            Some(TxKind::Incoming) => t.incoming,
+            Some(TxKind::Outgoing) => t.outgoing,
+            Some(TxKind::SelfTransfer) => t.self_transfer,
+            Some(TxKind::Consolidation) => t.consolidation,
+            Some(TxKind::Coinjoin) => t.coinjoin,
+            Some(TxKind::Coinbase) => t.coinbase,
+        };
+        format!("[{{wallet}}] {}", title)
+    }
+
+    pub fn default_body_template(&self, kind: Option<TxKind>) -> String {
+        let t = self.translations();
+        match kind {
+            Some(TxKind::SelfTransfer) | Some(TxKind::Consolidation) => format!(
+                "{}: {{fee_fmt}}, {}: {{total_balance_fmt}}, txid: {{txid_short}}",
+                t.fee, t.balance
+            ),
+            _ => format!(
+                "{}: {{tx_net_fmt}}, {}: {{total_balance_fmt}}, txid: {{txid_short}}",
+                t.net, t.balance
+            ),
+        }
+    }
+
+    pub fn default_summary_subject_template(&self) -> String {
+        format!(
+            "[{{wallets}}] {{count}} {}",
+            self.translations().new_transactions
+        )
+    }
+
+    pub fn default_summary_body_template(&self) -> String {
+        "{% for event in events %}- {{ event.subject }}: {{ event.body }}\n{% endfor %}".to_string()
+    }
+
+    pub fn default_digest_subject_template(&self, period: DigestPeriod) -> String {
+        let t = self.translations();
+        let title = match period {
+            DigestPeriod::Daily => t.daily_digest,
+            DigestPeriod::Weekly => t.weekly_digest,
+            DigestPeriod::Monthly => t.monthly_digest,
+        };
+        format!("[{{name}}] {}", title)
+    }
+
+    pub fn default_digest_body_template(&self) -> String {
+        let t = self.translations();
+        format!(
+            "{{{{ start }}}} - {{{{ end }}}}\n\
+             {}: {{{{ opening_balance_fmt }}}}\n\
+             {}: {{{{ closing_balance_fmt }}}} ({{{{ change_fmt }}}})\n\
+             {}: {{{{ num_transactions }}}}\n\
+             {}: {{{{ fees_fmt }}}}\n\
+             {{% if largest %}}{}:\n\
+             {{% for tx in largest %}}- [{{{{ tx.wallet }}}}] {{{{ tx.net_fmt }}}}, txid: {{{{ tx.txid_short }}}}\n\
+             {{% endfor %}}{{% endif %}}",
+            t.opening_balance, t.closing_balance, t.transactions, t.fees_paid, t.largest_movements
+        )
+    }
+
+    pub fn default_alert_subject_template(&self, kind: AlertKind) -> String {
+        let t = self.translations();
+        match kind {
+            AlertKind::BackendUnreachable => format!("[sentrum] {}", t.backend_unreachable),
+            AlertKind::BackendRecovered => format!("[sentrum] {}", t.backend_recovered),
+            AlertKind::SyncStalled => format!("[{{{{ wallet }}}}] {}", t.sync_stalled),
+            AlertKind::SyncRecovered => format!("[{{{{ wallet }}}}] {}", t.sync_recovered),
+        }
+    }
+
+    pub fn default_alert_body_template(&self, kind: AlertKind) -> String {
+        let t = self.translations();
+        let error = format!(
+            "{{% if error %}}\n{}: {{{{ error }}}}{{% endif %}}",
+            t.error
+        );
+        match kind {
+            AlertKind::BackendUnreachable => format!(
+                "{{{{ url }}}}\n{}: {{{{ since }}}} ({{{{ minutes }}}} min){}",
+                t.unreachable_since, error
+            ),
+            AlertKind::SyncStalled => format!(
+                "{}: {{{{ since }}}} ({{{{ minutes }}}} min){}",
+                t.last_sync, error
+            ),
+            AlertKind::BackendRecovered | AlertKind::SyncRecovered => {
+                format!("{}: {{{{ minutes }}}} min", t.downtime)
+            }
+        }
+    }
+
+    fn group_digits(&self, n: u64) -> String {
+        let digits = n.to_string();
+        let mut result = String::new();
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                result.push_str(self.thousands_separator());
+            }
+            result.push(c);
+        }
+        result
+    }
+
+    /// Formats `value / 10^decimals` with digit grouping and the locale's decimal separator
+    fn format_fixed_point(&self, value: i64, decimals: u32) -> String {
+        let sign = if value < 0 { "-" } else { "" };
+        let abs = value.unsigned_abs();
+        let integer = self.group_digits(abs / 10u64.pow(decimals));
+        if decimals == 0 {
+            return format!("{}{}", sign, integer);
+        }
+        format!(
+            "{}{}{}{:0width$}",
+            sign,
+            integer,
+            self.decimal_separator(),
+            abs % 10u64.pow(decimals),
+            width = decimals as usize
+        )
+    }
+
+    pub fn format_amount(&self, sats: i64, denomination: Denomination) -> String {
+        match denomination {
+            Denomination::Sats => format!("{} sats", self.format_fixed_point(sats, 0)),
+            Denomination::Btc => format!("{} BTC", self.format_fixed_point(sats, 8)),
+            Denomination::Mbtc => format!("{} mBTC", self.format_fixed_point(sats, 5)),
+            Denomination::Bits => format!("{} bits", self.format_fixed_point(sats, 2)),
+            Denomination::Symbol => {
+                let amount = self.format_fixed_point(sats, 8);
+                match amount.strip_prefix('-') {
+                    Some(abs) => format!("-₿{}", abs),
+                    None => format!("₿{}", amount),
+                }
+            }
+        }
+    }
+
+    /// Formats a fiat value with 2 decimal places, empty if there is no value
+    pub fn format_fiat(&self, value: Option<f64>) -> String {
+        value
+            .map(|v| self.format_fixed_point((v * 100.0).round() as i64, 2))
+            .unwrap_or_default()
+    }
+}
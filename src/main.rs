@@ -1,28 +1,71 @@
+use std::future::Future;
 use std::process::exit;
+use std::sync::Mutex;
 use std::time::Duration;
 
-use actions::Action;
 use anyhow::{bail, Context, Result};
 use async_scoped::TokioScope;
+use bdk::{bitcoin::Txid, TransactionDetails};
+use chrono::Utc;
 use clap::Parser;
 use human_panic::setup_panic;
 use log::{error, info, warn};
 #[cfg(unix)]
 use tokio::signal::unix::{signal, SignalKind};
-use tokio::time::sleep;
+#[cfg(feature = "api")]
+use tokio::sync::broadcast;
+use tokio::task::yield_now;
 
 mod actions;
+mod alerts;
+#[cfg(feature = "api")]
+mod api;
 mod blockchain;
+mod chart;
+mod check;
 mod config;
+mod control;
+mod digest;
+mod escalation;
+mod example;
+mod heartbeat;
+mod init;
+mod locale;
 mod message;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod notifier;
+mod outbox;
+mod persist;
+mod price;
+mod qr;
+mod reload;
+mod secret;
+#[cfg(feature = "ui")]
+mod ui;
 mod wallets;
 
-use crate::actions::{get_actions, run_actions};
-use crate::message::MessageParams;
+use crate::actions::{get_actions, run_actions, update_actions, ConfiguredAction};
+use crate::alerts::{Alerts, AlertsConfig};
+#[cfg(feature = "api")]
+use crate::api::ApiServer;
+use crate::blockchain::SafeBackendStatus;
+use crate::control::{ControlSocket, Daemon};
+use crate::digest::{get_digest, BalanceHistory, DigestConfig};
+use crate::example::ExampleWallet;
+use crate::heartbeat::Heartbeat;
+use crate::message::{Message, MessageParams, TxKind};
+#[cfg(feature = "metrics")]
+use crate::metrics::{MetricsServer, MetricsState};
+use crate::notifier::Notifier;
+use crate::price::{FiatPrices, PriceFeed};
+use crate::reload::{listen_for_hangup, reload_thread, StopSignal};
 use crate::{
     blockchain::BlockchainState,
-    config::{get_config, Args},
-    wallets::{get_wallets, SafeWalletInfo},
+    config::{get_config, Args, Command, Config, NotifySince},
+    wallets::{
+        fetch_prev_txs, get_states, get_wallets, update_wallets, SafeWalletInfo, SafeWalletState,
+    },
 };
 
 fn set_logger() {
@@ -52,108 +95,616 @@ fn set_signal_handlers() -> Result<()> {
         warn!("received process termination signal. Exiting...");
         exit(0)
     });
-    #[cfg(unix)]
-    tokio::spawn(async move {
-        let mut stream = match signal(SignalKind::hangup()) {
-            Err(e) => return e,
-            Ok(s) => s,
-        };
-        stream.recv().await;
-        warn!("received process hangup signal. Exiting...");
-        exit(0)
-    });
+    listen_for_hangup()?;
     Ok(())
 }
 
+async fn get_fiat_prices(price_feed: Option<&PriceFeed>, tx: &TransactionDetails) -> FiatPrices {
+    match price_feed {
+        Some(feed) => {
+            feed.fiat_prices(tx.confirmation_time.as_ref().map(|t| t.timestamp))
+                .await
+        }
+        None => Default::default(),
+    }
+}
+
 async fn get_and_handle_new_txs(
     wallet_info: &SafeWalletInfo,
-    actions: &[&(dyn Action<'_> + Sync)],
+    notifier: Option<&Notifier<'_, '_>>,
+    since: Option<&NotifySince>,
+    price_feed: Option<&PriceFeed>,
+    balance_history: &BalanceHistory,
 ) {
-    let mut locked_wallet_info = wallet_info.lock().unwrap();
-    let txs = locked_wallet_info.get_new_txs();
-    TokioScope::scope_and_block(|s| {
+    let txs = wallet_info.lock().unwrap().get_new_txs();
+    if txs.is_empty() {
+        return;
+    }
+    {
+        let locked_wallet_info = wallet_info.lock().unwrap();
+        match locked_wallet_info.balance() {
+            Ok(balance) => balance_history.record(locked_wallet_info.name(), balance.get_total()),
+            Err(e) => warn!(
+                "[{}] cannot get wallet balance: {:#}",
+                locked_wallet_info.name(),
+                e
+            ),
+        }
+    }
+    let Some(notifier) = notifier else {
+        return;
+    };
+    {
+        let locked_wallet_info = wallet_info.lock().unwrap();
+        if locked_wallet_info.mute().is_muted() {
+            info!(
+                "[{}] wallet muted, not notifying {} transactions",
+                locked_wallet_info.name(),
+                txs.len()
+            );
+            return;
+        }
+    }
+    let txs = txs
+        .into_iter()
+        .filter(|tx| since.is_none_or(|s| s.includes(tx)))
+        .collect::<Vec<_>>();
+    fetch_prev_txs(wallet_info, &txs).await;
+    let (_, fiat_prices) = TokioScope::scope_and_block(|s| {
         for tx in txs.iter() {
-            let params = MessageParams::new(tx, &locked_wallet_info);
+            s.spawn(get_fiat_prices(price_feed, tx));
+        }
+    });
+    let mut messages = Vec::with_capacity(txs.len());
+    {
+        let locked_wallet_info = wallet_info.lock().unwrap();
+        for (tx, fiat) in txs.iter().zip(fiat_prices) {
+            let fiat = fiat.unwrap_or_default();
+            let params = MessageParams::new(tx, &*locked_wallet_info, fiat);
             info!(
                 "[{}][{}] running actions",
                 params.wallet(),
                 params.txid_short()
             );
-            s.spawn(run_actions(actions, Some(params)));
+            match notifier.message_config().message(&params) {
+                Ok(message) => messages.push(message),
+                Err(e) => error!(
+                    "[{}][{}] could not render message: {:#}",
+                    params.wallet(),
+                    params.txid_short(),
+                    e
+                ),
+            }
+        }
+    }
+    TokioScope::scope_and_block(|s| {
+        for message in messages {
+            s.spawn(notifier.notify(message));
         }
     });
 }
 
-async fn update_blockchain_thread(blockchain_state: &mut BlockchainState) {
+async fn update_blockchain_thread(blockchain_state: &mut BlockchainState, stop: &StopSignal) {
     loop {
         blockchain_state.update_height();
-        sleep(Duration::from_secs(60)).await;
+        if !stop.sleep(Duration::from_secs(60)).await {
+            return;
+        }
+    }
+}
+
+async fn watch_wallet_thread(
+    wallet_info: &SafeWalletInfo,
+    notifier: &Notifier<'_, '_>,
+    price_feed: Option<&PriceFeed>,
+    balance_history: &BalanceHistory,
+    stop: &StopSignal,
+) {
+    while !stop.is_stopped() {
+        get_and_handle_new_txs(
+            wallet_info,
+            Some(notifier),
+            None,
+            price_feed,
+            balance_history,
+        )
+        .await;
+        // Syncing blocks, so give the other threads a chance to run
+        yield_now().await;
+    }
+}
+
+async fn pending_messages_thread(notifier: &Notifier<'_, '_>, stop: &StopSignal) {
+    loop {
+        notifier.process_pending().await;
+        if !stop.sleep(Duration::from_secs(10)).await {
+            return;
+        }
+    }
+}
+
+async fn heartbeat_thread(
+    heartbeat: &Heartbeat,
+    wallets: &[SafeWalletState],
+    backend: &SafeBackendStatus,
+    stop: &StopSignal,
+) {
+    loop {
+        heartbeat.beat(wallets, backend).await;
+        if !stop.sleep(heartbeat.interval()).await {
+            return;
+        }
     }
 }
 
-async fn watch_wallet_thread(wallet_info: &SafeWalletInfo, actions: &[&(dyn Action<'_> + Sync)]) {
+async fn alerts_thread(
+    alerts_config: &AlertsConfig,
+    alerts: &Alerts,
+    wallets: &[SafeWalletState],
+    backend: &SafeBackendStatus,
+    notifier: &Notifier<'_, '_>,
+    stop: &StopSignal,
+) {
+    let message_config = notifier.message_config();
     loop {
-        get_and_handle_new_txs(wallet_info, actions).await;
+        for alert in alerts.check(alerts_config, wallets, backend, Utc::now()) {
+            match message_config.alert_message(&alert, alerts_config.critical()) {
+                Ok(message) => {
+                    match alert.kind().is_recovery() {
+                        true => info!("[{}][{}] recovered", message.source(), message.id()),
+                        false => warn!("[{}][{}] alerting", message.source(), message.id()),
+                    }
+                    notifier.send(message).await;
+                }
+                Err(e) => error!("[{}] could not render alert: {:#}", alert.kind(), e),
+            }
+        }
+        if !stop.sleep(Duration::from_secs(60)).await {
+            return;
+        }
     }
 }
 
-async fn initial_wallet_sync(blockchain_state: &mut BlockchainState, wallets: &[SafeWalletInfo]) {
+async fn digest_thread(
+    digest_config: &DigestConfig,
+    wallets: &[SafeWalletInfo],
+    notifier: &Notifier<'_, '_>,
+    balance_history: &BalanceHistory,
+    stop: &StopSignal,
+) {
+    let message_config = notifier.message_config();
+    let timezone = message_config.timezone();
+    loop {
+        let now = Utc::now();
+        let next_run = digest_config.next_run(now, &timezone);
+        info!(
+            "[{}] next {} digest at {}",
+            digest_config.name(),
+            digest_config.period(),
+            message_config.format_timestamp(&next_run)
+        );
+        if !stop
+            .sleep((next_run - now).to_std().unwrap_or_default())
+            .await
+        {
+            return;
+        }
+
+        let (start, end) = digest_config.last_period(next_run, &timezone);
+        let digest = get_digest(digest_config, wallets, balance_history, start, end);
+        match message_config.digest_message(&digest) {
+            Ok(message) => notifier.send(message).await,
+            Err(e) => error!(
+                "[{}] could not render {} digest: {:#}",
+                digest_config.name(),
+                digest_config.period(),
+                e
+            ),
+        }
+    }
+}
+
+/// Marks the existing transactions as already seen, notifying only those after `since` if given
+async fn initial_wallet_sync(
+    blockchain_state: &mut BlockchainState,
+    wallets: &[SafeWalletInfo],
+    notifier: &Notifier<'_, '_>,
+    since: Option<&NotifySince>,
+    price_feed: Option<&PriceFeed>,
+    balance_history: &BalanceHistory,
+) {
     TokioScope::scope_and_block(|s| {
         s.spawn(async { blockchain_state.update_height() });
         for wallet_info in wallets {
-            s.spawn(get_and_handle_new_txs(wallet_info, &[]));
+            s.spawn(get_and_handle_new_txs(
+                wallet_info,
+                since.map(|_| notifier),
+                since,
+                price_feed,
+                balance_history,
+            ));
         }
     });
 }
 
-async fn watch_wallets(
+/// Serves the api, if configured
+#[cfg(feature = "api")]
+async fn serve_api(
+    config: &Config,
+    wallets: Vec<SafeWalletState>,
+    backend: SafeBackendStatus,
+    events: broadcast::Sender<Message>,
+) {
+    let Some(api_config) = config.api() else {
+        return;
+    };
+    match ApiServer::bind(api_config).await {
+        Ok(server) => {
+            server
+                .serve(config.electrum().network(), wallets, backend, events)
+                .await
+        }
+        Err(e) => error!("cannot serve the api: {:#}", e),
+    }
+}
+
+/// Runs every thread until the stop signal is given by `reload`
+#[allow(clippy::too_many_arguments)]
+async fn watch_wallets<'a>(
     blockchain_state: &mut BlockchainState,
+    config: &Config,
     wallets: &[SafeWalletInfo],
-    actions: &[&(dyn Action<'_> + Sync)],
+    actions: &[ConfiguredAction],
+    notifier: &Notifier<'_, 'a>,
+    price_feed: Option<&PriceFeed>,
+    balance_history: &BalanceHistory,
+    control_socket: Option<&ControlSocket>,
+    heartbeat: Option<&Heartbeat>,
+    alerts: &Alerts,
+    stop: &StopSignal,
+    reload: impl Future<Output = ()> + Send,
 ) {
+    let backend = blockchain_state.status();
+    let states = get_states(wallets);
     TokioScope::scope_and_block(|s| {
-        s.spawn(update_blockchain_thread(blockchain_state));
+        s.spawn(reload);
+        #[cfg(feature = "api")]
+        s.spawn(stop.until_stopped(serve_api(
+            config,
+            states.clone(),
+            backend.clone(),
+            notifier.events(),
+        )));
+        if let Some(heartbeat) = heartbeat {
+            s.spawn(heartbeat_thread(heartbeat, &states, &backend, stop));
+        }
+        if let Some(alerts_config) = config.alerts() {
+            s.spawn(alerts_thread(
+                alerts_config,
+                alerts,
+                &states,
+                &backend,
+                notifier,
+                stop,
+            ));
+        }
+        if let Some(control_socket) = control_socket {
+            s.spawn(stop.until_stopped(control_socket.serve(Daemon {
+                config,
+                wallets,
+                states: &states,
+                actions,
+                notifier,
+                price_feed,
+                balance_history,
+            })));
+        }
+        s.spawn(update_blockchain_thread(blockchain_state, stop));
+        s.spawn(pending_messages_thread(notifier, stop));
+        for action in actions {
+            s.spawn(stop.until_stopped(action.keep_registering()));
+        }
         for wallet_info in wallets {
-            s.spawn(watch_wallet_thread(wallet_info, actions));
+            s.spawn(watch_wallet_thread(
+                wallet_info,
+                notifier,
+                price_feed,
+                balance_history,
+                stop,
+            ));
+        }
+        for digest_config in config.digests() {
+            s.spawn(digest_thread(
+                digest_config,
+                wallets,
+                notifier,
+                balance_history,
+                stop,
+            ));
         }
     });
 }
 
+fn get_heartbeat(config: &Config) -> Result<Option<Heartbeat>> {
+    config
+        .heartbeat()
+        .map(|heartbeat_config| Heartbeat::new(heartbeat_config, config.electrum()))
+        .transpose()
+        .context("invalid heartbeat configuration")
+}
+
+fn get_price_feed(config: &Config) -> Result<Option<PriceFeed>> {
+    config
+        .price()
+        .map(|price_config| PriceFeed::new(price_config, config.electrum()))
+        .transpose()
+        .context("invalid price configuration")
+}
+
+/// Renders the test notification from a transaction of one of the wallets
+async fn get_tx_message(
+    config: &Config,
+    wallets: &[SafeWalletInfo],
+    txid: &Txid,
+    price_feed: Option<&PriceFeed>,
+) -> Result<Message> {
+    for wallet_info in wallets {
+        let Some(tx) = wallet_info.lock().unwrap().find_tx(txid) else {
+            continue;
+        };
+        fetch_prev_txs(wallet_info, std::slice::from_ref(&tx)).await;
+        let fiat = get_fiat_prices(price_feed, &tx).await;
+        let locked_wallet_info = wallet_info.lock().unwrap();
+        return config
+            .message()
+            .message(&MessageParams::new(&tx, &*locked_wallet_info, fiat));
+    }
+    bail!("transaction '{}' not found in any configured wallet", txid);
+}
+
+/// Renders the test notification from a made up incoming transaction to the first wallet
+async fn get_example_message(config: &Config, price_feed: Option<&PriceFeed>) -> Result<Message> {
+    let wallet = ExampleWallet::new(
+        config.wallets().first().map_or("example", |w| w.name()),
+        config.electrum().network(),
+        TxKind::Incoming,
+        true,
+    );
+    let fiat = get_fiat_prices(price_feed, wallet.tx()).await;
+    config
+        .message()
+        .message(&MessageParams::new(wallet.tx(), &wallet, fiat))
+}
+
+/// Acknowledges an escalated message through the running daemon, which keeps track of them
+async fn acknowledge(args: &Args, ack_id: &str) -> Result<()> {
+    let request = control::Request::Ack {
+        id: ack_id.to_string(),
+    };
+    match control::request(args.control_socket(), &request).await? {
+        Some(control::Response::Done(done)) => {
+            info!("{}", done);
+            Ok(())
+        }
+        Some(control::Response::Error(e)) => bail!(e),
+        Some(_) => bail!("unexpected response from the daemon"),
+        None => bail!("sentrum does not seem to be running"),
+    }
+}
+
+/// Sends a single test notification to the configured actions, or only to the selected one.
+/// Transactions are looked up by the running daemon if there is one, since it holds the wallet
+/// databases.
+async fn run_test(args: &Args, config: &Config) -> Result<()> {
+    if let Some(txid) = args.txid() {
+        let request = control::Request::Test {
+            action: args.action().map(str::to_string),
+            txid: Some(*txid),
+        };
+        match control::request(args.control_socket(), &request).await? {
+            Some(control::Response::Done(done)) => {
+                info!("{}", done);
+                return Ok(());
+            }
+            Some(control::Response::Error(e)) => bail!(e),
+            Some(_) => bail!("unexpected response from the daemon"),
+            None => {}
+        }
+    }
+    let action_configs = config
+        .actions()
+        .iter()
+        .filter(|a| args.action().is_none_or(|id_or_type| a.matches(id_or_type)))
+        .collect::<Vec<_>>();
+    if let (Some(id_or_type), true) = (args.action(), action_configs.is_empty()) {
+        bail!("no action with id or type '{}'", id_or_type);
+    }
+    let actions = get_actions(config.message(), action_configs, true).await;
+    if !actions.iter().any(ConfiguredAction::is_registered) {
+        bail!("no actions properly configured");
+    }
+
+    let price_feed = get_price_feed(config)?;
+    let message = match args.txid() {
+        Some(txid) => {
+            let wallets = get_wallets(config.wallets(), config.electrum());
+            get_tx_message(config, &wallets, txid, price_feed.as_ref()).await?
+        }
+        None => get_example_message(config, price_feed.as_ref()).await?,
+    };
+    info!("running test actions");
+    let actions_ref = actions.iter().collect::<Vec<_>>();
+    run_actions(&actions_ref, message).await;
+    Ok(())
+}
+
 async fn do_main() -> Result<()> {
     setup_panic!();
     let args = Args::parse();
     set_logger();
     set_signal_handlers().context("failed to setup a signal termination handler")?;
 
-    let config = get_config(&args.config())?;
-
-    let actions = get_actions(config.message(), config.actions()).await;
-    if actions.is_empty() {
-        bail!("no actions properly configured");
+    match args.command() {
+        Some(Command::Ack { id }) => return acknowledge(&args, id).await,
+        Some(Command::Init) => return init::init().await,
+        Some(Command::Check) => return check::check(&args).await,
+        #[cfg(feature = "ui")]
+        Some(Command::Ui { port }) => return ui::ui(&args, *port).await,
+        None => {}
     }
-    let actions_ref = actions.iter().map(Box::as_ref).collect::<Vec<_>>();
 
+    let mut config = get_config(&args.config())?;
+
+    if config.actions().is_empty() {
+        bail!("no actions configured");
+    }
     if args.test() {
-        info!("running test actions");
-        run_actions(&actions_ref, None).await;
-        return Ok(());
+        return run_test(&args, &config).await;
+    }
+
+    let mut actions = get_actions(config.message(), config.actions(), false).await;
+    if !actions.iter().any(ConfiguredAction::is_registered) {
+        warn!("no actions could be registered yet, retrying in the background");
     }
 
     let mut blockchain_state = BlockchainState::new(config.electrum())?;
 
-    let wallets = get_wallets(config.wallets(), config.electrum());
+    let mut price_feed = get_price_feed(&config)?;
+
+    let mut wallets = get_wallets(config.wallets(), config.electrum());
     if wallets.is_empty() {
         bail!("no wallets properly configured");
     }
 
-    if !args.notify_past_txs() {
-        info!("initial wallet sync");
-        initial_wallet_sync(&mut blockchain_state, &wallets).await;
+    let balance_history = BalanceHistory::load(config.digests());
+
+    let mut heartbeat = get_heartbeat(&config)?;
+    if let Some(heartbeat) = &heartbeat {
+        heartbeat.start().await;
     }
-    info!("listening for new relevant events");
-    watch_wallets(&mut blockchain_state, &wallets, &actions_ref).await;
 
-    Ok(())
+    let control_socket = match ControlSocket::bind(args.control_socket().map(Into::into)).await {
+        Ok(socket) => Some(socket),
+        Err(e) => {
+            warn!("control requests are disabled: {:#}", e);
+            None
+        }
+    };
+
+    #[cfg(feature = "metrics")]
+    let metrics_state = match config.metrics() {
+        Some(metrics_config) => match MetricsServer::bind(metrics_config).await {
+            Ok(server) => {
+                let metrics_state = MetricsState::default();
+                tokio::spawn(server.serve(metrics_state.clone()));
+                Some(metrics_state)
+            }
+            Err(e) => {
+                error!("cannot serve metrics: {:#}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let alerts = Alerts::default();
+
+    let mut since = args.notify_since();
+    let mut new_wallets = if args.notify_past_txs() {
+        Vec::new()
+    } else {
+        wallets.clone()
+    };
+    loop {
+        #[cfg(feature = "metrics")]
+        if let Some(metrics_state) = &metrics_state {
+            metrics_state.update(&config, get_states(&wallets), blockchain_state.status());
+        }
+        let notifier = Notifier::new(
+            &actions,
+            config.message(),
+            config.batching(),
+            config.escalations(),
+        );
+        if !new_wallets.is_empty() {
+            info!("initial wallet sync");
+            initial_wallet_sync(
+                &mut blockchain_state,
+                &new_wallets,
+                &notifier,
+                since.take(),
+                price_feed.as_ref(),
+                &balance_history,
+            )
+            .await;
+        }
+        // Not kept around, so that changed wallets can reopen their database on reloads
+        new_wallets.clear();
+        let stop = StopSignal::default();
+        let reloaded = Mutex::new(None);
+        info!("listening for new relevant events");
+        watch_wallets(
+            &mut blockchain_state,
+            &config,
+            &wallets,
+            &actions,
+            &notifier,
+            price_feed.as_ref(),
+            &balance_history,
+            control_socket.as_ref(),
+            heartbeat.as_ref(),
+            &alerts,
+            &stop,
+            reload_thread(&args, &config, &stop, &reloaded),
+        )
+        .await;
+        drop(notifier);
+
+        let Some(new_config) = reloaded.into_inner().unwrap() else {
+            return Ok(());
+        };
+        info!("applying new configuration");
+        if new_config.section_changed(&config, "electrum") {
+            match BlockchainState::new(new_config.electrum()) {
+                Ok(state) => blockchain_state = state,
+                Err(e) => error!(
+                    "cannot connect to the new electrum server, keeping the current one: {:#}",
+                    e
+                ),
+            }
+        }
+        if new_config.section_changed(&config, "price") {
+            match get_price_feed(&new_config) {
+                Ok(feed) => price_feed = feed,
+                Err(e) => error!("{:#}, keeping the current one", e),
+            }
+        }
+        if new_config.section_changed(&config, "heartbeat") {
+            match get_heartbeat(&new_config) {
+                Ok(new_heartbeat) => heartbeat = new_heartbeat,
+                Err(e) => error!("{:#}, keeping the current one", e),
+            }
+        }
+        actions = update_actions(
+            actions,
+            new_config.message(),
+            new_config.actions(),
+            false,
+            |id| !new_config.action_changed(&config, id),
+        )
+        .await;
+        (wallets, new_wallets) = update_wallets(
+            wallets,
+            new_config.wallets(),
+            new_config.electrum(),
+            |name| !new_config.wallet_changed(&config, name),
+        );
+        if wallets.is_empty() {
+            warn!("no wallets properly configured");
+        }
+        balance_history.set_digests(new_config.digests());
+        config = new_config;
+    }
 }
 
 #[tokio::main]
@@ -1,30 +1,200 @@
 extern crate chrono;
 extern crate strfmt;
 
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
 use anyhow::{bail, Context, Result};
-use bdk::{bitcoin::Network, TransactionDetails};
-use chrono::DateTime;
-use serde::Deserialize;
+use bdk::{
+    bitcoin::{Address, Network, OutPoint, Script, TxOut},
+    Balance, TransactionDetails,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use log::warn;
+use minijinja::{context, path_loader, Environment, UndefinedBehavior, Value};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use strfmt::strfmt;
 
-use crate::wallets::WalletInfo;
+use crate::alerts::{Alert, AlertKind};
+use crate::chart::balance_chart_png;
+use crate::digest::{Digest, DigestPeriod, DigestTx};
+use crate::locale::{Denomination, Locale};
+use crate::price::{sats_to_fiat, FiatPrices};
+use crate::qr::qr_code_png;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TxInput {
+    txid: String,
+    vout: u32,
+    value: Option<u64>,
+    address: Option<String>,
+    is_mine: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TxOutput {
+    address: Option<String>,
+    value: u64,
+    is_mine: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Hash, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum TxKind {
+    Incoming,
+    Outgoing,
+    SelfTransfer,
+    Consolidation,
+    Coinjoin,
+    Coinbase,
+}
+
+impl fmt::Display for TxKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxKind::Incoming => write!(f, "incoming"),
+            TxKind::Outgoing => write!(f, "outgoing"),
+            TxKind::SelfTransfer => write!(f, "self_transfer"),
+            TxKind::Consolidation => write!(f, "consolidation"),
+            TxKind::Coinjoin => write!(f, "coinjoin"),
+            TxKind::Coinbase => write!(f, "coinbase"),
+        }
+    }
+}
+
+fn classify_tx(tx: &TransactionDetails, inputs: &[TxInput], outputs: &[TxOutput]) -> TxKind {
+    let Some(raw_tx) = &tx.transaction else {
+        return if tx.received >= tx.sent {
+            TxKind::Incoming
+        } else {
+            TxKind::Outgoing
+        };
+    };
+    if raw_tx.is_coin_base() {
+        return TxKind::Coinbase;
+    }
+    // Inputs whose previous output couldn't be looked up can't be told apart, so go with
+    // whether the wallet spent anything
+    if inputs.iter().any(|i| i.value.is_none()) {
+        return if tx.sent > 0 {
+            TxKind::Outgoing
+        } else {
+            TxKind::Incoming
+        };
+    }
+    let own_inputs = inputs.iter().filter(|i| i.is_mine).count();
+    if own_inputs == 0 {
+        return TxKind::Incoming;
+    }
+    // Foreign inputs next to ours means a collaborative transaction (coinjoin, payjoin, etc)
+    if own_inputs < inputs.len() {
+        return TxKind::Coinjoin;
+    }
+    if outputs.iter().any(|o| !o.is_mine) {
+        return TxKind::Outgoing;
+    }
+    if inputs.len() > 1 && outputs.len() == 1 {
+        TxKind::Consolidation
+    } else {
+        TxKind::SelfTransfer
+    }
+}
 
 pub struct MessageParams<'a, 'b> {
     tx: &'a TransactionDetails,
     wallet: &'b str,
-    total_balance: u64,
+    balance: Balance,
     current_height: u32,
     network: Network,
+    inputs: Vec<TxInput>,
+    outputs: Vec<TxOutput>,
+    kind: TxKind,
+    fiat: FiatPrices,
+    receive_address: Option<String>,
+}
+
+/// What describing a transaction needs to know about the wallet it belongs to
+pub trait WalletView {
+    fn name(&self) -> &str;
+
+    fn network(&self) -> Network;
+
+    fn current_balance(&self) -> Balance;
+
+    fn current_height(&self) -> u32;
+
+    fn receive_address(&self) -> Option<String>;
+
+    fn is_mine(&self, script: &Script) -> bool;
+
+    fn get_output(&self, outpoint: &OutPoint) -> Option<TxOut>;
 }
 
 impl<'a, 'b> MessageParams<'a, 'b> {
-    pub fn new(tx: &'a TransactionDetails, wallet: &'b WalletInfo) -> Self {
+    pub fn new(
+        tx: &'a TransactionDetails,
+        wallet: &'b (impl WalletView + ?Sized),
+        fiat: FiatPrices,
+    ) -> Self {
+        let network = wallet.network();
+        let (inputs, outputs): (Vec<TxInput>, Vec<TxOutput>) = match &tx.transaction {
+            Some(raw_tx) => (
+                raw_tx
+                    .input
+                    .iter()
+                    .map(|txin| {
+                        let prev_output = if txin.previous_output.is_null() {
+                            None
+                        } else {
+                            wallet.get_output(&txin.previous_output)
+                        };
+                        TxInput {
+                            txid: txin.previous_output.txid.to_string(),
+                            vout: txin.previous_output.vout,
+                            value: prev_output.as_ref().map(|o| o.value),
+                            address: prev_output.as_ref().and_then(|o| {
+                                Address::from_script(&o.script_pubkey, network)
+                                    .ok()
+                                    .map(|a| a.to_string())
+                            }),
+                            is_mine: prev_output
+                                .as_ref()
+                                .is_some_and(|o| wallet.is_mine(&o.script_pubkey)),
+                        }
+                    })
+                    .collect(),
+                raw_tx
+                    .output
+                    .iter()
+                    .map(|txout| TxOutput {
+                        address: Address::from_script(&txout.script_pubkey, network)
+                            .ok()
+                            .map(|a| a.to_string()),
+                        value: txout.value,
+                        is_mine: wallet.is_mine(&txout.script_pubkey),
+                    })
+                    .collect(),
+            ),
+            None => Default::default(),
+        };
         Self {
             tx,
             wallet: wallet.name(),
-            total_balance: wallet.total_balance().unwrap_or_default(),
-            current_height: wallet.get_height().unwrap_or_default(),
-            network: wallet.get_network(),
+            balance: wallet.current_balance(),
+            current_height: wallet.current_height(),
+            network,
+            kind: classify_tx(tx, &inputs, &outputs),
+            inputs,
+            outputs,
+            fiat,
+            receive_address: wallet.receive_address(),
         }
     }
 
@@ -39,27 +209,15 @@ impl<'a, 'b> MessageParams<'a, 'b> {
     pub fn confs(&self) -> u32 {
         let current_height = self.current_height;
         self.tx_height()
-            .map(|h| {
-                if current_height >= h {
-                    current_height - h
-                } else {
-                    0
-                }
-            })
+            .map(|h| current_height.saturating_sub(h))
             .unwrap_or_default()
     }
 
-    pub fn conf_timestamp(&self) -> String {
+    pub fn conf_timestamp(&self) -> Option<DateTime<Utc>> {
         self.tx
             .confirmation_time
             .as_ref()
-            .map(|x| {
-                DateTime::from_timestamp(x.timestamp as i64, 0)
-                    .unwrap_or_default()
-                    .format("%Y-%m-%d %H:%M:%S UTC")
-                    .to_string()
-            })
-            .unwrap_or_default()
+            .map(|x| DateTime::from_timestamp(x.timestamp as i64, 0).unwrap_or_default())
     }
 
     pub fn txid(&self) -> String {
@@ -81,9 +239,133 @@ impl<'a, 'b> MessageParams<'a, 'b> {
     pub fn wallet(&self) -> &str {
         self.wallet
     }
+
+    pub fn total_balance(&self) -> u64 {
+        self.balance.get_total()
+    }
+
+    pub fn confirmed_balance(&self) -> u64 {
+        self.balance.confirmed
+    }
+
+    pub fn unconfirmed_balance(&self) -> u64 {
+        self.balance.trusted_pending + self.balance.untrusted_pending
+    }
+
+    pub fn immature_balance(&self) -> u64 {
+        self.balance.immature
+    }
+
+    pub fn vsize(&self) -> usize {
+        self.tx
+            .transaction
+            .as_ref()
+            .map(|t| t.vsize())
+            .unwrap_or_default()
+    }
+
+    pub fn weight(&self) -> u64 {
+        self.tx
+            .transaction
+            .as_ref()
+            .map(|t| t.weight().to_wu())
+            .unwrap_or_default()
+    }
+
+    /// Fee rate in sat/vB, rounded to 2 decimal places
+    pub fn fee_rate(&self) -> f64 {
+        match (self.tx.fee, self.vsize()) {
+            (Some(fee), vsize) if vsize > 0 => (fee as f64 / vsize as f64 * 100.0).round() / 100.0,
+            _ => 0.0,
+        }
+    }
+
+    pub fn rbf(&self) -> bool {
+        self.tx
+            .transaction
+            .as_ref()
+            .is_some_and(|t| t.is_explicitly_rbf())
+    }
+
+    pub fn locktime(&self) -> u32 {
+        self.tx
+            .transaction
+            .as_ref()
+            .map(|t| t.lock_time.to_consensus_u32())
+            .unwrap_or_default()
+    }
+
+    fn input_addresses(&self, is_mine: bool) -> Vec<&str> {
+        self.inputs
+            .iter()
+            .filter(|i| i.is_mine == is_mine)
+            .filter_map(|i| i.address.as_deref())
+            .collect()
+    }
+
+    fn output_addresses(&self, is_mine: bool) -> Vec<&str> {
+        self.outputs
+            .iter()
+            .filter(|o| o.is_mine == is_mine)
+            .filter_map(|o| o.address.as_deref())
+            .collect()
+    }
+
+    pub fn own_receiving_addresses(&self) -> Vec<&str> {
+        self.output_addresses(true)
+    }
+
+    pub fn own_sending_addresses(&self) -> Vec<&str> {
+        self.input_addresses(true)
+    }
+
+    pub fn counterparty_receiving_addresses(&self) -> Vec<&str> {
+        self.output_addresses(false)
+    }
+
+    pub fn counterparty_sending_addresses(&self) -> Vec<&str> {
+        self.input_addresses(false)
+    }
+
+    /// The most relevant address of the transaction: the first of our receiving addresses for
+    /// incoming funds, otherwise the first counterparty receiving address
+    pub fn address(&self) -> Option<&str> {
+        let (primary, fallback) = if self.tx_net() >= 0 {
+            (
+                self.own_receiving_addresses(),
+                self.counterparty_receiving_addresses(),
+            )
+        } else {
+            (
+                self.counterparty_receiving_addresses(),
+                self.own_receiving_addresses(),
+            )
+        };
+        primary.first().or(fallback.first()).copied()
+    }
+
+    pub fn receive_address(&self) -> Option<&str> {
+        self.receive_address.as_deref()
+    }
+
+    pub fn fiat(&self) -> &FiatPrices {
+        &self.fiat
+    }
+
+    pub fn kind(&self) -> TxKind {
+        self.kind
+    }
+
+    pub fn inputs(&self) -> &[TxInput] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[TxOutput] {
+        &self.outputs
+    }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Copy, Clone)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Copy, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum MessageFormat {
     Plain,
@@ -91,11 +373,145 @@ pub enum MessageFormat {
     Html,
 }
 
-#[derive(Deserialize, Default, Debug)]
+#[derive(Deserialize, Debug, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum QrCodeContent {
+    TxUrl,
+    ReceiveAddress,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Attachment {
+    filename: String,
+    content_type: String,
+    data: Vec<u8>,
+}
+
+impl Attachment {
+    pub fn new(filename: &str, content_type: &str, data: Vec<u8>) -> Self {
+        Self {
+            filename: filename.to_string(),
+            content_type: content_type.to_string(),
+            data,
+        }
+    }
+
+    #[cfg(any(feature = "ntfy", feature = "email", feature = "telegram"))]
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    #[cfg(feature = "email")]
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    #[cfg(any(feature = "ntfy", feature = "email", feature = "telegram"))]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    #[cfg(any(feature = "email", feature = "telegram"))]
+    pub fn is_image(&self) -> bool {
+        self.content_type.starts_with("image/")
+    }
+}
+
+/// A fully rendered notification, ready to be delivered by any action
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Message {
+    source: String,
+    id: String,
+    subject: String,
+    body: String,
+    format: MessageFormat,
+    url: Option<String>,
+    context: Option<Value>,
+    attachments: Vec<Attachment>,
+    critical: bool,
+    #[serde(default)]
+    ack_id: Option<String>,
+}
+
+impl Message {
+    /// Name of what originated the message (e.g. the wallet name), used for logging
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Short identifier of the message (e.g. the shortened txid), used for logging
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    #[cfg(any(feature = "ntfy", feature = "email"))]
+    pub fn format(&self) -> MessageFormat {
+        self.format
+    }
+
+    #[cfg(feature = "ntfy")]
+    pub fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    /// Template parameters the message was rendered with. `None` for raw test messages.
+    pub fn context(&self) -> Option<&Value> {
+        self.context.as_ref()
+    }
+
+    #[cfg(any(feature = "ntfy", feature = "email", feature = "telegram"))]
+    pub fn attachments(&self) -> &[Attachment] {
+        &self.attachments
+    }
+
+    /// Whether the message matched the configured critical rule
+    pub fn critical(&self) -> bool {
+        self.critical
+    }
+
+    /// Id with which an escalated message can be acknowledged
+    #[cfg(feature = "ntfy")]
+    pub fn ack_id(&self) -> Option<&str> {
+        self.ack_id.as_deref()
+    }
+
+    /// Copy of the message that can be acknowledged, with the ack id appended to the body
+    pub fn with_ack_id(&self, ack_id: &str) -> Message {
+        Message {
+            body: format!("{}\n\n(ack: {})", self.body, ack_id),
+            ack_id: Some(ack_id.to_string()),
+            ..self.clone()
+        }
+    }
+}
+
+/// Finds an ack id in a reply (e.g. "ack 1a2b3c4d" or a quoted "(ack: 1a2b3c4d)")
+#[cfg(any(feature = "ntfy", feature = "telegram"))]
+pub fn find_ack_id(text: &str) -> Option<&str> {
+    static ACK_ID: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?i)\back:?\s+([a-z0-9]{8})\b").unwrap());
+    ACK_ID
+        .captures(text)
+        .and_then(|c| c.get(1))
+        .map(|m| m.as_str())
+}
+
+#[derive(Deserialize, Default, Debug, Clone)]
 pub struct BlockExplorers {
     mainnet: Option<String>,
     testnet: Option<String>,
     signet: Option<String>,
+    mainnet_address: Option<String>,
+    testnet_address: Option<String>,
+    signet_address: Option<String>,
 }
 
 impl BlockExplorers {
@@ -130,39 +546,333 @@ impl BlockExplorers {
         strfmt!(template, txid => txid.to_string())
             .with_context(|| format!("bad block explorer URL template '{}'", template))
     }
+
+    fn mainnet_address(&self) -> &str {
+        self.mainnet_address
+            .as_deref()
+            .unwrap_or("https://mempool.space/address/{address}")
+    }
+
+    fn testnet_address(&self) -> &str {
+        self.testnet_address
+            .as_deref()
+            .unwrap_or("https://mempool.space/testnet/address/{address}")
+    }
+
+    fn signet_address(&self) -> &str {
+        self.signet_address
+            .as_deref()
+            .unwrap_or("https://mempool.space/signet/address/{address}")
+    }
+
+    pub fn get_address_url_template(&self, network: &Network) -> Result<&str> {
+        Ok(match network {
+            Network::Bitcoin => self.mainnet_address(),
+            Network::Testnet => self.testnet_address(),
+            Network::Signet => self.signet_address(),
+            _ => bail!("unsupported network"),
+        })
+    }
+
+    pub fn get_address_url(&self, network: &Network, address: &str) -> Result<String> {
+        let template = self.get_address_url_template(network)?;
+        strfmt!(template, address => address.to_string())
+            .with_context(|| format!("bad block explorer URL template '{}'", template))
+    }
+}
+
+/// Formats an amount of sats as BTC with all 8 decimal places
+fn btc_filter(sats: i64) -> String {
+    let sign = if sats < 0 { "-" } else { "" };
+    let sats = sats.unsigned_abs();
+    format!("{}{}.{:08}", sign, sats / 100_000_000, sats % 100_000_000)
+}
+
+fn truncate_filter(value: String, length: Option<usize>) -> String {
+    let length = length.unwrap_or(255);
+    if value.chars().count() <= length {
+        return value;
+    }
+    let mut truncated: String = value.chars().take(length).collect();
+    truncated.push('…');
+    truncated
 }
 
-#[derive(Deserialize, Default, Debug)]
+fn pluralize_filter(count: i64, singular: Option<String>, plural: Option<String>) -> String {
+    if count == 1 || count == -1 {
+        singular.unwrap_or_default()
+    } else {
+        plural.unwrap_or("s".to_string())
+    }
+}
+
+/// Templates that use the original `{param}` style (and no jinja tags or comments) are
+/// rewritten into jinja expressions, so existing configurations keep working unchanged. In
+/// those, `{{` and `}}` are escaped braces, and format specs such as `{amount:>10}` are
+/// rejected since jinja has no equivalent.
+fn convert_legacy_template(template: &str) -> Result<Cow<'_, str>> {
+    static LEGACY_TOKEN: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"\{\{|\}\}|\{[%#]|\{([A-Za-z_][A-Za-z0-9_]*)(:[^{}]*)?\}").unwrap()
+    });
+    let mut has_params = false;
+    for token in LEGACY_TOKEN.captures_iter(template) {
+        match &token[0] {
+            "{%" | "{#" => return Ok(Cow::Borrowed(template)),
+            _ => has_params |= token.get(1).is_some(),
+        }
+    }
+    if !has_params {
+        return Ok(Cow::Borrowed(template));
+    }
+    let mut converted = String::with_capacity(template.len());
+    let mut last = 0;
+    for token in LEGACY_TOKEN.captures_iter(template) {
+        let whole = token.get(0).unwrap();
+        converted.push_str(&template[last..whole.start()]);
+        match (token.get(1), token.get(2)) {
+            (Some(_), Some(_)) => bail!(
+                "format specs such as '{}' are not supported, use jinja filters instead",
+                whole.as_str()
+            ),
+            (Some(name), None) => {
+                converted.push_str("{{ ");
+                converted.push_str(name.as_str());
+                converted.push_str(" }}");
+            }
+            // A literal brace could otherwise start a jinja delimiter with what follows it
+            _ if whole.as_str() == "{{" => converted.push_str("{{ '{' }}"),
+            _ => converted.push('}'),
+        }
+        last = whole.end();
+    }
+    converted.push_str(&template[last..]);
+    Ok(Cow::Owned(converted))
+}
+
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct EventTemplates {
+    subject: Option<String>,
+    body: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug, Clone)]
 pub struct MessageConfig {
     subject: Option<String>,
     body: Option<String>,
+    #[serde(default)]
+    events: HashMap<TxKind, EventTemplates>,
+    #[serde(default)]
+    digest: EventTemplates,
+    #[serde(default)]
+    summary: EventTemplates,
+    #[serde(default)]
+    alerts: HashMap<AlertKind, EventTemplates>,
+    subject_file: Option<PathBuf>,
+    body_file: Option<PathBuf>,
+    template_dir: Option<PathBuf>,
     format: Option<MessageFormat>,
     #[serde(default)]
+    locale: Locale,
+    #[serde(default)]
+    denomination: Denomination,
+    timezone: Option<Tz>,
+    date_format: Option<String>,
+    qr_code: Option<QrCodeContent>,
+    critical: Option<String>,
+    #[serde(default)]
     block_explorers: BlockExplorers,
+    #[serde(skip)]
+    env: Environment<'static>,
+    /// Same as `env`, but rendering undefined parameters as empty
+    #[serde(skip)]
+    lenient_env: Environment<'static>,
 }
 
 impl MessageConfig {
-    pub fn subject_template(&self) -> &str {
-        self.subject
-            .as_deref()
-            .unwrap_or("[{wallet}] new transaction")
+    fn template_path(&self, path: &Path) -> PathBuf {
+        match &self.template_dir {
+            Some(dir) => dir.join(path),
+            None => path.to_path_buf(),
+        }
     }
 
-    pub fn body_template(&self) -> &str {
-        self.body
-            .as_deref()
-            .unwrap_or("net: {tx_net} sats, balance: {total_balance} sats, txid: {txid_short}")
+    fn read_template_file(&self, path: &Path) -> Result<String> {
+        let path = self.template_path(path);
+        fs::read_to_string(&path)
+            .with_context(|| format!("could not read template file '{}'", path.display()))
+    }
+
+    /// Reads template files and sets up the template engine. Must be called before rendering.
+    pub fn load(&mut self) -> Result<()> {
+        if let Some(path) = &self.subject_file {
+            self.subject = Some(self.read_template_file(path)?);
+        }
+        if let Some(path) = &self.body_file {
+            self.body = Some(self.read_template_file(path)?);
+        }
+
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        if let Some(dir) = &self.template_dir {
+            env.set_loader(path_loader(dir));
+        }
+        env.add_filter("btc", btc_filter);
+        env.add_filter("truncate", truncate_filter);
+        env.add_filter("pluralize", pluralize_filter);
+        let (locale, denomination) = (self.locale, self.denomination);
+        env.add_filter("amount", move |sats: i64| {
+            locale.format_amount(sats, denomination)
+        });
+        self.lenient_env = env.clone();
+        self.lenient_env
+            .set_undefined_behavior(UndefinedBehavior::Lenient);
+        self.env = env;
+
+        let kinds = [
+            None,
+            Some(TxKind::Incoming),
+            Some(TxKind::Outgoing),
+            Some(TxKind::SelfTransfer),
+            Some(TxKind::Consolidation),
+            Some(TxKind::Coinjoin),
+            Some(TxKind::Coinbase),
+        ];
+        for kind in kinds {
+            for template in [self.subject_template(kind), self.body_template(kind)] {
+                self.check_template(&template)?;
+            }
+        }
+        for template in [
+            self.summary_subject_template(),
+            self.summary_body_template(),
+        ] {
+            self.check_template(&template)?;
+        }
+        for period in [
+            DigestPeriod::Daily,
+            DigestPeriod::Weekly,
+            DigestPeriod::Monthly,
+        ] {
+            for template in [
+                self.digest_subject_template(period),
+                self.digest_body_template(),
+            ] {
+                self.check_template(&template)?;
+            }
+        }
+        for kind in [
+            AlertKind::BackendUnreachable,
+            AlertKind::BackendRecovered,
+            AlertKind::SyncStalled,
+            AlertKind::SyncRecovered,
+        ] {
+            for template in [
+                self.alert_subject_template(kind),
+                self.alert_body_template(kind),
+            ] {
+                self.check_template(&template)?;
+            }
+        }
+        if let Some(expression) = &self.critical {
+            self.check_expression(expression)
+                .context("invalid critical rule")?;
+        }
+        Ok(())
+    }
+
+    /// Event specific templates take precedence over the general one, which takes precedence
+    /// over the event specific defaults of the configured locale
+    pub fn subject_template(&self, kind: Option<TxKind>) -> Cow<'_, str> {
+        match kind
+            .and_then(|k| self.events.get(&k))
+            .and_then(|e| e.subject.as_deref())
+            .or(self.subject.as_deref())
+        {
+            Some(template) => Cow::Borrowed(template),
+            None => Cow::Owned(self.locale.default_subject_template(kind)),
+        }
+    }
+
+    pub fn body_template(&self, kind: Option<TxKind>) -> Cow<'_, str> {
+        match kind
+            .and_then(|k| self.events.get(&k))
+            .and_then(|e| e.body.as_deref())
+            .or(self.body.as_deref())
+        {
+            Some(template) => Cow::Borrowed(template),
+            None => Cow::Owned(self.locale.default_body_template(kind)),
+        }
+    }
+
+    pub fn summary_subject_template(&self) -> Cow<'_, str> {
+        match self.summary.subject.as_deref() {
+            Some(template) => Cow::Borrowed(template),
+            None => Cow::Owned(self.locale.default_summary_subject_template()),
+        }
+    }
+
+    pub fn summary_body_template(&self) -> Cow<'_, str> {
+        match self.summary.body.as_deref() {
+            Some(template) => Cow::Borrowed(template),
+            None => Cow::Owned(self.locale.default_summary_body_template()),
+        }
+    }
+
+    pub fn digest_subject_template(&self, period: DigestPeriod) -> Cow<'_, str> {
+        match self.digest.subject.as_deref() {
+            Some(template) => Cow::Borrowed(template),
+            None => Cow::Owned(self.locale.default_digest_subject_template(period)),
+        }
+    }
+
+    pub fn digest_body_template(&self) -> Cow<'_, str> {
+        match self.digest.body.as_deref() {
+            Some(template) => Cow::Borrowed(template),
+            None => Cow::Owned(self.locale.default_digest_body_template()),
+        }
+    }
+
+    pub fn alert_subject_template(&self, kind: AlertKind) -> Cow<'_, str> {
+        match self.alerts.get(&kind).and_then(|a| a.subject.as_deref()) {
+            Some(template) => Cow::Borrowed(template),
+            None => Cow::Owned(self.locale.default_alert_subject_template(kind)),
+        }
     }
 
-    pub fn replace_template_params(
-        &self,
-        template: &str,
-        params: &MessageParams,
-    ) -> Result<String> {
-        strfmt!(template,
+    pub fn alert_body_template(&self, kind: AlertKind) -> Cow<'_, str> {
+        match self.alerts.get(&kind).and_then(|a| a.body.as_deref()) {
+            Some(template) => Cow::Borrowed(template),
+            None => Cow::Owned(self.locale.default_alert_body_template(kind)),
+        }
+    }
+
+    pub fn format_amount(&self, sats: i64) -> String {
+        self.locale.format_amount(sats, self.denomination)
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.timezone.unwrap_or(Tz::UTC)
+    }
+
+    pub fn format_timestamp(&self, timestamp: &DateTime<Utc>) -> String {
+        timestamp
+            .with_timezone(&self.timezone())
+            .format(
+                self.date_format
+                    .as_deref()
+                    .unwrap_or("%Y-%m-%d %H:%M:%S %Z"),
+            )
+            .to_string()
+    }
+
+    fn template_context(&self, params: &MessageParams) -> Result<Value> {
+        Ok(context!(
             tx_net => params.tx_net(),
-            wallet => params.wallet.to_string(),
-            total_balance => params.total_balance,
+            wallet => params.wallet(),
+            total_balance => params.total_balance(),
+            confirmed_balance => params.confirmed_balance(),
+            unconfirmed_balance => params.unconfirmed_balance(),
+            immature_balance => params.immature_balance(),
             txid => params.txid(),
             txid_short => params.txid_short(),
             received => params.tx().received,
@@ -171,38 +881,481 @@ impl MessageConfig {
             current_height => params.current_height,
             tx_height => params.tx_height().unwrap_or_default(),
             confs => params.confs(),
-            conf_timestamp => params.conf_timestamp(),
-            tx_url => self.get_tx_url(Some(params))?
-        )
-        .with_context(|| format!("invalid template '{}'", template))
+            conf_timestamp => params
+                .conf_timestamp()
+                .map(|t| self.format_timestamp(&t))
+                .unwrap_or_default(),
+            tx_url => self.get_tx_url(params)?,
+            direction => params.kind().to_string(),
+            currency => params.fiat().currency(),
+            price => self.locale.format_fiat(params.fiat().current()),
+            conf_price => self.locale.format_fiat(params.fiat().at_confirmation()),
+            tx_net_fiat => self.locale.format_fiat(
+                sats_to_fiat(params.tx_net(), params.fiat().current())
+            ),
+            tx_net_fiat_at_conf => self.locale.format_fiat(
+                sats_to_fiat(params.tx_net(), params.fiat().at_confirmation())
+            ),
+            total_balance_fiat => self.locale.format_fiat(
+                sats_to_fiat(params.total_balance() as i64, params.fiat().current())
+            ),
+            tx_net_fmt => self.format_amount(params.tx_net()),
+            total_balance_fmt => self.format_amount(params.total_balance() as i64),
+            received_fmt => self.format_amount(params.tx().received as i64),
+            sent_fmt => self.format_amount(params.tx().sent as i64),
+            fee_fmt => self.format_amount(params.tx().fee.unwrap_or_default() as i64),
+            address => params.address().unwrap_or_default(),
+            address_url => self.get_address_url(params)?,
+            own_receiving_addresses => params.own_receiving_addresses().join(", "),
+            own_sending_addresses => params.own_sending_addresses().join(", "),
+            counterparty_receiving_addresses => params.counterparty_receiving_addresses().join(", "),
+            counterparty_sending_addresses => params.counterparty_sending_addresses().join(", "),
+            num_inputs => params.inputs().len(),
+            num_outputs => params.outputs().len(),
+            fee_rate => params.fee_rate(),
+            vsize => params.vsize(),
+            weight => params.weight(),
+            rbf => params.rbf(),
+            locktime => params.locktime(),
+            receive_address => params.receive_address().unwrap_or_default(),
+            inputs => params.inputs(),
+            outputs => params.outputs(),
+        ))
     }
 
-    pub fn subject(&self, params: Option<&MessageParams>) -> Result<String> {
-        match params {
-            Some(p) => self.replace_template_params(self.subject_template(), p),
-            None => Ok(self.subject_template().to_string()),
-        }
+    fn check_template(&self, template: &str) -> Result<()> {
+        convert_legacy_template(template)
+            .and_then(|converted| Ok(self.env.template_from_str(&converted).map(|_| ())?))
+            .with_context(|| format!("invalid template '{}'", template))
     }
 
-    pub fn body(&self, params: Option<&MessageParams>) -> Result<String> {
-        match params {
-            Some(p) => self.replace_template_params(self.body_template(), p),
-            None => Ok(self.body_template().to_string()),
-        }
+    pub fn render(&self, template: &str, context: &Value) -> Result<String> {
+        convert_legacy_template(template)
+            .and_then(|converted| Ok(self.env.render_str(&converted, context)?))
+            .with_context(|| format!("invalid template '{}'", template))
+    }
+
+    /// Renders a template shared by every kind of message, in which the parameters the context
+    /// doesn't have (e.g. `txid` for an alert) are left empty
+    pub fn render_lenient(&self, template: &str, context: &Value) -> Result<String> {
+        convert_legacy_template(template)
+            .and_then(|converted| Ok(self.lenient_env.render_str(&converted, context)?))
+            .with_context(|| format!("invalid template '{}'", template))
     }
 
-    #[allow(dead_code)]
     pub fn format(&self) -> &MessageFormat {
         self.format.as_ref().unwrap_or(&MessageFormat::Plain)
     }
 
-    pub fn get_tx_url(&self, params: Option<&MessageParams>) -> Result<String> {
-        match params {
-            Some(p) => self.block_explorers.get_tx_url(&p.network(), &p.txid()),
-            None => Ok(self
+    fn qr_code(&self, params: &MessageParams) -> Result<Option<Attachment>> {
+        let data = match self.qr_code {
+            Some(QrCodeContent::TxUrl) => Some(self.get_tx_url(params)?),
+            Some(QrCodeContent::ReceiveAddress) => {
+                params.receive_address().map(|a| format!("bitcoin:{}", a))
+            }
+            None => None,
+        };
+        data.map(|d| Ok(Attachment::new("qrcode.png", "image/png", qr_code_png(&d)?)))
+            .transpose()
+    }
+
+    /// Makes sure a rule expression (e.g. `tx_net|abs >= 1000000`) is valid
+    pub fn check_expression(&self, expression: &str) -> Result<()> {
+        self.env
+            .compile_expression(expression)
+            .with_context(|| format!("invalid rule '{}'", expression))?;
+        Ok(())
+    }
+
+    /// Evaluates a rule expression against the parameters a message was rendered with
+    pub fn evaluate(&self, expression: &str, context: &Value) -> Result<bool> {
+        Ok(self
+            .env
+            .compile_expression(expression)?
+            .eval(context)
+            .with_context(|| format!("cannot evaluate rule '{}'", expression))?
+            .is_true())
+    }
+
+    /// Rule deciding which messages are critical, if any
+    pub fn critical_rule(&self) -> Option<&str> {
+        self.critical.as_deref()
+    }
+
+    fn is_critical(&self, context: &Value) -> Result<bool> {
+        match &self.critical {
+            Some(expression) => self.evaluate(expression, context),
+            None => Ok(false),
+        }
+    }
+
+    /// Renders the message for a transaction
+    pub fn message(&self, p: &MessageParams) -> Result<Message> {
+        let context = self.template_context(p)?;
+        Ok(Message {
+            source: p.wallet().to_string(),
+            id: p.txid_short(),
+            subject: self.render(&self.subject_template(Some(p.kind())), &context)?,
+            body: self.render(&self.body_template(Some(p.kind())), &context)?,
+            format: *self.format(),
+            url: Some(self.get_tx_url(p)?),
+            attachments: self.qr_code(p)?.into_iter().collect(),
+            // A rule that can't be evaluated shouldn't cost the notification
+            critical: self.is_critical(&context).unwrap_or_else(|e| {
+                warn!(
+                    "[{}][{}] treating message as not critical: {:#}",
+                    p.wallet(),
+                    p.txid_short(),
+                    e
+                );
+                false
+            }),
+            context: Some(context),
+            ack_id: None,
+        })
+    }
+
+    fn summary_context(&self, messages: &[Message]) -> Value {
+        let mut sources: Vec<&str> = Vec::new();
+        for message in messages {
+            if !sources.contains(&message.source()) {
+                sources.push(message.source());
+            }
+        }
+        context!(
+            count => messages.len(),
+            wallets => sources.join(", "),
+            events => messages
+                .iter()
+                .map(|m| context!(
+                    subject => m.subject(),
+                    body => m.body(),
+                    ..m.context().cloned().unwrap_or_default()
+                ))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Merges several messages into a single one listing all of them
+    pub fn summary_message(&self, messages: &[Message]) -> Result<Message> {
+        let context = self.summary_context(messages);
+        Ok(Message {
+            source: context.get_attr("wallets")?.to_string(),
+            id: format!("{} events", messages.len()),
+            subject: self.render(&self.summary_subject_template(), &context)?,
+            body: self.render(&self.summary_body_template(), &context)?,
+            format: *self.format(),
+            url: None,
+            context: Some(context),
+            attachments: Default::default(),
+            critical: messages.iter().any(Message::critical),
+            ack_id: None,
+        })
+    }
+
+    fn digest_context(&self, digest: &Digest) -> Result<Value> {
+        let tx_context = |tx: &DigestTx| -> Result<Value> {
+            Ok(context!(
+                wallet => tx.wallet(),
+                txid => tx.txid(),
+                txid_short => tx.txid().get(..8).unwrap_or_default(),
+                net => tx.net(),
+                net_fmt => self.format_amount(tx.net()),
+                fee => tx.fee(),
+                fee_fmt => self.format_amount(tx.fee() as i64),
+                timestamp => self.format_timestamp(&tx.timestamp()),
+                tx_url => self.block_explorers.get_tx_url(&tx.network(), tx.txid())?,
+            ))
+        };
+        Ok(context!(
+            name => digest.name(),
+            period => digest.period().to_string(),
+            start => self.format_timestamp(&digest.start()),
+            end => self.format_timestamp(&digest.end()),
+            opening_balance => digest.opening_balance(),
+            closing_balance => digest.closing_balance(),
+            change => digest.change(),
+            fees => digest.fees(),
+            opening_balance_fmt => self.format_amount(digest.opening_balance() as i64),
+            closing_balance_fmt => self.format_amount(digest.closing_balance() as i64),
+            change_fmt => self.format_amount(digest.change()),
+            fees_fmt => self.format_amount(digest.fees() as i64),
+            num_transactions => digest.transactions().len(),
+            transactions => digest
+                .transactions()
+                .iter()
+                .map(tx_context)
+                .collect::<Result<Vec<_>>>()?,
+            largest => digest
+                .largest(3)
+                .into_iter()
+                .map(tx_context)
+                .collect::<Result<Vec<_>>>()?,
+        ))
+    }
+
+    /// Renders a periodic digest, with a balance chart attached if enabled
+    pub fn digest_message(&self, digest: &Digest) -> Result<Message> {
+        let context = self.digest_context(digest)?;
+        let attachments = if digest.chart() {
+            vec![Attachment::new(
+                "balance.png",
+                "image/png",
+                balance_chart_png(digest.balances())?,
+            )]
+        } else {
+            Default::default()
+        };
+        Ok(Message {
+            source: digest.name().to_string(),
+            id: digest.period().to_string(),
+            subject: self.render(&self.digest_subject_template(digest.period()), &context)?,
+            body: self.render(&self.digest_body_template(), &context)?,
+            format: *self.format(),
+            url: None,
+            context: Some(context),
+            attachments,
+            critical: false,
+            ack_id: None,
+        })
+    }
+
+    fn alert_context(&self, alert: &Alert) -> Value {
+        context!(
+            kind => alert.kind().to_string(),
+            wallet => alert.wallet().unwrap_or_default(),
+            url => alert.url(),
+            since => self.format_timestamp(&alert.since()),
+            minutes => alert.duration().num_minutes(),
+            error => alert.error().unwrap_or_default(),
+        )
+    }
+
+    /// Renders an alert about sentrum itself. Recoveries are never critical.
+    pub fn alert_message(&self, alert: &Alert, critical: bool) -> Result<Message> {
+        let context = self.alert_context(alert);
+        Ok(Message {
+            source: alert.wallet().unwrap_or(alert.url()).to_string(),
+            id: alert.kind().to_string(),
+            subject: self.render(&self.alert_subject_template(alert.kind()), &context)?,
+            body: self.render(&self.alert_body_template(alert.kind()), &context)?,
+            format: *self.format(),
+            url: None,
+            context: Some(context),
+            attachments: Default::default(),
+            critical: critical && !alert.kind().is_recovery(),
+            ack_id: None,
+        })
+    }
+
+    pub fn get_address_url(&self, params: &MessageParams) -> Result<String> {
+        match params.address() {
+            Some(address) => self
                 .block_explorers
-                .get_tx_url_template(&Network::Bitcoin)?
-                .to_string()),
+                .get_address_url(&params.network(), address),
+            None => Ok(Default::default()),
+        }
+    }
+
+    pub fn get_tx_url(&self, params: &MessageParams) -> Result<String> {
+        self.block_explorers
+            .get_tx_url(&params.network(), &params.txid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::{absolute::LockTime, Transaction, TxIn};
+
+    use crate::example::ExampleWallet;
+
+    fn input(value: Option<u64>, is_mine: bool) -> TxInput {
+        TxInput {
+            txid: "00".repeat(32),
+            vout: 0,
+            value,
+            address: None,
+            is_mine,
+        }
+    }
+
+    fn output(is_mine: bool) -> TxOutput {
+        TxOutput {
+            address: None,
+            value: 1000,
+            is_mine,
         }
     }
+
+    fn tx(inputs: &[TxInput], sent: u64, received: u64) -> TransactionDetails {
+        let previous_output = OutPoint::new("ab".repeat(32).parse().unwrap(), 0);
+        let transaction = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|_| TxIn {
+                    previous_output,
+                    ..Default::default()
+                })
+                .collect(),
+            output: Vec::new(),
+        };
+        TransactionDetails {
+            txid: transaction.txid(),
+            transaction: Some(transaction),
+            received,
+            sent,
+            fee: None,
+            confirmation_time: None,
+        }
+    }
+
+    fn classify(inputs: &[TxInput], outputs: &[TxOutput], sent: u64, received: u64) -> TxKind {
+        classify_tx(&tx(inputs, sent, received), inputs, outputs)
+    }
+
+    #[test]
+    fn classifies_incoming() {
+        let inputs = [input(Some(5000), false)];
+        let outputs = [output(true), output(false)];
+        assert_eq!(classify(&inputs, &outputs, 0, 1000), TxKind::Incoming);
+    }
+
+    #[test]
+    fn classifies_outgoing() {
+        let inputs = [input(Some(5000), true)];
+        let outputs = [output(false), output(true)];
+        assert_eq!(classify(&inputs, &outputs, 5000, 1000), TxKind::Outgoing);
+    }
+
+    #[test]
+    fn classifies_self_transfers_and_consolidations() {
+        let inputs = [input(Some(5000), true)];
+        let outputs = [output(true), output(true)];
+        assert_eq!(
+            classify(&inputs, &outputs, 5000, 2000),
+            TxKind::SelfTransfer
+        );
+        let inputs = [input(Some(5000), true), input(Some(5000), true)];
+        let outputs = [output(true)];
+        assert_eq!(
+            classify(&inputs, &outputs, 10000, 1000),
+            TxKind::Consolidation
+        );
+    }
+
+    #[test]
+    fn classifies_collaborative_transactions() {
+        let inputs = [input(Some(5000), true), input(Some(5000), false)];
+        let outputs = [output(true), output(false)];
+        assert_eq!(classify(&inputs, &outputs, 5000, 1000), TxKind::Coinjoin);
+    }
+
+    #[test]
+    fn unresolved_inputs_fall_back_to_what_was_sent() {
+        let inputs = [input(None, false)];
+        let outputs = [output(false), output(true)];
+        assert_eq!(classify(&inputs, &outputs, 5000, 1000), TxKind::Outgoing);
+        assert_eq!(classify(&inputs, &outputs, 0, 1000), TxKind::Incoming);
+        let inputs = [input(Some(5000), true), input(None, false)];
+        assert_eq!(classify(&inputs, &outputs, 5000, 1000), TxKind::Outgoing);
+    }
+
+    #[test]
+    fn transactions_without_details_compare_amounts() {
+        let mut details = tx(&[], 5000, 1000);
+        details.transaction = None;
+        assert_eq!(classify_tx(&details, &[], &[]), TxKind::Outgoing);
+        details.sent = 0;
+        assert_eq!(classify_tx(&details, &[], &[]), TxKind::Incoming);
+    }
+
+    #[test]
+    fn legacy_templates_are_converted() {
+        assert_eq!(
+            convert_legacy_template("{wallet} received {amount} sats").unwrap(),
+            "{{ wallet }} received {{ amount }} sats"
+        );
+        assert_eq!(convert_legacy_template("no params").unwrap(), "no params");
+    }
+
+    #[test]
+    fn legacy_brace_escapes_stay_literal() {
+        let config = message_config("").unwrap();
+        for (template, expected) in [
+            ("{{ wallet }} {amount}", "{ wallet } 5"),
+            ("{{{wallet}}}: {amount}", "{w}: 5"),
+            ("{{#}} {{%}} {amount}", "{#} {%} 5"),
+        ] {
+            let rendered = config
+                .render(template, &context!(wallet => "w", amount => 5))
+                .unwrap();
+            assert_eq!(rendered, expected, "{}", template);
+        }
+    }
+
+    #[test]
+    fn legacy_format_specs_are_rejected() {
+        let error = convert_legacy_template("{wallet} received {amount:>10}").unwrap_err();
+        assert!(error.to_string().contains("'{amount:>10}'"), "{}", error);
+        assert!(message_config("subject = '{amount:.2}'").is_err());
+    }
+
+    #[test]
+    fn jinja_templates_are_left_alone() {
+        for template in [
+            "{{ wallet }} received {{ amount }}",
+            "{{wallet}}",
+            "{% if fee %}{fee}{% endif %}",
+            "{# comment #} {wallet}",
+        ] {
+            assert!(matches!(
+                convert_legacy_template(template).unwrap(),
+                Cow::Borrowed(t) if t == template
+            ));
+        }
+    }
+
+    fn message_config(content: &str) -> Result<MessageConfig> {
+        let mut config: MessageConfig = toml::from_str(content)?;
+        config.load()?;
+        Ok(config)
+    }
+
+    fn example_message(config: &MessageConfig) -> Message {
+        let wallet = ExampleWallet::new("example", Network::Bitcoin, TxKind::Outgoing, true);
+        config
+            .message(&MessageParams::new(
+                wallet.tx(),
+                &wallet,
+                FiatPrices::default(),
+            ))
+            .unwrap()
+    }
+
+    #[test]
+    fn critical_rule_is_checked_on_load() {
+        assert!(message_config("critical = 'tx_net >'").is_err());
+        let config = message_config("critical = \"direction == 'outgoing'\"").unwrap();
+        assert!(example_message(&config).critical());
+    }
+
+    #[test]
+    fn critical_rule_failing_to_evaluate_keeps_the_message() {
+        let config = message_config("critical = 'no_such_param > 1'").unwrap();
+        assert!(!example_message(&config).critical());
+    }
+
+    #[test]
+    fn lenient_rendering_leaves_missing_parameters_empty() {
+        let config = message_config("").unwrap();
+        let context = context!(kind => "backend_down");
+        assert!(config.render("{kind} {txid}", &context).is_err());
+        assert_eq!(
+            config.render_lenient("{kind} {txid}", &context).unwrap(),
+            "backend_down "
+        );
+    }
 }
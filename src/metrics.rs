@@ -0,0 +1,270 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use log::{error, info};
+use prometheus::{Encoder, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use serde::Deserialize;
+use tokio::net::TcpListener;
+
+use crate::blockchain::SafeBackendStatus;
+use crate::config::Config;
+use crate::wallets::SafeWalletState;
+
+#[derive(Deserialize, Debug)]
+pub struct MetricsConfig {
+    bind: Option<SocketAddr>,
+    balances: Option<bool>,
+}
+
+impl MetricsConfig {
+    pub fn bind(&self) -> SocketAddr {
+        self.bind
+            .unwrap_or(SocketAddr::from((Ipv4Addr::LOCALHOST, 9421)))
+    }
+
+    /// Whether wallet balances are exported, which is opt-in since anyone that can scrape them
+    /// learns how much the wallets hold
+    pub fn balances(&self) -> bool {
+        self.balances.unwrap_or(false)
+    }
+}
+
+/// Counters kept for the whole run, so that they don't reset on reloads
+struct Counters {
+    registry: Registry,
+    events: IntCounterVec,
+    deliveries: IntCounterVec,
+    failures: IntCounterVec,
+}
+
+impl Counters {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let events = IntCounterVec::new(
+            Opts::new("sentrum_events_total", "Transactions notified"),
+            &["wallet"],
+        )
+        .unwrap();
+        let deliveries = IntCounterVec::new(
+            Opts::new(
+                "sentrum_action_deliveries_total",
+                "Messages delivered by an action",
+            ),
+            &["action"],
+        )
+        .unwrap();
+        let failures = IntCounterVec::new(
+            Opts::new(
+                "sentrum_action_failures_total",
+                "Failed attempts to deliver a message by an action",
+            ),
+            &["action"],
+        )
+        .unwrap();
+        registry.register(Box::new(events.clone())).unwrap();
+        registry.register(Box::new(deliveries.clone())).unwrap();
+        registry.register(Box::new(failures.clone())).unwrap();
+        Self {
+            registry,
+            events,
+            deliveries,
+            failures,
+        }
+    }
+}
+
+static COUNTERS: LazyLock<Counters> = LazyLock::new(Counters::new);
+
+pub fn count_event(wallet: &str) {
+    COUNTERS.events.with_label_values(&[wallet]).inc();
+}
+
+pub fn count_delivery(action: &str, delivered: bool) {
+    match delivered {
+        true => COUNTERS.deliveries.with_label_values(&[action]).inc(),
+        false => COUNTERS.failures.with_label_values(&[action]).inc(),
+    }
+}
+
+struct Watched {
+    balances: bool,
+    wallets: Vec<SafeWalletState>,
+    backend: SafeBackendStatus,
+}
+
+/// What the metrics and health checks are read from, replaced after every reload
+#[derive(Clone, Default)]
+pub struct MetricsState(Arc<Mutex<Option<Watched>>>);
+
+impl MetricsState {
+    pub fn update(
+        &self,
+        config: &Config,
+        wallets: Vec<SafeWalletState>,
+        backend: SafeBackendStatus,
+    ) {
+        *self.0.lock().unwrap() = Some(Watched {
+            balances: config.metrics().is_some_and(MetricsConfig::balances),
+            wallets,
+            backend,
+        });
+    }
+
+    /// Gauges read from the current state of the wallets and the electrum server
+    fn gauges(&self) -> prometheus::Result<Registry> {
+        let registry = Registry::new();
+        let guard = self.0.lock().unwrap();
+        let Some(watched) = guard.as_ref() else {
+            return Ok(registry);
+        };
+
+        let backend = watched.backend.lock().unwrap().clone();
+        let up = IntGauge::new(
+            "sentrum_backend_up",
+            "Whether the electrum server answered the last time it was polled",
+        )?;
+        up.set((backend.last_seen.is_some() && backend.error.is_none()) as i64);
+        registry.register(Box::new(up))?;
+        if let Some(last_seen) = backend.last_seen {
+            let gauge = IntGauge::new(
+                "sentrum_backend_last_seen_timestamp_seconds",
+                "Last time the electrum server answered",
+            )?;
+            gauge.set(last_seen.timestamp());
+            registry.register(Box::new(gauge))?;
+        }
+        if let Some(height) = backend.height {
+            let gauge = IntGauge::new("sentrum_chain_height", "Current block height")?;
+            gauge.set(height as i64);
+            registry.register(Box::new(gauge))?;
+        }
+
+        let last_sync = IntGaugeVec::new(
+            Opts::new(
+                "sentrum_wallet_last_sync_timestamp_seconds",
+                "Last time the wallet synced successfully",
+            ),
+            &["wallet"],
+        )?;
+        let balance = IntGaugeVec::new(
+            Opts::new("sentrum_wallet_balance_sats", "Total balance of the wallet"),
+            &["wallet"],
+        )?;
+        let balances = watched.balances;
+        for state in &watched.wallets {
+            let state = state.lock().unwrap();
+            if let Some(time) = state.last_sync {
+                last_sync
+                    .with_label_values(&[&state.name])
+                    .set(time.timestamp());
+            }
+            if balances && state.last_sync.is_some() {
+                balance
+                    .with_label_values(&[&state.name])
+                    .set(state.balance.get_total() as i64);
+            }
+        }
+        registry.register(Box::new(last_sync))?;
+        if balances {
+            registry.register(Box::new(balance))?;
+        }
+        Ok(registry)
+    }
+
+    /// Why the daemon is not ready: the electrum server can't be reached, or some wallets didn't
+    /// complete their initial sync
+    fn unready_reasons(&self) -> Vec<String> {
+        let guard = self.0.lock().unwrap();
+        let Some(watched) = guard.as_ref() else {
+            return vec!["starting".to_string()];
+        };
+        let mut reasons = Vec::new();
+        let backend = watched.backend.lock().unwrap().clone();
+        match (backend.last_seen, backend.error) {
+            (_, Some(e)) => reasons.push(format!("cannot reach '{}': {}", backend.url, e)),
+            (None, None) => reasons.push(format!("not connected to '{}' yet", backend.url)),
+            (Some(_), None) => {}
+        }
+        for state in &watched.wallets {
+            let state = state.lock().unwrap();
+            if state.last_sync.is_none() {
+                reasons.push(format!(
+                    "wallet '{}' did not complete its initial sync",
+                    state.name
+                ));
+            }
+        }
+        reasons
+    }
+}
+
+async fn metrics_handler(State(state): State<MetricsState>) -> Response {
+    let mut families = COUNTERS.registry.gather();
+    match state.gauges() {
+        Ok(gauges) => families.extend(gauges.gather()),
+        Err(e) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)).into_response();
+        }
+    }
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&families, &mut buffer) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)).into_response();
+    }
+    (
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        buffer,
+    )
+        .into_response()
+}
+
+/// Answers as long as the daemon is running
+async fn healthz_handler() -> &'static str {
+    "ok"
+}
+
+async fn readyz_handler(State(state): State<MetricsState>) -> Response {
+    let reasons = state.unready_reasons();
+    match reasons.is_empty() {
+        true => "ready".into_response(),
+        false => (StatusCode::SERVICE_UNAVAILABLE, reasons.join("\n")).into_response(),
+    }
+}
+
+/// Prometheus metrics and health checks, served from startup so that the health checks also
+/// answer during the initial sync
+pub struct MetricsServer {
+    listener: TcpListener,
+}
+
+impl MetricsServer {
+    pub async fn bind(config: &MetricsConfig) -> Result<Self> {
+        let listener = TcpListener::bind(config.bind())
+            .await
+            .with_context(|| format!("could not listen on '{}'", config.bind()))?;
+        info!("serving metrics on 'http://{}/metrics'", config.bind());
+        Ok(Self { listener })
+    }
+
+    /// Serves requests until the daemon exits
+    pub async fn serve(self, state: MetricsState) {
+        let app = Router::new()
+            .route("/metrics", get(metrics_handler))
+            .route("/healthz", get(healthz_handler))
+            .route("/readyz", get(readyz_handler))
+            .with_state(state);
+        if let Err(e) = axum::serve(self.listener, app).await {
+            error!("metrics server failed: {:#}", e);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::{sync::Mutex, time::Duration};
+
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::Deserialize;
+use tokio::{sync::broadcast, time::sleep};
+
+use crate::actions::{run_actions, ConfiguredAction};
+use crate::escalation::{EscalationConfig, Escalations};
+use crate::message::{Message, MessageConfig};
+
+#[derive(Deserialize, Debug)]
+pub struct BatchingConfig {
+    window_secs: Option<u64>,
+    max_messages: Option<usize>,
+}
+
+impl BatchingConfig {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_secs.unwrap_or(10))
+    }
+
+    /// Messages arriving within a window are merged if there are more than this
+    pub fn max_messages(&self) -> usize {
+        self.max_messages.unwrap_or(3)
+    }
+}
+
+/// Delivers messages to every action, coalescing bursts of messages if batching is configured
+/// and escalating messages that need an acknowledgement through a chain of actions
+pub struct Notifier<'n, 'a> {
+    actions: &'n [ConfiguredAction],
+    message_config: &'a MessageConfig,
+    batching: Option<&'a BatchingConfig>,
+    escalations: Escalations<'a>,
+    pending: Mutex<Vec<Message>>,
+    events: broadcast::Sender<Message>,
+}
+
+impl<'n, 'a> Notifier<'n, 'a> {
+    pub fn new(
+        actions: &'n [ConfiguredAction],
+        message_config: &'a MessageConfig,
+        batching: Option<&'a BatchingConfig>,
+        escalations: &'a [EscalationConfig],
+    ) -> Self {
+        Self {
+            actions,
+            message_config,
+            batching,
+            escalations: Escalations::load(escalations),
+            pending: Default::default(),
+            events: broadcast::channel(64).0,
+        }
+    }
+
+    fn action(&self, id: &str) -> Option<&'n ConfiguredAction> {
+        let action = self.actions.iter().find(|a| a.id() == id);
+        if action.is_none() {
+            warn!("[{}] escalation step skipped, action is not registered", id);
+        }
+        action
+    }
+
+    pub fn message_config(&self) -> &MessageConfig {
+        self.message_config
+    }
+
+    /// Channel every transaction message is published to as it is generated, before batching
+    #[cfg(feature = "api")]
+    pub fn events(&self) -> broadcast::Sender<Message> {
+        self.events.clone()
+    }
+
+    /// Stops escalating the message with the given ack id, returning whether there was one
+    pub fn acknowledge(&self, ack_id: &str, via: &str) -> bool {
+        self.escalations.acknowledge(ack_id, via)
+    }
+
+    /// Delivers the messages actions held back, if they are due, and advances the escalations
+    /// that were not acknowledged in time
+    pub async fn process_pending(&self) {
+        for action in self.actions {
+            action.process_pending().await;
+        }
+        if self.escalations.is_empty() {
+            return;
+        }
+        for action in self.actions {
+            for ack_id in action.poll_acks().await {
+                self.escalations.acknowledge(&ack_id, action.id());
+            }
+        }
+        for (action_id, message) in self.escalations.take_due(Utc::now()) {
+            if let Some(action) = self.action(action_id) {
+                run_actions(&[action], message).await;
+            }
+        }
+    }
+
+    /// Sends the message right away. Actions in the escalation chain of the message, if any, are
+    /// notified one at a time until it is acknowledged.
+    pub async fn send(&self, message: Message) {
+        let Some(policy) = self.escalations.policy_for(&message, self.message_config) else {
+            let actions = self.actions.iter().collect::<Vec<_>>();
+            return run_actions(&actions, message).await;
+        };
+        let others = self
+            .actions
+            .iter()
+            .filter(|a| !policy.involves(a.id()))
+            .collect::<Vec<_>>();
+        let (action_id, escalated) = self.escalations.start(policy, &message);
+        let first = self.action(action_id).into_iter().collect::<Vec<_>>();
+        tokio::join!(
+            run_actions(&others, message),
+            run_actions(&first, escalated)
+        );
+    }
+
+    /// Sends the message after the batching window, merged with the messages that arrived in the
+    /// meantime if there are too many. The first message of a window waits for and sends the
+    /// whole batch.
+    pub async fn notify(&self, message: Message) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::count_event(message.source());
+        // Only fails when nobody is listening
+        let _ = self.events.send(message.clone());
+        let Some(batching) = self.batching else {
+            return self.send(message).await;
+        };
+        let is_first = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.push(message);
+            pending.len() == 1
+        };
+        if !is_first {
+            return;
+        }
+        sleep(batching.window()).await;
+
+        let messages = std::mem::take(&mut *self.pending.lock().unwrap());
+        if messages.len() <= batching.max_messages() {
+            for message in messages {
+                self.send(message).await;
+            }
+            return;
+        }
+        info!("merging {} messages into a summary", messages.len());
+        match self.message_config.summary_message(&messages) {
+            Ok(summary) => self.send(summary).await,
+            Err(e) => {
+                error!("could not render summary message: {:#}", e);
+                for message in messages {
+                    self.send(message).await;
+                }
+            }
+        }
+    }
+}
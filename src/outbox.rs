@@ -0,0 +1,270 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_reader, to_string};
+
+use crate::message::Message;
+use crate::persist::{get_state_dir, migrate, set_aside, write_atomically};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OutboxEntry {
+    /// Tells entries apart while running, assigned when they are loaded or pushed
+    #[serde(skip)]
+    id: u64,
+    message: Message,
+    due: DateTime<Utc>,
+    #[serde(default)]
+    attempts: u32,
+    /// Being delivered right now. Not persisted, so that deliveries interrupted by a crash or
+    /// restart are attempted again.
+    #[serde(skip)]
+    in_flight: bool,
+}
+
+impl OutboxEntry {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// When the message is to be delivered
+    pub fn due(&self) -> DateTime<Utc> {
+        self.due
+    }
+
+    /// Number of failed delivery attempts so far
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+}
+
+fn get_outbox_filename(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}.json", name)
+}
+
+fn get_outbox_filepath(name: &str) -> PathBuf {
+    get_state_dir()
+        .join("outbox")
+        .join(get_outbox_filename(name))
+}
+
+/// Where older versions kept the outbox, named after the action's position in the config unless
+/// it had an explicit id
+fn get_legacy_outbox_filepath(name: &str) -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or(PathBuf::from("cache"))
+        .join(env!("CARGO_PKG_NAME"))
+        .join("outbox")
+        .join(get_outbox_filename(name))
+}
+
+/// Messages waiting to be delivered later, persisted to disk so they survive restarts. Entries
+/// stay in the outbox while they are being delivered, until they are removed.
+pub struct Outbox {
+    path: PathBuf,
+    entries: Mutex<Vec<OutboxEntry>>,
+    next_id: AtomicU64,
+}
+
+impl Outbox {
+    pub fn open(name: &str, legacy_name: &str) -> Self {
+        let path = get_outbox_filepath(name);
+        migrate(&get_legacy_outbox_filepath(legacy_name), &path);
+        Self::load(path)
+    }
+
+    /// Opens the outbox stored at the given path. An outbox that can't be read is set aside rather
+    /// than overwritten, so that its messages can still be recovered.
+    pub fn load(path: PathBuf) -> Self {
+        let mut entries: Vec<OutboxEntry> = match File::open(&path) {
+            Ok(file) => from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+                warn!("cannot read outbox from '{}': {:#}", path.display(), e);
+                set_aside(&path);
+                Default::default()
+            }),
+            Err(_) => Default::default(),
+        };
+        for (id, entry) in entries.iter_mut().enumerate() {
+            entry.id = id as u64;
+        }
+        Self {
+            path,
+            next_id: AtomicU64::new(entries.len() as u64),
+            entries: Mutex::new(entries),
+        }
+    }
+
+    fn save(&self, entries: &[OutboxEntry]) -> Result<()> {
+        write_atomically(&self.path, &to_string(entries)?).context("could not save the outbox")
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Vec<OutboxEntry>)) {
+        let mut entries = self.entries.lock().unwrap();
+        f(&mut entries);
+        if let Err(e) = self.save(&entries) {
+            warn!("{:#}", e);
+        }
+    }
+
+    fn insert(&self, message: Message, due: DateTime<Utc>, attempts: u32, in_flight: bool) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.update(|entries| {
+            entries.push(OutboxEntry {
+                id,
+                message,
+                due,
+                attempts,
+                in_flight,
+            })
+        });
+        id
+    }
+
+    /// Stores a message to be delivered once it is due
+    pub fn push(&self, message: Message, due: DateTime<Utc>, attempts: u32) -> u64 {
+        self.insert(message, due, attempts, false)
+    }
+
+    /// Stores a message that is about to be delivered, so that it isn't lost if the process
+    /// stops before it is
+    pub fn push_in_flight(&self, message: Message) -> u64 {
+        self.insert(message, Utc::now(), 0, true)
+    }
+
+    pub fn entries(&self) -> Vec<OutboxEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Returns the entries that are due and marks them as being delivered. They have to be
+    /// removed or rescheduled afterwards.
+    pub fn take_due(&self, now: DateTime<Utc>) -> Vec<OutboxEntry> {
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .iter_mut()
+            .filter(|e| !e.in_flight && e.due <= now)
+            .map(|e| {
+                e.in_flight = true;
+                e.clone()
+            })
+            .collect()
+    }
+
+    /// Sets when a message is attempted again
+    pub fn reschedule(&self, id: u64, due: DateTime<Utc>, attempts: u32) {
+        self.update(|entries| {
+            if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+                entry.due = due;
+                entry.attempts = attempts;
+                entry.in_flight = false;
+            }
+        });
+    }
+
+    /// Forgets a message once it was delivered, or given up on
+    pub fn remove(&self, id: u64) {
+        self.update(|entries| entries.retain(|e| e.id != id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str) -> Message {
+        serde_json::from_value(serde_json::json!({
+            "source": "wallet",
+            "id": id,
+            "subject": "subject",
+            "body": "body",
+            "format": "plain",
+            "url": null,
+            "context": null,
+            "attachments": [],
+            "critical": false,
+        }))
+        .unwrap()
+    }
+
+    use std::fs;
+
+    fn outbox_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "{}-test-{}",
+                env!("CARGO_PKG_NAME"),
+                std::process::id()
+            ))
+            .join(format!("{}.json", name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn messages_in_flight_survive_a_restart() {
+        let path = outbox_path("in-flight");
+        let outbox = Outbox::load(path.clone());
+        outbox.push_in_flight(message("a"));
+        assert!(outbox.take_due(Utc::now()).is_empty());
+
+        let outbox = Outbox::load(path);
+        let due = outbox.take_due(Utc::now());
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].message().id(), "a");
+    }
+
+    #[test]
+    fn due_entries_stay_until_removed_or_rescheduled() {
+        let path = outbox_path("due");
+        let outbox = Outbox::load(path.clone());
+        let now = Utc::now();
+        let a = outbox.push(message("a"), now, 1);
+        outbox.push(message("b"), now + chrono::Duration::hours(1), 0);
+
+        let due = outbox.take_due(now);
+        assert_eq!(due.iter().map(OutboxEntry::id).collect::<Vec<_>>(), [a]);
+        assert!(outbox.take_due(now).is_empty());
+        assert_eq!(Outbox::load(path.clone()).entries().len(), 2);
+
+        outbox.reschedule(a, now + chrono::Duration::minutes(1), 2);
+        assert!(outbox.take_due(now).is_empty());
+        let due = outbox.take_due(now + chrono::Duration::minutes(1));
+        assert_eq!(due[0].attempts(), 2);
+
+        outbox.remove(a);
+        let entries = Outbox::load(path).entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].message().id(), "b");
+    }
+
+    #[test]
+    fn unreadable_outboxes_are_set_aside() {
+        let path = outbox_path("corrupt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "[{").unwrap();
+        let outbox = Outbox::load(path.clone());
+        assert!(outbox.entries().is_empty());
+        outbox.push(message("a"), Utc::now(), 0);
+
+        let mut corrupt_path = path.into_os_string();
+        corrupt_path.push(".corrupt");
+        assert_eq!(fs::read_to_string(corrupt_path).unwrap(), "[{");
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+/// Directory for what has to survive restarts, unlike the cache which may be cleared at any time
+pub fn get_state_dir() -> PathBuf {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .unwrap_or(PathBuf::from("state"))
+        .join(env!("CARGO_PKG_NAME"))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// Replaces the file through a temporary one, so that a crash never leaves it half written
+pub fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("could not create directory '{}'", dir.display()))?;
+    }
+    let tmp_path = with_suffix(path, ".tmp");
+    let written = File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp_path, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    written.with_context(|| format!("could not write '{}'", path.display()))
+}
+
+/// Moves a file that can't be read out of the way, so that it isn't overwritten and can be
+/// recovered by hand
+pub fn set_aside(path: &Path) {
+    let corrupt_path = with_suffix(path, ".corrupt");
+    match fs::rename(path, &corrupt_path) {
+        Ok(()) => warn!("moved '{}' to '{}'", path.display(), corrupt_path.display()),
+        Err(e) => warn!("could not move '{}' aside: {}", path.display(), e),
+    }
+}
+
+/// Moves a file kept by an older version to where it is now expected, unless there is one already
+pub fn migrate(old_path: &Path, path: &Path) {
+    if path.exists() || !old_path.exists() {
+        return;
+    }
+    let moved = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::rename(old_path, path));
+    match moved {
+        Ok(()) => info!("moved '{}' to '{}'", old_path.display(), path.display()),
+        Err(e) => warn!(
+            "could not move '{}' to '{}': {}",
+            old_path.display(),
+            path.display(),
+            e
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_replaced_whole() {
+        let dir = std::env::temp_dir().join(format!(
+            "{}-test-{}-persist",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let path = dir.join("file.json");
+        write_atomically(&path, "old").unwrap();
+        write_atomically(&path, "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!with_suffix(&path, ".tmp").exists());
+
+        set_aside(&path);
+        assert!(!path.exists());
+        assert_eq!(
+            fs::read_to_string(with_suffix(&path, ".corrupt")).unwrap(),
+            "new"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use strfmt::strfmt;
+
+use crate::blockchain::ElectrumConfig;
+
+/// How long a price source that failed is left alone, so that messages don't keep waiting on it
+const FAILURE_BACKOFF: Duration = Duration::from_secs(60);
+
+fn backing_off(failed_at: &Mutex<Option<Instant>>) -> bool {
+    failed_at
+        .lock()
+        .unwrap()
+        .is_some_and(|t| t.elapsed() < FAILURE_BACKOFF)
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceSource {
+    Mempool,
+    Kraken,
+    Coinbase,
+    Custom,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PriceConfig {
+    source: Option<PriceSource>,
+    currency: Option<String>,
+    url: Option<String>,
+    json_path: Option<String>,
+    proxy: Option<String>,
+    cache_secs: Option<u64>,
+}
+
+impl PriceConfig {
+    pub fn source(&self) -> PriceSource {
+        self.source.unwrap_or(PriceSource::Mempool)
+    }
+
+    pub fn currency(&self) -> String {
+        self.currency.as_deref().unwrap_or("USD").to_uppercase()
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    pub fn json_path(&self) -> Option<&str> {
+        self.json_path.as_deref()
+    }
+
+    pub fn cache_duration(&self) -> Duration {
+        Duration::from_secs(self.cache_secs.unwrap_or(300))
+    }
+}
+
+/// Fiat prices of a single transaction, to be used as message parameters
+#[derive(Default, Debug, Clone)]
+pub struct FiatPrices {
+    currency: String,
+    current: Option<f64>,
+    at_confirmation: Option<f64>,
+}
+
+impl FiatPrices {
+    /// Made up prices, to try out templates
+    pub fn example(currency: &str) -> Self {
+        Self {
+            currency: currency.to_string(),
+            current: Some(65_432.1),
+            at_confirmation: Some(64_000.0),
+        }
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    pub fn current(&self) -> Option<f64> {
+        self.current
+    }
+
+    pub fn at_confirmation(&self) -> Option<f64> {
+        self.at_confirmation
+    }
+}
+
+/// Converts an amount of sats into fiat, if there is a price
+pub fn sats_to_fiat(sats: i64, price: Option<f64>) -> Option<f64> {
+    price.map(|p| sats as f64 / 100_000_000.0 * p)
+}
+
+fn get_json_path<'a>(value: &'a Value, path: &str) -> Result<&'a Value> {
+    let mut current = value;
+    for key in path.split('.').filter(|k| !k.is_empty()) {
+        current = match current {
+            Value::Array(array) => key.parse::<usize>().ok().and_then(|i| array.get(i)),
+            Value::Object(object) => object.get(key),
+            _ => None,
+        }
+        .ok_or_else(|| anyhow!("json path '{}' not found in price response", path))?;
+    }
+    Ok(current)
+}
+
+fn json_to_price(value: &Value) -> Result<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .filter(|p| *p > 0.0)
+    .ok_or_else(|| anyhow!("invalid price value '{}'", value))
+}
+
+pub struct PriceFeed {
+    source: PriceSource,
+    currency: String,
+    url: Option<String>,
+    json_path: Option<String>,
+    cache_duration: Duration,
+    client: reqwest::Client,
+    /// Held while fetching, so that concurrent lookups wait for the same fetch
+    current: tokio::sync::Mutex<Option<(Instant, f64)>>,
+    current_failed_at: Mutex<Option<Instant>>,
+    historical: Mutex<HashMap<u64, f64>>,
+    historical_failed_at: Mutex<Option<Instant>>,
+}
+
+impl PriceFeed {
+    pub fn new(price_config: &PriceConfig, electrum_config: &ElectrumConfig) -> Result<Self> {
+        let mut client_builder = reqwest::Client::builder().timeout(Duration::from_secs(10));
+        // Default to the same proxy used to reach the electrum server, so tor users stay on tor
+        let proxy = price_config.proxy.clone().or(electrum_config
+            .socks5_url()
+            .map(|p| format!("socks5h://{}", p)));
+        if let Some(proxy) = proxy {
+            client_builder = client_builder.proxy(
+                reqwest::Proxy::all(&proxy)
+                    .with_context(|| format!("invalid price proxy '{}'", proxy))?,
+            );
+        }
+        if price_config.source() == PriceSource::Custom
+            && (price_config.url().is_none() || price_config.json_path().is_none())
+        {
+            bail!("custom price source requires both 'url' and 'json_path'");
+        }
+        Ok(Self {
+            source: price_config.source(),
+            currency: price_config.currency(),
+            url: price_config.url().map(String::from),
+            json_path: price_config.json_path().map(String::from),
+            cache_duration: price_config.cache_duration(),
+            client: client_builder.build()?,
+            current: Default::default(),
+            current_failed_at: Default::default(),
+            historical: Default::default(),
+            historical_failed_at: Default::default(),
+        })
+    }
+
+    fn mempool_url(&self) -> &str {
+        self.url
+            .as_deref()
+            .unwrap_or("https://mempool.space")
+            .trim_end_matches('/')
+    }
+
+    async fn get_json(&self, url: &str) -> Result<Value> {
+        debug!("fetching price from '{}'", url);
+        self.client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("invalid price response from '{}'", url))
+    }
+
+    /// Fetches the current price, bypassing the cache
+    pub async fn fetch_price(&self) -> Result<f64> {
+        let currency = &self.currency;
+        match self.source {
+            PriceSource::Mempool => {
+                let json = self
+                    .get_json(&format!("{}/api/v1/prices", self.mempool_url()))
+                    .await?;
+                json_to_price(get_json_path(&json, currency)?)
+            }
+            PriceSource::Kraken => {
+                let json = self
+                    .get_json(&format!(
+                        "https://api.kraken.com/0/public/Ticker?pair=XBT{}",
+                        currency
+                    ))
+                    .await?;
+                // Kraken names the result after its internal pair name (e.g. "XXBTZUSD")
+                let ticker = get_json_path(&json, "result")?
+                    .as_object()
+                    .and_then(|o| o.values().next())
+                    .ok_or_else(|| anyhow!("no kraken ticker for currency '{}'", currency))?;
+                json_to_price(get_json_path(ticker, "c.0")?)
+            }
+            PriceSource::Coinbase => {
+                let json = self
+                    .get_json(&format!(
+                        "https://api.coinbase.com/v2/prices/BTC-{}/spot",
+                        currency
+                    ))
+                    .await?;
+                json_to_price(get_json_path(&json, "data.amount")?)
+            }
+            PriceSource::Custom => {
+                let template = self.url.as_deref().unwrap_or_default();
+                let url = strfmt!(template, currency => currency.clone())
+                    .with_context(|| format!("bad price URL template '{}'", template))?;
+                let json = self.get_json(&url).await?;
+                json_to_price(get_json_path(
+                    &json,
+                    self.json_path.as_deref().unwrap_or_default(),
+                )?)
+            }
+        }
+    }
+
+    /// Current price, served from cache while fresh. If the feed is unavailable, the last known
+    /// price is used, without trying the feed again for a while.
+    pub async fn price(&self) -> Option<f64> {
+        let mut current = self.current.lock().await;
+        if let Some((fetched_at, price)) = *current {
+            if fetched_at.elapsed() < self.cache_duration || backing_off(&self.current_failed_at) {
+                return Some(price);
+            }
+        } else if backing_off(&self.current_failed_at) {
+            return None;
+        }
+        match self.fetch_price().await {
+            Ok(price) => {
+                *current = Some((Instant::now(), price));
+                Some(price)
+            }
+            Err(e) => {
+                warn!("could not fetch {} price: {:#}", self.currency, e);
+                *self.current_failed_at.lock().unwrap() = Some(Instant::now());
+                current.map(|(_, price)| price)
+            }
+        }
+    }
+
+    async fn fetch_historical_price(&self, timestamp: u64) -> Result<f64> {
+        if self.source != PriceSource::Mempool {
+            bail!("historical prices are only supported by the mempool price source");
+        }
+        let json = self
+            .get_json(&format!(
+                "{}/api/v1/historical-price?currency={}&timestamp={}",
+                self.mempool_url(),
+                self.currency,
+                timestamp
+            ))
+            .await?;
+        json_to_price(get_json_path(
+            &json,
+            &format!("prices.0.{}", self.currency),
+        )?)
+    }
+
+    /// Price at a given unix timestamp
+    pub async fn historical_price(&self, timestamp: u64) -> Option<f64> {
+        if let Some(price) = self.historical.lock().unwrap().get(&timestamp) {
+            return Some(*price);
+        }
+        if backing_off(&self.historical_failed_at) {
+            return None;
+        }
+        match self.fetch_historical_price(timestamp).await {
+            Ok(price) => {
+                self.historical.lock().unwrap().insert(timestamp, price);
+                Some(price)
+            }
+            Err(e) => {
+                debug!(
+                    "could not fetch historical {} price: {:#}",
+                    self.currency, e
+                );
+                *self.historical_failed_at.lock().unwrap() = Some(Instant::now());
+                None
+            }
+        }
+    }
+
+    pub async fn fiat_prices(&self, conf_timestamp: Option<u64>) -> FiatPrices {
+        let (current, at_confirmation) = tokio::join!(self.price(), async {
+            match conf_timestamp {
+                Some(timestamp) => self.historical_price(timestamp).await,
+                None => None,
+            }
+        });
+        FiatPrices {
+            currency: self.currency.clone(),
+            current,
+            at_confirmation,
+        }
+    }
+}
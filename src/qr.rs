@@ -0,0 +1,37 @@
+use anyhow::{Context, Result};
+use qrcode::{types::Color, QrCode};
+
+/// Pixels per QR code module
+const MODULE_SIZE: usize = 8;
+/// Width of the blank border around the QR code, in modules
+const QUIET_ZONE: usize = 4;
+
+/// Renders data into a grayscale PNG QR code
+pub fn qr_code_png(data: &str) -> Result<Vec<u8>> {
+    let code = QrCode::new(data.as_bytes())
+        .with_context(|| format!("cannot encode '{}' as a QR code", data))?;
+    let modules = code.to_colors();
+    let code_width = code.width();
+    let image_width = (code_width + 2 * QUIET_ZONE) * MODULE_SIZE;
+
+    let mut pixels = vec![u8::MAX; image_width * image_width];
+    for (i, color) in modules.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let x0 = (i % code_width + QUIET_ZONE) * MODULE_SIZE;
+        let y0 = (i / code_width + QUIET_ZONE) * MODULE_SIZE;
+        for y in y0..y0 + MODULE_SIZE {
+            pixels[y * image_width + x0..y * image_width + x0 + MODULE_SIZE].fill(0);
+        }
+    }
+
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, image_width as u32, image_width as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+    Ok(png_data)
+}
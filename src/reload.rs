@@ -0,0 +1,135 @@
+use std::{
+    fs,
+    future::Future,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use log::{error, info};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::{select, sync::Notify, time::sleep};
+
+use crate::config::{get_config, get_drop_in_dir, Args, Config};
+
+/// Tells the threads running with a configuration to wind down, so that it can be replaced
+#[derive(Default)]
+pub struct StopSignal {
+    stopped: AtomicBool,
+    notify: Notify,
+}
+
+impl StopSignal {
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    async fn stopped(&self) {
+        loop {
+            let notified = self.notify.notified();
+            if self.is_stopped() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Sleeps for the given duration, returning false if stopped in the meantime
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        select! {
+            _ = sleep(duration) => !self.is_stopped(),
+            _ = self.stopped() => false,
+        }
+    }
+
+    /// Runs the future until it completes or the signal is given, whatever comes first
+    pub async fn until_stopped(&self, future: impl Future<Output = ()>) {
+        select! {
+            _ = future => {},
+            _ = self.stopped() => {},
+        }
+    }
+}
+
+/// Last time the config file or its drop-in files changed
+fn modified(path: &Path) -> Option<SystemTime> {
+    let drop_in_dir = get_drop_in_dir(path);
+    let drop_in_files = fs::read_dir(&drop_in_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()));
+    [path.to_path_buf(), drop_in_dir]
+        .into_iter()
+        .chain(drop_in_files)
+        .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+static HANGUP: Notify = Notify::const_new();
+
+/// Forwards SIGHUP to the reload thread. Signals received while a reload is being applied are
+/// remembered, and handled as soon as it's done.
+pub fn listen_for_hangup() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut stream = signal(SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while stream.recv().await.is_some() {
+                HANGUP.notify_one();
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Waits for a SIGHUP or, if watching, for the config file to change
+async fn reload_requested(path: &Path, watch: bool, last_modified: &mut Option<SystemTime>) {
+    loop {
+        select! {
+            _ = HANGUP.notified() => {
+                info!("received process hangup signal, reloading configuration");
+                return;
+            }
+            _ = sleep(Duration::from_secs(5)), if watch => {
+                let modified = modified(path);
+                if modified != *last_modified {
+                    *last_modified = modified;
+                    info!("configuration file changed, reloading");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Waits until a reload is requested and the configuration is valid, then hands it over and
+/// stops the threads running with the current one. Invalid configurations are ignored.
+pub async fn reload_thread(
+    args: &Args,
+    current: &Config,
+    stop: &StopSignal,
+    reloaded: &Mutex<Option<Config>>,
+) {
+    let mut last_modified = modified(current.path());
+    loop {
+        reload_requested(current.path(), args.watch_config(), &mut last_modified).await;
+        match get_config(&args.config()) {
+            Ok(config) => {
+                *reloaded.lock().unwrap() = Some(config);
+                stop.stop();
+                return;
+            }
+            Err(e) => error!("invalid configuration, keeping the current one: {:#}", e),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+#[cfg(any(
+    feature = "ntfy",
+    feature = "email",
+    feature = "telegram",
+    feature = "api"
+))]
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+#[cfg(any(
+    feature = "ntfy",
+    feature = "email",
+    feature = "telegram",
+    feature = "api"
+))]
+use anyhow::{bail, Context, Result};
+
+/// Settings holding secrets, as named in the configuration file
+const SECRET_KEYS: [&str; 5] = ["secret", "password", "bot_token", "token", "ack_token"];
+
+const ENV_PREFIX: &str = "env:";
+
+/// Relative paths are looked up in the directory of the credentials passed by systemd (see
+/// `LoadCredential=`) first
+#[cfg(any(
+    feature = "ntfy",
+    feature = "email",
+    feature = "telegram",
+    feature = "api"
+))]
+fn get_secret_path(path: &Path) -> PathBuf {
+    if path.is_relative() {
+        if let Some(dir) = env::var_os("CREDENTIALS_DIRECTORY") {
+            let credential_path = Path::new(&dir).join(path);
+            if credential_path.exists() {
+                return credential_path;
+            }
+        }
+    }
+    path.to_path_buf()
+}
+
+/// Resolves a secret setting, written inline as `<name> = "..."`, referencing an environment
+/// variable as `<name> = "env:VAR"`, or read from a file with `<name>_file = "..."`
+#[cfg(any(
+    feature = "ntfy",
+    feature = "email",
+    feature = "telegram",
+    feature = "api"
+))]
+pub fn get_secret(name: &str, value: Option<&str>, file: Option<&Path>) -> Result<String> {
+    match (value, file) {
+        (Some(_), Some(_)) => bail!("only one of '{}' and '{}_file' can be set", name, name),
+        (Some(value), None) => match value.strip_prefix(ENV_PREFIX) {
+            Some(var) => env::var(var).with_context(|| {
+                format!("cannot read '{}' from environment variable '{}'", name, var)
+            }),
+            None => Ok(value.to_string()),
+        },
+        (None, Some(path)) => {
+            let path = get_secret_path(path);
+            let secret = fs::read_to_string(&path)
+                .with_context(|| format!("cannot read '{}' from '{}'", name, path.display()))?;
+            Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+        }
+        (None, None) => bail!("either '{}' or '{}_file' is required", name, name),
+    }
+}
+
+/// Whether the setting holds a secret, which is always a string
+pub fn is_secret_key(key: &str) -> bool {
+    SECRET_KEYS.contains(&key)
+}
+
+/// Whether the configuration has secrets written inline, rather than in files or environment
+/// variables
+pub fn has_inline_secrets(value: &toml::Value) -> bool {
+    match value {
+        toml::Value::Table(table) => table.iter().any(|(key, value)| {
+            let inline =
+                is_secret_key(key) && value.as_str().is_some_and(|s| !s.starts_with(ENV_PREFIX));
+            inline || has_inline_secrets(value)
+        }),
+        toml::Value::Array(array) => array.iter().any(has_inline_secrets),
+        _ => false,
+    }
+}
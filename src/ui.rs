@@ -0,0 +1,278 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use bdk::bitcoin::Network;
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+
+use crate::blockchain::ElectrumConfig;
+use crate::config::{
+    apply_layers, get_config_path, get_config_search_paths, parse_config, read_config_file,
+    write_config_file, Args,
+};
+use crate::example::ExampleWallet;
+use crate::message::{MessageConfig, MessageParams, TxKind};
+use crate::price::{FiatPrices, PriceConfig};
+use crate::wallets::{check_wallet, WalletConfig};
+
+const PAGE: &str = include_str!("ui.html");
+
+struct UiState {
+    /// Config file being edited
+    path: PathBuf,
+}
+
+type SharedState = Arc<UiState>;
+
+#[derive(Serialize)]
+struct ConfigResponse {
+    path: String,
+    exists: bool,
+    config: toml::Table,
+}
+
+#[derive(Serialize, Default)]
+struct WalletCheck {
+    address: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+struct Validation {
+    errors: Vec<String>,
+    /// First address of each wallet, in the order they are configured
+    wallets: Vec<WalletCheck>,
+}
+
+#[derive(Deserialize)]
+struct PreviewRequest {
+    config: toml::Table,
+    kind: TxKind,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum PreviewResponse {
+    Message { subject: String, body: String },
+    Error { error: String },
+}
+
+/// Deserializes a section on its own, so that errors elsewhere don't prevent previews
+fn get_section<T: Default + for<'de> Deserialize<'de>>(source: &toml::Table, name: &str) -> T {
+    source
+        .get(name)
+        .and_then(|value| T::deserialize(value.clone()).ok())
+        .unwrap_or_default()
+}
+
+fn get_network(source: &toml::Table) -> Network {
+    get_section::<ElectrumConfig>(source, "electrum").network()
+}
+
+fn check_wallets(source: &toml::Table) -> Vec<WalletCheck> {
+    let network = get_network(source);
+    let Some(wallets) = source.get("wallets").and_then(|w| w.as_array()) else {
+        return Vec::new();
+    };
+    wallets
+        .iter()
+        .map(|value| {
+            match WalletConfig::deserialize(value.clone())
+                .map_err(anyhow::Error::from)
+                .and_then(|wallet| check_wallet(&wallet, network))
+            {
+                Ok(address) => WalletCheck {
+                    address: Some(address),
+                    error: None,
+                },
+                Err(e) => WalletCheck {
+                    address: None,
+                    error: Some(format!("{:#}", e)),
+                },
+            }
+        })
+        .collect()
+}
+
+/// Validates the configuration the same way it's read when starting, and renders every
+/// transaction template
+fn validate(state: &UiState, source: &toml::Table) -> Validation {
+    let mut validation = Validation {
+        wallets: check_wallets(source),
+        ..Default::default()
+    };
+    let config = match apply_layers(&state.path, source.clone())
+        .and_then(|layered| parse_config(layered, state.path.clone()))
+    {
+        Ok(config) => config,
+        Err(e) => {
+            validation.errors.push(format!("{:#}", e));
+            return validation;
+        }
+    };
+    for (index, wallet) in validation.wallets.iter().enumerate() {
+        if let Some(error) = &wallet.error {
+            validation.errors.push(format!(
+                "wallet '{}': {}",
+                config.wallets()[index].name(),
+                error
+            ));
+        }
+    }
+    if config.actions().is_empty() {
+        validation.errors.push("no actions configured".to_string());
+    }
+    let network = config.electrum().network();
+    for kind in [
+        TxKind::Incoming,
+        TxKind::Outgoing,
+        TxKind::SelfTransfer,
+        TxKind::Consolidation,
+        TxKind::Coinjoin,
+        TxKind::Coinbase,
+    ] {
+        let wallet = ExampleWallet::new("example", network, kind, true);
+        let params = MessageParams::new(wallet.tx(), &wallet, FiatPrices::default());
+        if let Err(e) = config.message().message(&params) {
+            validation
+                .errors
+                .push(format!("{} transaction message: {:#}", kind, e));
+        }
+    }
+    validation
+}
+
+fn preview(request: &PreviewRequest) -> Result<(String, String)> {
+    let mut message_config: MessageConfig = match request.config.get("message") {
+        Some(message) => MessageConfig::deserialize(message.clone())?,
+        None => Default::default(),
+    };
+    message_config.load()?;
+    let currency = request
+        .config
+        .get("price")
+        .and_then(|price| PriceConfig::deserialize(price.clone()).ok())
+        .map(|price| price.currency())
+        .unwrap_or("USD".to_string());
+    let wallet = ExampleWallet::new("example", get_network(&request.config), request.kind, true);
+    let message = message_config.message(&MessageParams::new(
+        wallet.tx(),
+        &wallet,
+        FiatPrices::example(&currency),
+    ))?;
+    Ok((message.subject().to_string(), message.body().to_string()))
+}
+
+async fn page_handler() -> Html<&'static str> {
+    Html(PAGE)
+}
+
+async fn config_handler(State(state): State<SharedState>) -> Response {
+    let exists = state.path.exists();
+    let config = match exists {
+        true => match read_config_file(&state.path) {
+            Ok(config) => config,
+            Err(e) => {
+                return (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)).into_response()
+            }
+        },
+        false => toml::Table::new(),
+    };
+    Json(ConfigResponse {
+        path: state.path.display().to_string(),
+        exists,
+        config,
+    })
+    .into_response()
+}
+
+async fn validate_handler(
+    State(state): State<SharedState>,
+    Json(source): Json<toml::Table>,
+) -> Json<Validation> {
+    Json(validate(&state, &source))
+}
+
+async fn preview_handler(Json(request): Json<PreviewRequest>) -> Json<PreviewResponse> {
+    Json(match preview(&request) {
+        Ok((subject, body)) => PreviewResponse::Message { subject, body },
+        Err(e) => PreviewResponse::Error {
+            error: format!("{:#}", e),
+        },
+    })
+}
+
+/// Only writes configurations that pass validation
+async fn save_handler(
+    State(state): State<SharedState>,
+    Json(source): Json<toml::Table>,
+) -> Json<Validation> {
+    let mut validation = validate(&state, &source);
+    if validation.errors.is_empty() {
+        let result = toml::to_string(&source)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| write_config_file(&state.path, &content));
+        match result {
+            Ok(()) => info!("configuration written to '{}'", state.path.display()),
+            Err(e) => validation.errors.push(format!("{:#}", e)),
+        }
+    }
+    Json(validation)
+}
+
+/// Rejects requests for other host names, so that other websites can't reach the UI through DNS
+/// rebinding
+async fn check_host(request: Request, next: Next) -> Response {
+    let local = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(|host| match host.rsplit_once(':') {
+            Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+            _ => host,
+        })
+        .is_some_and(|name| ["localhost", "127.0.0.1"].contains(&name));
+    match local {
+        true => next.run(request).await,
+        false => StatusCode::FORBIDDEN.into_response(),
+    }
+}
+
+/// Serves the configuration editor on localhost, until interrupted
+pub async fn ui(args: &Args, port: u16) -> Result<()> {
+    let path = get_config_path(&args.config())
+        .unwrap_or_else(|_| get_config_search_paths().swap_remove(0));
+    let app = Router::new()
+        .route("/", get(page_handler))
+        .route("/api/config", get(config_handler))
+        .route("/api/validate", post(validate_handler))
+        .route("/api/preview", post(preview_handler))
+        .route("/api/save", post(save_handler))
+        .layer(middleware::from_fn(check_host))
+        .with_state(Arc::new(UiState { path: path.clone() }));
+
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("could not listen on '{}'", address))?;
+    info!(
+        "editing '{}', open http://localhost:{} in your browser",
+        path.display(),
+        port
+    );
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -1,23 +1,31 @@
 use std::{
-    collections::{hash_map::DefaultHasher, HashSet},
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
     hash::{Hash, Hasher},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    thread,
+    time::Duration,
 };
 
 use anyhow::{Context, Result};
 use bdk::{
-    bitcoin::{bip32::ExtendedPubKey, Network, Txid},
+    bitcoin::{bip32::ExtendedPubKey, Network, OutPoint, Script, Transaction, TxOut, Txid},
     blockchain::{ElectrumBlockchain, GetHeight},
+    database::{BatchDatabase, MemoryDatabase},
+    electrum_client::ElectrumApi,
     sled,
     template::{Bip44Public, Bip49Public, Bip84Public, Bip86Public},
-    KeychainKind, SyncOptions, TransactionDetails, Wallet,
+    wallet::AddressIndex,
+    Balance, KeychainKind, SyncOptions, TransactionDetails, Wallet,
 };
-use log::{debug, error, warn};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
 use regex::Regex;
 use serde::Deserialize;
 
 use crate::blockchain::{get_blockchain, ElectrumConfig};
+use crate::control::Mute;
+use crate::message::WalletView;
 
 #[derive(Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
@@ -33,6 +41,7 @@ pub struct XpubSpec {
     name: String,
     xpub: String,
     kind: Option<AddressKind>,
+    birthday: Option<u32>,
 }
 
 impl XpubSpec {
@@ -47,19 +56,26 @@ impl XpubSpec {
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    pub fn birthday(&self) -> Option<u32> {
+        self.birthday
+    }
 }
 
-#[derive(Deserialize, Debug, Hash, Clone)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct DescriptorsSpec {
     name: String,
     descriptor: String,
     change_descriptor: Option<String>,
+    birthday: Option<u32>,
 }
 
 impl DescriptorsSpec {
+    /// Identifies the wallet cache. Only covers the fields that existed when it was introduced, so
+    /// that existing caches keep being used.
     pub fn get_hash(&self) -> String {
         let mut s = DefaultHasher::new();
-        self.hash(&mut s);
+        (&self.name, &self.descriptor, &self.change_descriptor).hash(&mut s);
         s.finish().to_string()
     }
 
@@ -74,6 +90,10 @@ impl DescriptorsSpec {
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    pub fn birthday(&self) -> Option<u32> {
+        self.birthday
+    }
 }
 
 fn handle_multipart_descritor(desc_spec: &DescriptorsSpec) -> Result<DescriptorsSpec> {
@@ -90,6 +110,7 @@ fn handle_multipart_descritor(desc_spec: &DescriptorsSpec) -> Result<Descriptors
         name: desc_spec.name().to_string(),
         descriptor: desc,
         change_descriptor: Some(change_desc),
+        birthday: desc_spec.birthday(),
     })
 }
 
@@ -107,81 +128,181 @@ impl WalletConfig {
             WalletConfig::Descriptors(descriptors_spec) => descriptors_spec.name(),
         }
     }
+
+    /// Height before which transactions are never notified
+    pub fn birthday(&self) -> Option<u32> {
+        match self {
+            WalletConfig::Xpub(xpub_spec) => xpub_spec.birthday(),
+            WalletConfig::Descriptors(descriptors_spec) => descriptors_spec.birthday(),
+        }
+    }
 }
 
-fn get_cache_dir(db_name: &str) -> PathBuf {
+fn get_cache_dir() -> PathBuf {
     dirs::cache_dir()
         .unwrap_or(PathBuf::from("cache"))
         .join(env!("CARGO_PKG_NAME"))
-        .join(db_name)
 }
 
-fn get_xpub_wallet(xpub_spec: &XpubSpec, network: Network) -> Result<Wallet<sled::Tree>> {
-    let xpub: ExtendedPubKey = xpub_spec.xpub().parse().unwrap();
+fn parse_xpub(xpub_spec: &XpubSpec) -> Result<ExtendedPubKey> {
+    xpub_spec
+        .xpub()
+        .parse()
+        .with_context(|| format!("invalid xpub '{}'", xpub_spec.xpub()))
+}
+
+fn get_xpub_wallet<D: BatchDatabase>(
+    xpub_spec: &XpubSpec,
+    network: Network,
+    database: D,
+) -> Result<Wallet<D>> {
+    let xpub = parse_xpub(xpub_spec)?;
     let fingerprint = xpub.fingerprint();
-    let sled = sled::open(get_cache_dir(&fingerprint.to_string()))?.open_tree("wallet")?;
     match xpub_spec.kind() {
         AddressKind::Legacy => Wallet::new(
             Bip44Public(xpub, fingerprint, KeychainKind::External),
             Some(Bip44Public(xpub, fingerprint, KeychainKind::Internal)),
             network,
-            sled,
+            database,
         ),
         AddressKind::NestedSegwit => Wallet::new(
             Bip49Public(xpub, fingerprint, KeychainKind::External),
             Some(Bip49Public(xpub, fingerprint, KeychainKind::Internal)),
             network,
-            sled,
+            database,
         ),
         AddressKind::Segwit => Wallet::new(
             Bip84Public(xpub, fingerprint, KeychainKind::External),
             Some(Bip84Public(xpub, fingerprint, KeychainKind::Internal)),
             network,
-            sled,
+            database,
         ),
         AddressKind::Taproot => Wallet::new(
             Bip86Public(xpub, fingerprint, KeychainKind::External),
             Some(Bip86Public(xpub, fingerprint, KeychainKind::Internal)),
             network,
-            sled,
+            database,
         ),
     }
     .with_context(|| format!("invalid xpub wallet '{}'", xpub))
 }
 
-fn get_descriptors_wallet(
+fn get_descriptors_wallet<D: BatchDatabase>(
     descriptors_spec: &DescriptorsSpec,
     network: Network,
-) -> Result<Wallet<sled::Tree>> {
-    let sled = sled::open(get_cache_dir(&descriptors_spec.get_hash()))?.open_tree("wallet")?;
+    database: D,
+) -> Result<Wallet<D>> {
     let desc_spec_no_multi = handle_multipart_descritor(descriptors_spec)?;
     Wallet::new(
         desc_spec_no_multi.descriptor(),
         desc_spec_no_multi.change_descriptor(),
         network,
-        sled,
+        database,
     )
     .with_context(|| format!("invalid descriptor wallet '{:?}'", descriptors_spec))
 }
 
-fn get_wallet(wallet_config: &WalletConfig, network: Network) -> Result<Wallet<sled::Tree>> {
+fn get_wallet<D: BatchDatabase>(
+    wallet_config: &WalletConfig,
+    network: Network,
+    database: D,
+) -> Result<Wallet<D>> {
     match &wallet_config {
-        WalletConfig::Xpub(xpub_spec) => get_xpub_wallet(xpub_spec, network),
+        WalletConfig::Xpub(xpub_spec) => get_xpub_wallet(xpub_spec, network, database),
         WalletConfig::Descriptors(descriptors_spec) => {
-            get_descriptors_wallet(descriptors_spec, network)
+            get_descriptors_wallet(descriptors_spec, network, database)
+        }
+    }
+}
+
+/// Opens a wallet cache. The one it replaces on reloads only releases its lock once sled's
+/// background threads have noticed it was dropped, so failures are retried for a little while.
+fn open_database(path: &Path) -> Result<sled::Db> {
+    let mut attempts = 0;
+    loop {
+        match sled::open(path) {
+            Err(sled::Error::Io(_)) if attempts < 20 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(50));
+            }
+            result => return Ok(result?),
         }
     }
 }
 
+/// Opens the wallet along with its cache in the given directory, named after the xpub fingerprint
+/// or the descriptors
+fn open_wallet(
+    wallet_config: &WalletConfig,
+    network: Network,
+    cache_dir: &Path,
+) -> Result<Wallet<sled::Tree>> {
+    let db_name = match &wallet_config {
+        WalletConfig::Xpub(xpub_spec) => parse_xpub(xpub_spec)?.fingerprint().to_string(),
+        WalletConfig::Descriptors(descriptors_spec) => descriptors_spec.get_hash(),
+    };
+    let sled = open_database(&cache_dir.join(db_name))?.open_tree("wallet")?;
+    get_wallet(wallet_config, network, sled)
+}
+
+/// Makes sure the wallet can be set up, without touching its cache. Returns its first receive
+/// address, so it can be compared with the one shown by the wallet software.
+pub fn check_wallet(wallet_config: &WalletConfig, network: Network) -> Result<String> {
+    let wallet = get_wallet(wallet_config, network, MemoryDatabase::new())?;
+    Ok(wallet
+        .get_address(AddressIndex::Peek(0))?
+        .address
+        .to_string())
+}
+
 pub struct WalletInfo {
     name: String,
     wallet: Wallet<sled::Tree>,
     old_txs: HashSet<Txid>,
-    blockchain: ElectrumBlockchain,
+    birthday: Option<u32>,
+    blockchain: Arc<ElectrumBlockchain>,
+    /// Transactions spent by the wallet's transactions that aren't its own, see `fetch_prev_txs`
+    prev_txs: HashMap<Txid, Transaction>,
+    state: SafeWalletState,
 }
 
 pub type SafeWalletInfo = Arc<Mutex<WalletInfo>>;
 
+/// What is known about a wallet from its syncs, published as they happen so that it can be read
+/// while the wallet is locked syncing
+#[derive(Default, Debug, Clone)]
+pub struct WalletState {
+    pub name: String,
+    pub balance: Balance,
+    /// Next unused receiving address
+    pub receive_address: Option<String>,
+    /// Transactions known to the wallet, without their raw transaction
+    pub transactions: Vec<TransactionDetails>,
+    /// Last time the wallet synced successfully
+    pub last_sync: Option<DateTime<Utc>>,
+    /// Why the last sync failed, if it did
+    pub sync_error: Option<String>,
+    /// When the sync in progress started
+    pub syncing_since: Option<DateTime<Utc>>,
+    /// Changed through the control socket
+    pub mute: Arc<Mute>,
+}
+
+pub type SafeWalletState = Arc<Mutex<WalletState>>;
+
+/// Transactions confirmed before the birthday are never reported. The birthday is only a
+/// notification filter: the wallet is still synced from the start, since electrum servers always
+/// return the whole history of a script and can't be asked for the part after some height.
+fn after_birthday(tx: &TransactionDetails, birthday: Option<u32>) -> bool {
+    match (&tx.confirmation_time, birthday) {
+        (Some(time), Some(birthday)) => time.height >= birthday,
+        _ => true,
+    }
+}
+
+/// Foreign previous transactions kept around, so they aren't fetched again for every message
+const MAX_PREV_TXS: usize = 1000;
+
 impl WalletInfo {
     pub fn name(&self) -> &str {
         &self.name
@@ -195,17 +316,88 @@ impl WalletInfo {
         self.wallet.network()
     }
 
-    pub fn total_balance(&self) -> Result<u64, bdk::Error> {
-        self.wallet.get_balance().map(|b| b.get_total())
+    pub fn balance(&self) -> Result<Balance, bdk::Error> {
+        self.wallet.get_balance()
     }
 
-    pub fn get_new_txs(&mut self) -> Vec<TransactionDetails> {
+    /// State kept up to date by every sync, shareable with other threads
+    pub fn state(&self) -> SafeWalletState {
+        self.state.clone()
+    }
+
+    pub fn mute(&self) -> Arc<Mute> {
+        self.state.lock().unwrap().mute.clone()
+    }
+
+    /// Transactions already known to the wallet, without syncing
+    pub fn transactions(&self) -> Vec<TransactionDetails> {
+        self.wallet.list_transactions(false).unwrap_or_else(|e| {
+            warn!("[{}] cannot retrieve transactions: {:#}", self.name, e);
+            Default::default()
+        })
+    }
+
+    fn sync(&mut self) -> bool {
         debug!("[{}] syncing wallet", self.name);
-        if let Err(e) = self.wallet.sync(&self.blockchain, SyncOptions::default()) {
+        self.state.lock().unwrap().syncing_since = Some(Utc::now());
+        let result = self.wallet.sync(&self.blockchain, SyncOptions::default());
+        let mut state = self.state.lock().unwrap();
+        state.syncing_since = None;
+        if let Err(e) = result {
             warn!("[{}] cannot sync wallet: {:#}", self.name, e);
+            state.sync_error = Some(format!("{:#}", e));
+            return false;
+        }
+        self.publish(&mut state);
+        state.last_sync = Some(Utc::now());
+        state.sync_error = None;
+        true
+    }
+
+    /// Updates the state with what the wallet database holds
+    fn publish(&self, state: &mut WalletState) {
+        state.balance = self.balance().unwrap_or_default();
+        state.receive_address = self.receive_address();
+        state.transactions = self.transactions();
+    }
+
+    /// Syncs the wallet and looks up one of its transactions
+    pub fn find_tx(&mut self, txid: &Txid) -> Option<TransactionDetails> {
+        if !self.sync() {
+            return None;
+        }
+        self.wallet.get_tx(txid, true).ok().flatten()
+    }
+
+    /// Previous transactions of the inputs of `txs` that are neither in the wallet nor fetched yet
+    fn missing_prev_txids(&self, txs: &[TransactionDetails]) -> Vec<Txid> {
+        let mut txids = txs
+            .iter()
+            .filter_map(|tx| tx.transaction.as_ref())
+            .flat_map(|tx| &tx.input)
+            .filter(|txin| !txin.previous_output.is_null())
+            .map(|txin| txin.previous_output.txid)
+            .filter(|txid| {
+                !self.prev_txs.contains_key(txid)
+                    && !matches!(
+                        self.wallet.get_tx(txid, true),
+                        Ok(Some(TransactionDetails {
+                            transaction: Some(_),
+                            ..
+                        }))
+                    )
+            })
+            .collect::<Vec<_>>();
+        txids.sort();
+        txids.dedup();
+        txids
+    }
+
+    pub fn get_new_txs(&mut self) -> Vec<TransactionDetails> {
+        if !self.sync() {
             return Default::default();
         }
-        let tx_list = match self.wallet.list_transactions(false) {
+        let tx_list = match self.wallet.list_transactions(true) {
             Ok(txs) => txs,
             Err(e) => {
                 warn!("[{}] cannot retrieve transactions: {:#}", self.name, e);
@@ -222,29 +414,275 @@ impl WalletInfo {
             self.old_txs.insert(tx.txid);
         });
         new_txs
+            .into_iter()
+            .filter(|tx| after_birthday(tx, self.birthday))
+            .collect()
     }
 }
 
-pub fn get_wallets(
+impl WalletView for WalletInfo {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn network(&self) -> Network {
+        self.get_network()
+    }
+
+    fn current_balance(&self) -> Balance {
+        self.balance().unwrap_or_default()
+    }
+
+    fn current_height(&self) -> u32 {
+        self.get_height().unwrap_or_default()
+    }
+
+    /// First receive address that hasn't been used yet
+    fn receive_address(&self) -> Option<String> {
+        self.wallet
+            .get_address(AddressIndex::LastUnused)
+            .ok()
+            .map(|a| a.address.to_string())
+    }
+
+    fn is_mine(&self, script: &Script) -> bool {
+        self.wallet.is_mine(script).unwrap_or_default()
+    }
+
+    /// Looks up a previous output in the wallet or in the transactions fetched by
+    /// `fetch_prev_txs`, without going to the blockchain backend
+    fn get_output(&self, outpoint: &OutPoint) -> Option<TxOut> {
+        let output = |tx: &Transaction| tx.output.get(outpoint.vout as usize).cloned();
+        match self.wallet.get_tx(&outpoint.txid, true) {
+            Ok(Some(TransactionDetails {
+                transaction: Some(tx),
+                ..
+            })) => output(&tx),
+            _ => self.prev_txs.get(&outpoint.txid).and_then(output),
+        }
+    }
+}
+
+/// Fetches the previous transactions of the inputs of `txs` that aren't the wallet's own, so that
+/// their outputs can be described. They are fetched in one batch, without holding the wallet lock.
+pub async fn fetch_prev_txs(wallet_info: &SafeWalletInfo, txs: &[TransactionDetails]) {
+    let (name, blockchain, txids) = {
+        let wallet_info = wallet_info.lock().unwrap();
+        (
+            wallet_info.name.clone(),
+            wallet_info.blockchain.clone(),
+            wallet_info.missing_prev_txids(txs),
+        )
+    };
+    if txids.is_empty() {
+        return;
+    }
+    debug!("[{}] fetching {} previous transactions", name, txids.len());
+    let fetched =
+        tokio::task::spawn_blocking(move || blockchain.batch_transaction_get(&txids)).await;
+    let prev_txs = match fetched {
+        Ok(Ok(prev_txs)) => prev_txs,
+        Ok(Err(e)) => return warn!("[{}] cannot fetch previous transactions: {:#}", name, e),
+        Err(e) => return warn!("[{}] cannot fetch previous transactions: {:#}", name, e),
+    };
+    let mut wallet_info = wallet_info.lock().unwrap();
+    if wallet_info.prev_txs.len() + prev_txs.len() > MAX_PREV_TXS {
+        wallet_info.prev_txs.clear();
+    }
+    wallet_info
+        .prev_txs
+        .extend(prev_txs.into_iter().map(|tx| (tx.txid(), tx)));
+}
+
+fn setup_wallet(
+    wallet_config: &WalletConfig,
+    electrum_cfg: &ElectrumConfig,
+    cache_dir: &Path,
+) -> Result<SafeWalletInfo> {
+    let wallet_info = WalletInfo {
+        name: wallet_config.name().to_string(),
+        wallet: open_wallet(wallet_config, electrum_cfg.network(), cache_dir)?,
+        old_txs: Default::default(),
+        birthday: wallet_config.birthday(),
+        blockchain: Arc::new(get_blockchain(electrum_cfg)?),
+        prev_txs: Default::default(),
+        state: Arc::new(Mutex::new(WalletState {
+            name: wallet_config.name().to_string(),
+            ..Default::default()
+        })),
+    };
+    wallet_info.publish(&mut wallet_info.state.lock().unwrap());
+    Ok(Arc::new(Mutex::new(wallet_info)))
+}
+
+/// Keeps the wallets whose configuration didn't change, along with the transactions they already
+/// saw, and sets up the rest. Returns all the wallets and the newly set up ones. Changed wallets
+/// are reopened, so the caller must not hold any other reference to them.
+pub fn update_wallets(
+    old_wallets: Vec<SafeWalletInfo>,
     wallet_configs: &[WalletConfig],
     electrum_cfg: &ElectrumConfig,
-) -> Vec<SafeWalletInfo> {
-    let mut result: Vec<SafeWalletInfo> = vec![];
-    for wallet_config in wallet_configs.iter() {
+    unchanged: impl Fn(&str) -> bool,
+) -> (Vec<SafeWalletInfo>, Vec<SafeWalletInfo>) {
+    update_wallets_in(
+        &get_cache_dir(),
+        old_wallets,
+        wallet_configs,
+        electrum_cfg,
+        unchanged,
+    )
+}
+
+/// Same as `update_wallets`, with the wallet caches in the given directory
+fn update_wallets_in(
+    cache_dir: &Path,
+    old_wallets: Vec<SafeWalletInfo>,
+    wallet_configs: &[WalletConfig],
+    electrum_cfg: &ElectrumConfig,
+    unchanged: impl Fn(&str) -> bool,
+) -> (Vec<SafeWalletInfo>, Vec<SafeWalletInfo>) {
+    let (mut kept, removed): (Vec<_>, Vec<_>) = old_wallets.into_iter().partition(|w| {
+        let name = w.lock().unwrap().name().to_string();
+        wallet_configs.iter().any(|c| c.name() == name) && unchanged(&name)
+    });
+    // Dropped before setting up their replacements, which reuse the same database. That only
+    // unlocks it if nothing else holds on to them.
+    for wallet_info in removed {
+        info!("[{}] removing wallet", wallet_info.lock().unwrap().name());
+    }
+
+    let mut result = Vec::new();
+    let mut added = Vec::new();
+    for wallet_config in wallet_configs {
         let name = wallet_config.name();
-        match get_wallet(wallet_config, electrum_cfg.network()) {
-            Ok(w) => {
-                result.push(Arc::new(Mutex::new(WalletInfo {
-                    name: name.to_string(),
-                    wallet: w,
-                    old_txs: Default::default(),
-                    blockchain: get_blockchain(electrum_cfg).unwrap(),
-                })));
+        if let Some(index) = kept.iter().position(|w| w.lock().unwrap().name() == name) {
+            result.push(kept.swap_remove(index));
+            continue;
+        }
+        match setup_wallet(wallet_config, electrum_cfg, cache_dir) {
+            Ok(wallet_info) => {
+                result.push(wallet_info.clone());
+                added.push(wallet_info);
             }
             Err(e) => {
                 error!("[{}] cannot setup wallet: {:#}", name, e);
             }
         }
     }
-    result
+    (result, added)
+}
+
+/// Shared states of the wallets, which can be read without waiting for their syncs
+pub fn get_states(wallets: &[SafeWalletInfo]) -> Vec<SafeWalletState> {
+    wallets.iter().map(|w| w.lock().unwrap().state()).collect()
+}
+
+pub fn get_wallets(
+    wallet_configs: &[WalletConfig],
+    electrum_cfg: &ElectrumConfig,
+) -> Vec<SafeWalletInfo> {
+    update_wallets(Vec::new(), wallet_configs, electrum_cfg, |_| false).0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, net::TcpListener, thread};
+
+    use bdk::BlockTime;
+
+    use super::*;
+
+    /// Electrum server that accepts connections and never answers, enough to set up wallets
+    fn silent_electrum() -> ElectrumConfig {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("tcp://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let connections: Vec<_> = listener.incoming().collect();
+            drop(connections);
+        });
+        toml::from_str(&format!("url = '{}'\nnetwork = 'bitcoin'", url)).unwrap()
+    }
+
+    fn wallet_configs(birthday: u32) -> Vec<WalletConfig> {
+        let configs = format!(
+            "[[wallets]]\nname = 'a'\nbirthday = {}\n\
+             xpub = 'xpub6CUGRUonZSQ4TWtTMmzXdrXDtypWKiKrhko4egpiMZbpiaQL2jkwSB1icqYh2cfDfVxdx4df189oLKnC5fSwqPfgyP3hooxujYzAu3fDVmz'\n\
+             [[wallets]]\nname = 'b'\n\
+             xpub = 'xpub6CkXHzuU1NyHUFNiQZLq2bgt6QPqjZbwpJ1MDgDeo4bWZ8ZP7HZr7v9WTLCQFhxVhqiJNcw5wSKE77rkAK1SzcuHjt36ZUibBHezGzGL9h9'",
+            birthday
+        );
+        #[derive(Deserialize)]
+        struct Wallets {
+            wallets: Vec<WalletConfig>,
+        }
+        toml::from_str::<Wallets>(&configs).unwrap().wallets
+    }
+
+    fn names(wallets: &[SafeWalletInfo]) -> Vec<String> {
+        wallets
+            .iter()
+            .map(|w| w.lock().unwrap().name().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn changed_wallets_are_reopened_on_reload() {
+        let cache_dir = env::temp_dir().join(format!(
+            "{}-test-{}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let electrum = silent_electrum();
+        let (wallets, _) = update_wallets_in(
+            &cache_dir,
+            Vec::new(),
+            &wallet_configs(800_000),
+            &electrum,
+            |_| false,
+        );
+        assert_eq!(names(&wallets), ["a", "b"]);
+        let unchanged = wallets[1].clone();
+
+        let (wallets, added) = update_wallets_in(
+            &cache_dir,
+            wallets,
+            &wallet_configs(840_000),
+            &electrum,
+            |name| name != "a",
+        );
+        assert_eq!(names(&wallets), ["a", "b"]);
+        assert_eq!(names(&added), ["a"]);
+        assert!(Arc::ptr_eq(&wallets[1], &unchanged));
+    }
+
+    fn tx(height: Option<u32>) -> TransactionDetails {
+        TransactionDetails {
+            transaction: None,
+            txid: "ab".repeat(32).parse().unwrap(),
+            received: 1000,
+            sent: 0,
+            fee: None,
+            confirmation_time: height.map(|height| BlockTime {
+                height,
+                timestamp: 1_700_000_000,
+            }),
+        }
+    }
+
+    #[test]
+    fn birthday_filters_older_transactions() {
+        assert!(!after_birthday(&tx(Some(839_999)), Some(840_000)));
+        assert!(after_birthday(&tx(Some(840_000)), Some(840_000)));
+        assert!(after_birthday(&tx(Some(840_001)), Some(840_000)));
+    }
+
+    #[test]
+    fn birthday_keeps_unconfirmed_transactions() {
+        assert!(after_birthday(&tx(None), Some(840_000)));
+    }
+
+    #[test]
+    fn no_birthday_keeps_everything() {
+        assert!(after_birthday(&tx(Some(1)), None));
+    }
 }